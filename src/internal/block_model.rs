@@ -0,0 +1,638 @@
+use nalgebra_glm::{self as glm, Mat3, Vec3};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::entitiy::color::Color;
+use super::entitiy::material::{Diffuse, Material};
+use super::entitiy::model::{face_index, BlockShape, ElementFace, ModelElement, MODEL_UNITS};
+use super::entitiy::texture::{FaceTexture, FaceTextures, Texture, MISSING};
+use super::resource_pack::ResourcePack;
+
+// Minecraft's block model and blockstate JSON. A blockstate picks models for a state like
+// `facing=east,half=bottom`, a model lists boxes (elements) with a texture on each face and may
+// take its elements and textures from a parent, with `#name` texture variables filled in by the
+// children. Faces with a `tintindex` are colored like the game colors grass and leaves, and
+// `uvlock` keeps the textures still while the blockstate turns the model.
+
+const MAX_NESTING: usize = 32; // parents or texture variables followed before giving up on a cycle
+const FACE_KEYS: [&str; 6] = ["up", "down", "north", "south", "east", "west"];
+
+// A model placed by a blockstate, turned in steps of 90 degrees around the block's center
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub model: String,
+    pub x: i32,
+    pub y: i32,
+    pub weight: u32, // odds against the other models listed with it, the game picks one per block
+    pub uvlock: bool, // the textures don't turn with the model
+}
+
+// A model with everything inherited from its parents merged in
+#[derive(Default)]
+struct BlockModel {
+    textures: HashMap<String, String>,
+    elements: Vec<ElementDef>,
+}
+
+struct ElementDef {
+    from: Vec3,
+    to: Vec3,
+    rotation: Option<ElementRotation>,
+    faces: [Option<FaceDef>; 6],
+}
+
+struct ElementRotation {
+    origin: Vec3,
+    axis: usize,
+    angle: f32,    // degrees
+    rescale: bool, // stretch the element so it still spans the block after turning
+}
+
+struct FaceDef {
+    uv: Option<[f32; 4]>,
+    texture: String,
+    rotation: u32,
+    tinted: bool, // has a `tintindex`, the game colors it
+}
+
+impl BlockModel {
+    // Follows `#name` variables to a texture name, None where one is never defined
+    fn texture<'a>(&'a self, reference: &'a str) -> Option<&'a str> {
+        let mut name = reference;
+        for _ in 0..MAX_NESTING {
+            match name.strip_prefix('#') {
+                Some(variable) => name = self.textures.get(variable)?,
+                None => return Some(name),
+            }
+        }
+        None
+    }
+}
+
+// The models a blockstate shows for `state`: the first variant in the file whose conditions all hold, or every
+// multipart piece that applies. Each is a list of models one of which is shown, by their weights.
+pub fn blockstate_models(pack: &ResourcePack, name: &str, state: &[(String, String)]) -> Result<Vec<Vec<Placement>>, String> {
+    let json = read_json(&pack.blockstate_path(name)).map_err(|e| format!("blockstate `{}`: {}", name, e))?;
+    if let Some(variants) = json.get("variants").and_then(Value::as_object) {
+        for (key, variant) in variants {
+            let matches = key.split(',').filter(|condition| !condition.is_empty()).all(|condition| {
+                condition.split_once('=').is_some_and(|(key, value)| state_value(state, key) == Some(value))
+            });
+            if matches {
+                return Ok(vec![placements(variant).map_err(|e| format!("blockstate `{}`: {}", name, e))?]);
+            }
+        }
+        let state: Vec<String> = state.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        return Err(format!("blockstate `{}` has no variant for `{}`", name, state.join(",")));
+    }
+
+    if let Some(parts) = json.get("multipart").and_then(Value::as_array) {
+        let mut models = Vec::new();
+        for part in parts {
+            let applies = part.get("when").is_none_or(|when| condition_holds(when, state));
+            if applies {
+                let apply = part.get("apply").ok_or_else(|| format!("blockstate `{}`: multipart piece without `apply`", name))?;
+                models.push(placements(apply).map_err(|e| format!("blockstate `{}`: {}", name, e))?);
+            }
+        }
+        return Ok(models);
+    }
+
+    Err(format!("blockstate `{}` has neither `variants` nor `multipart`", name))
+}
+
+// Every way of taking one model from each part, with its odds, the product of the models' weights
+pub fn combinations(parts: &[Vec<Placement>]) -> Vec<(u64, Vec<Placement>)> {
+    let mut combinations = vec![(1_u64, Vec::with_capacity(parts.len()))];
+    for part in parts {
+        combinations = combinations
+            .iter()
+            .flat_map(|(weight, picked)| {
+                part.iter().map(move |placement| {
+                    let mut picked = picked.clone();
+                    picked.push(placement.clone());
+                    (weight.saturating_mul(u64::from(placement.weight)), picked)
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+// The index of the weighted choice the block at `x,y,z` shows. Random like in the game, where a
+// field of grass doesn't face one way, but always the same for a position so renders repeat.
+pub fn pick(weights: &[u64], x: usize, y: usize, z: usize) -> usize {
+    // The game's position hash
+    let (x, y, z) = (x as i64, y as i64, z as i64);
+    let seed = (x.wrapping_mul(3129871) as i32 as i64) ^ z.wrapping_mul(116129781) ^ y;
+    let seed = seed.wrapping_mul(seed).wrapping_mul(42317861).wrapping_add(seed.wrapping_mul(11)) >> 16;
+
+    let total = weights.iter().fold(0, |total: u64, weight| total.saturating_add(*weight));
+    let mut roll = seed as u64 % total;
+    for (index, weight) in weights.iter().enumerate() {
+        if roll < *weight {
+            return index;
+        }
+        roll -= weight;
+    }
+    weights.len() - 1
+}
+
+// The elements of the placed models, with `material` on every face and the textures the models
+// name looked up through `texture`, multiplied by `tint` on the faces the game tints. Also returns
+// the `particle` texture, which stands for the whole block where it's seen from far away.
+pub fn build_shape(
+    pack: &ResourcePack,
+    placements: &[Placement],
+    material: &Material,
+    tint: Option<Color>,
+    texture: &mut dyn FnMut(&str) -> Arc<Texture>,
+) -> Result<(BlockShape, Option<Arc<Texture>>), String> {
+    let mut elements = Vec::new();
+    let mut particle = None;
+    let mut full_cube = false;
+    let mut tinted_textures: HashMap<String, Arc<Texture>> = HashMap::new();
+
+    for placement in placements {
+        let model = load_model(pack, &placement.model, 0)?;
+        if particle.is_none() {
+            particle = model.texture("#particle").map(&mut *texture);
+        }
+        full_cube |= placements.len() == 1 && model.elements.len() == 1 && is_full_cube(&model.elements[0]);
+
+        let turn = blockstate_rotation(placement.x, placement.y);
+        for element in &model.elements {
+            let mut textures: [Arc<Texture>; 6] = std::array::from_fn(|_| Arc::clone(&MISSING));
+            let mut faces = [None; 6];
+            for (face, def) in element.faces.iter().enumerate() {
+                let Some(def) = def else {
+                    continue;
+                };
+                let name = model.texture(&def.texture).unwrap_or(&def.texture);
+                textures[face] = match (def.tinted, tint) {
+                    (false, _) => texture(name),
+                    (true, Some(tint)) => {
+                        let tinted = tinted_textures.entry(name.to_string()).or_insert_with(|| Arc::new(texture(name).tinted(tint)));
+                        Arc::clone(tinted)
+                    }
+                    (true, None) => {
+                        return Err(format!(
+                            "model `{}` has faces the game tints (`tintindex`), give the material a `block_tint` color, like 145,189,89 for plains grass",
+                            placement.model
+                        ));
+                    }
+                };
+                let face_def = ElementFace { uv: def.uv.unwrap_or_else(|| default_uv(element, face)), rotation: def.rotation };
+                let face_def = if placement.uvlock { locked_uv(&turn, face, &face_def) } else { face_def };
+                faces[face] = Some(ElementFace { uv: face_def.uv.map(|value| value / MODEL_UNITS), ..face_def });
+            }
+
+            // Block point = turn * (rotation * (p - origin) + origin - center) + center
+            let center = Vec3::new(8.0, 8.0, 8.0);
+            let (rotation, origin) = match &element.rotation {
+                Some(rotation) => (element_rotation(rotation), rotation.origin),
+                None => (Mat3::identity(), Vec3::zeros()),
+            };
+            let to_block = turn * rotation;
+            let offset = turn * (origin - rotation * origin - center) + center;
+            let to_element = glm::inverse(&to_block);
+
+            // Every face shows the first frame of its texture, animated ones stack the rest below
+            let faces_textures = FaceTextures { faces: textures.map(|texture| FaceTexture { rect: texture.first_frame(), texture }) };
+            let element_material = Material { diffuse: Diffuse::Texture(Box::new(faces_textures)), shape: None, ..material.clone() };
+            elements.push(ModelElement {
+                from: element.from,
+                to: element.to,
+                to_element,
+                offset,
+                faces,
+                material: Arc::new(element_material),
+            });
+        }
+    }
+
+    Ok((BlockShape { elements, full_cube }, particle))
+}
+
+fn load_model(pack: &ResourcePack, name: &str, depth: usize) -> Result<BlockModel, String> {
+    if depth > MAX_NESTING {
+        return Err(format!("model `{}` has too many parents", name));
+    }
+    let json = read_json(&pack.model_path(name)).map_err(|e| format!("model `{}`: {}", name, e))?;
+
+    // Items and entities rendered by the game itself have no elements to inherit
+    let mut model = match json.get("parent").and_then(Value::as_str) {
+        Some(parent) if parent.starts_with("builtin/") => BlockModel::default(),
+        Some(parent) => load_model(pack, parent, depth + 1)?,
+        None => BlockModel::default(),
+    };
+    if let Some(textures) = json.get("textures").and_then(Value::as_object) {
+        for (variable, texture) in textures {
+            if let Some(texture) = texture.as_str() {
+                model.textures.insert(variable.clone(), texture.to_string());
+            }
+        }
+    }
+    if let Some(elements) = json.get("elements").and_then(Value::as_array) {
+        model.elements = elements.iter().map(parse_element).collect::<Result<_, _>>().map_err(|e| format!("model `{}`: {}", name, e))?;
+    }
+    Ok(model)
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("could not read '{}': {}", path.display(), e))?;
+    serde_json::from_str(&source).map_err(|e| format!("'{}' is not valid JSON: {}", path.display(), e))
+}
+
+fn parse_element(json: &Value) -> Result<ElementDef, String> {
+    let rotation = match json.get("rotation") {
+        Some(rotation) => Some(ElementRotation {
+            origin: parse_vec3(rotation.get("origin"), "rotation origin")?,
+            axis: match rotation.get("axis").and_then(Value::as_str) {
+                Some("x") => 0,
+                Some("y") => 1,
+                Some("z") => 2,
+                _ => return Err(String::from("element rotation needs an `axis` of x, y or z")),
+            },
+            angle: rotation.get("angle").and_then(Value::as_f64).unwrap_or(0.0) as f32,
+            rescale: rotation.get("rescale").and_then(Value::as_bool).unwrap_or(false),
+        }),
+        None => None,
+    };
+
+    let mut faces: [Option<FaceDef>; 6] = Default::default();
+    if let Some(face_defs) = json.get("faces").and_then(Value::as_object) {
+        for (key, def) in face_defs {
+            let face = FACE_KEYS.iter().position(|face| face == key).ok_or_else(|| format!("unknown face `{}`", key))?;
+            let uv = match def.get("uv") {
+                Some(uv) => {
+                    let values = parse_numbers(uv, 4, "face uv")?;
+                    Some([values[0], values[1], values[2], values[3]])
+                }
+                None => None,
+            };
+            let texture = def.get("texture").and_then(Value::as_str).ok_or_else(|| format!("the {} face has no texture", key))?;
+            let rotation = def.get("rotation").and_then(Value::as_u64).unwrap_or(0) as u32;
+            let tinted = def.get("tintindex").is_some();
+            faces[face] = Some(FaceDef { uv, texture: texture.to_string(), rotation, tinted });
+        }
+    }
+
+    Ok(ElementDef {
+        from: parse_vec3(json.get("from"), "element `from`")?,
+        to: parse_vec3(json.get("to"), "element `to`")?,
+        rotation,
+        faces,
+    })
+}
+
+fn parse_vec3(json: Option<&Value>, what: &str) -> Result<Vec3, String> {
+    let values = parse_numbers(json.ok_or_else(|| format!("{} is missing", what))?, 3, what)?;
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+fn parse_numbers(json: &Value, count: usize, what: &str) -> Result<Vec<f32>, String> {
+    let values: Option<Vec<f32>> = json.as_array().map(|array| array.iter().map(|value| value.as_f64().map(|value| value as f32)).collect()).unwrap_or(None);
+    match values {
+        Some(values) if values.len() == count => Ok(values),
+        _ => Err(format!("{} should be {} numbers, found {}", what, count, json)),
+    }
+}
+
+// One model, or a list the game picks one of at random
+fn placements(json: &Value) -> Result<Vec<Placement>, String> {
+    let Some(choices) = json.as_array() else {
+        return Ok(vec![placement(json)?]);
+    };
+    if choices.is_empty() {
+        return Err(String::from("empty list of models"));
+    }
+    choices.iter().map(placement).collect()
+}
+
+fn placement(json: &Value) -> Result<Placement, String> {
+    let model = json.get("model").and_then(Value::as_str).ok_or_else(|| String::from("variant without a `model`"))?;
+    let angle = |key: &str| json.get(key).and_then(Value::as_i64).unwrap_or(0) as i32;
+    let weight = match json.get("weight") {
+        Some(weight) => match weight.as_u64() {
+            Some(weight @ 1..=0xffff) => weight as u32,
+            _ => return Err(format!("model `{}` has a weight of {}, weights are whole numbers from 1 to 65535", model, weight)),
+        },
+        None => 1,
+    };
+    let uvlock = json.get("uvlock").and_then(Value::as_bool).unwrap_or(false);
+    Ok(Placement { model: model.to_string(), x: angle("x"), y: angle("y"), weight, uvlock })
+}
+
+// A multipart `when`: every listed property has one of the `|` separated values, or any or all
+// of the conditions under `OR` or `AND` hold
+fn condition_holds(when: &Value, state: &[(String, String)]) -> bool {
+    if let Some(any) = when.get("OR").and_then(Value::as_array) {
+        return any.iter().any(|condition| condition_holds(condition, state));
+    }
+    if let Some(all) = when.get("AND").and_then(Value::as_array) {
+        return all.iter().all(|condition| condition_holds(condition, state));
+    }
+    when.as_object().is_some_and(|conditions| {
+        conditions.iter().all(|(key, values)| {
+            let values = match values {
+                Value::String(values) => values.clone(),
+                other => other.to_string(),
+            };
+            state_value(state, key).is_some_and(|value| values.split('|').any(|allowed| allowed == value))
+        })
+    })
+}
+
+fn state_value<'a>(state: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    state.iter().find(|(property, _)| property == key).map(|(_, value)| value.as_str())
+}
+
+// The part of the texture a face shows when the model doesn't say, as if the texture was
+// projected onto the block along the face's normal
+fn default_uv(element: &ElementDef, face: usize) -> [f32; 4] {
+    let (from, to) = (element.from, element.to);
+    match face {
+        0 => [from.x, from.z, to.x, to.z],
+        1 => [from.x, 16.0 - to.z, to.x, 16.0 - from.z],
+        2 => [16.0 - to.x, 16.0 - to.y, 16.0 - from.x, 16.0 - from.y],
+        3 => [from.x, 16.0 - to.y, to.x, 16.0 - from.y],
+        4 => [16.0 - to.z, 16.0 - to.y, 16.0 - from.z, 16.0 - from.y],
+        _ => [from.z, 16.0 - to.y, to.z, 16.0 - from.y],
+    }
+}
+
+// With `uvlock` a face shows its texture as if the blockstate hadn't turned the model: the part of
+// the texture the turned face covers is found by projecting it on the block face it now looks out
+// of. Element rotations are left out, like the game does.
+fn locked_uv(turn: &Mat3, face: usize, element_face: &ElementFace) -> ElementFace {
+    let center = Vec3::new(8.0, 8.0, 8.0);
+    let normal = turn * FACE_NORMALS[face];
+    let axis = normal.iamax();
+    let turned_face = face_index(axis, normal[axis]);
+
+    // Where the texture point `u`, `v` of the face lands on the texture of the face it turned into
+    let relock = |(u, v): (f32, f32)| project(turned_face, &(turn * (unproject(face, u, v) - center) + center));
+    let start = relock(element_face.at(0.0, 0.0));
+    let along_s = relock(element_face.at(1.0, 0.0));
+    let along_t = relock(element_face.at(0.0, 1.0));
+
+    // Turned by 0 or 180 degrees the texture's u still runs along the face's s, otherwise along t.
+    // The other two rotations are these with the uv flipped.
+    let straight = (along_s.0 - start.0).abs() + (along_t.1 - start.1).abs();
+    let across = (along_s.1 - start.1).abs() + (along_t.0 - start.0).abs();
+    if straight >= across {
+        ElementFace { uv: [start.0, start.1, along_s.0, along_t.1], rotation: 0 }
+    } else {
+        ElementFace { uv: [start.0, along_s.1, along_t.0, start.1], rotation: 90 }
+    }
+}
+
+const FACE_NORMALS: [Vec3; 6] = [
+    Vec3::new(0.0, 1.0, 0.0),
+    Vec3::new(0.0, -1.0, 0.0),
+    Vec3::new(0.0, 0.0, -1.0),
+    Vec3::new(0.0, 0.0, 1.0),
+    Vec3::new(1.0, 0.0, 0.0),
+    Vec3::new(-1.0, 0.0, 0.0),
+];
+
+// The texture point a point of the block shows on `face` by default, like `default_uv`
+fn project(face: usize, point: &Vec3) -> (f32, f32) {
+    match face {
+        0 => (point.x, point.z),
+        1 => (point.x, 16.0 - point.z),
+        2 => (16.0 - point.x, 16.0 - point.y),
+        3 => (point.x, 16.0 - point.y),
+        4 => (16.0 - point.z, 16.0 - point.y),
+        _ => (point.z, 16.0 - point.y),
+    }
+}
+
+// The point of the block's `face` showing the texture point `u`, `v` by default
+fn unproject(face: usize, u: f32, v: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(u, 16.0, v),
+        1 => Vec3::new(u, 0.0, 16.0 - v),
+        2 => Vec3::new(16.0 - u, 16.0 - v, 0.0),
+        3 => Vec3::new(u, 16.0 - v, 16.0),
+        4 => Vec3::new(16.0, 16.0 - v, 16.0 - u),
+        _ => Vec3::new(0.0, 16.0 - v, u),
+    }
+}
+
+fn element_rotation(rotation: &ElementRotation) -> Mat3 {
+    let mut axis = Vec3::zeros();
+    axis[rotation.axis] = 1.0;
+    let turn = glm::mat4_to_mat3(&glm::rotation(rotation.angle.to_radians(), &axis));
+    if !rotation.rescale {
+        return turn;
+    }
+    // Rescaled elements are stretched across the other two axes to reach the block's edges again
+    let stretch = 1.0 / rotation.angle.to_radians().cos();
+    let mut scale = Vec3::new(stretch, stretch, stretch);
+    scale[rotation.axis] = 1.0;
+    turn * Mat3::from_diagonal(&scale)
+}
+
+// Blockstates turn models clockwise looking along the axis from the positive side: `x` first, then `y`
+fn blockstate_rotation(x: i32, y: i32) -> Mat3 {
+    let turn = |degrees: i32, axis: Vec3| glm::mat4_to_mat3(&glm::rotation(-(degrees as f32).to_radians(), &axis));
+    turn(y, Vec3::y()) * turn(x, Vec3::x())
+}
+
+fn is_full_cube(element: &ElementDef) -> bool {
+    element.rotation.is_none()
+        && element.from == Vec3::zeros()
+        && element.to == Vec3::new(16.0, 16.0, 16.0)
+        && element.faces.iter().all(Option::is_some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::entitiy::color::HdrColor;
+    use crate::internal::temp_dir::TempDir;
+
+    fn placement(model: &str, weight: u32) -> Placement {
+        Placement { model: model.to_string(), x: 0, y: 0, weight, uvlock: false }
+    }
+
+    fn models(parts: &[Vec<Placement>]) -> Vec<Vec<(&str, u32)>> {
+        parts.iter().map(|part| part.iter().map(|placement| (placement.model.as_str(), placement.weight)).collect()).collect()
+    }
+
+    #[test]
+    fn variants_and_multipart_pieces_list_their_weighted_models() {
+        let dir = TempDir::new("block_model_weights");
+        let pack = dir.resource_pack(&[
+            ("blockstates/rock.json", r#"{ "variants": { "": [{ "model": "rock", "weight": 3 }, { "model": "mossy_rock", "y": 90 }] } }"#),
+            ("blockstates/post.json", r#"{ "multipart": [
+                { "apply": { "model": "post" } },
+                { "when": { "north": "true" }, "apply": [{ "model": "side" }, { "model": "side_vines", "weight": 2 }] }
+            ] }"#),
+            ("blockstates/bad.json", r#"{ "variants": { "": [{ "model": "rock", "weight": 0 }] } }"#),
+            ("blockstates/log.json", r#"{ "variants": { "axis=y": { "model": "log" }, "": { "model": "bark" }, "axis=x": { "model": "log", "x": 90 } } }"#),
+        ]);
+
+        let rock = blockstate_models(&pack, "rock", &[]).unwrap();
+        assert_eq!(models(&rock), vec![vec![("rock", 3), ("mossy_rock", 1)]]);
+        assert_eq!(rock[0][1].y, 90);
+
+        let north = [(String::from("north"), String::from("true"))];
+        let post = blockstate_models(&pack, "post", &north).unwrap();
+        assert_eq!(models(&post), vec![vec![("post", 1)], vec![("side", 1), ("side_vines", 2)]]);
+
+        assert!(blockstate_models(&pack, "bad", &[]).is_err());
+
+        // Variants are tried in the order the file lists them, not sorted by key
+        let axis = |value: &str| [(String::from("axis"), String::from(value))];
+        assert_eq!(models(&blockstate_models(&pack, "log", &axis("y")).unwrap()), vec![vec![("log", 1)]]);
+        assert_eq!(models(&blockstate_models(&pack, "log", &axis("x")).unwrap()), vec![vec![("bark", 1)]]);
+    }
+
+    #[test]
+    fn combinations_multiply_the_weights_of_each_part() {
+        let parts = [vec![placement("a", 2), placement("b", 1)], vec![placement("c", 3)], vec![placement("d", 1), placement("e", 5)]];
+        let combinations = combinations(&parts);
+        let combinations: Vec<(u64, Vec<&str>)> = combinations
+            .iter()
+            .map(|(weight, picked)| (*weight, picked.iter().map(|placement| placement.model.as_str()).collect()))
+            .collect();
+        assert_eq!(combinations, vec![
+            (6, vec!["a", "c", "d"]),
+            (30, vec!["a", "c", "e"]),
+            (3, vec!["b", "c", "d"]),
+            (15, vec!["b", "c", "e"]),
+        ]);
+    }
+
+    const CUBE: &str = r##"{ "textures": { "all": "stone" }, "elements": [{ "from": [0, 0, 0], "to": [16, 16, 16], "faces": {
+        "up": { "texture": "#all" }, "down": { "texture": "#all" }, "north": { "texture": "#all" },
+        "south": { "texture": "#all" }, "east": { "texture": "#all" }, "west": { "texture": "#all" }
+    } }] }"##;
+
+    fn shape(pack: &ResourcePack, placement: Placement, tint: Option<Color>) -> Result<BlockShape, String> {
+        build_shape(pack, &[placement], &Material::black(), tint, &mut |_| Arc::clone(&MISSING)).map(|(shape, _)| shape)
+    }
+
+    // Where a ray straight at `face` of the unit block hits, through the point `s`, `t` of it
+    fn hit_face(shape: &BlockShape, face: usize, s: f32, t: f32) -> Option<(Vec3, f32, f32)> {
+        let point = unproject(face, s * 16.0, t * 16.0) / 16.0;
+        let normal = FACE_NORMALS[face];
+        let hit = shape.ray_intersect(&Vec3::zeros(), &Vec3::new(1.0, 1.0, 1.0), &(point + normal * 2.0), &-normal);
+        let coords = hit.tex_coords?;
+        Some((hit.point, coords.u, coords.v))
+    }
+
+    #[test]
+    fn textures_and_parents_are_resolved() {
+        let dir = TempDir::new("block_model_parents");
+        let pack = dir.resource_pack(&[
+            ("models/block/half.json", r##"{ "elements": [{ "from": [0, 0, 0], "to": [16, 8, 16], "faces": {
+                "up": { "texture": "#top" }, "north": { "texture": "#side", "uv": [0, 8, 16, 16] }
+            } }] }"##),
+            ("models/block/slab.json", r##"{ "parent": "block/half", "textures": { "top": "#base", "base": "stone", "side": "minecraft:block/planks" } }"##),
+        ]);
+        let mut names = Vec::new();
+        let (shape, _) = build_shape(&pack, &[placement("slab", 1)], &Material::black(), None, &mut |name| {
+            names.push(name.to_string());
+            Arc::clone(&MISSING)
+        })
+        .unwrap();
+        assert_eq!(names, ["stone", "minecraft:block/planks"]);
+        assert!(!shape.full_cube);
+
+        let hit = shape.ray_intersect(&Vec3::zeros(), &Vec3::new(1.0, 1.0, 1.0), &Vec3::new(0.25, 3.0, 0.75), &Vec3::new(0.0, -1.0, 0.0));
+        assert!((hit.point.y - 0.5).abs() < 1e-5);
+        let coords = hit.tex_coords.unwrap();
+        assert!((coords.u - 0.25).abs() < 1e-5 && (coords.v - 0.75).abs() < 1e-5);
+
+        // The north face was given the lower half of its texture, and has no texture above the slab
+        let (_, u, v) = hit_face(&shape, 2, 0.25, 0.75).unwrap();
+        assert!((u - 0.25).abs() < 1e-5 && (v - 0.75).abs() < 1e-5, "{} {}", u, v);
+        assert!(hit_face(&shape, 2, 0.5, 0.25).is_none());
+    }
+
+    #[test]
+    fn blockstates_turn_models_clockwise() {
+        let dir = TempDir::new("block_model_turns");
+        let pack = dir.resource_pack(&[("models/block/front.json", r##"{ "elements": [{ "from": [0, 0, 0], "to": [16, 16, 16], "faces": {
+            "north": { "texture": "stone" }, "up": { "texture": "stone" }
+        } }] }"##)]);
+        // y=90 takes the north face east, x=90 takes it down and the top to the north, like
+        // observers facing down
+        let turned = |x, y| shape(&pack, Placement { x, y, ..placement("front", 1) }, None).unwrap();
+        let east = turned(0, 90);
+        assert!(hit_face(&east, 4, 0.5, 0.5).is_some());
+        assert!(hit_face(&east, 2, 0.5, 0.5).is_none());
+        let down = turned(90, 0);
+        assert_eq!(hit_face(&down, 1, 0.5, 0.5).unwrap().0.y, 0.0);
+        assert_eq!(hit_face(&down, 2, 0.5, 0.5).unwrap().0.z, 0.0);
+        // From above the ray goes in through the open top and sees the bottom from inside
+        assert_eq!(hit_face(&down, 0, 0.5, 0.5).unwrap().0.y, 0.0);
+
+        // The texture turns with the model: the north-west corner of the top is now north-east
+        let (_, u, v) = hit_face(&east, 0, 31.0 / 32.0, 1.0 / 32.0).unwrap();
+        assert!((u - 1.0 / 32.0).abs() < 1e-5 && (v - 1.0 / 32.0).abs() < 1e-5, "{} {}", u, v);
+    }
+
+    #[test]
+    fn uvlock_keeps_textures_lined_up_with_the_world() {
+        let dir = TempDir::new("block_model_uvlock");
+        let pack = dir.resource_pack(&[("models/block/cube.json", CUBE)]);
+        for x in [0, 90, 180, 270] {
+            for y in [0, 90, 180, 270] {
+                let shape = shape(&pack, Placement { x, y, uvlock: true, ..placement("cube", 1) }, None).unwrap();
+                for face in 0..6 {
+                    for (s, t) in [(0.1, 0.2), (0.8, 0.3), (0.4, 0.9)] {
+                        let (point, u, v) = hit_face(&shape, face, s, t).unwrap();
+                        let (expected_u, expected_v) = project(face, &(point * 16.0));
+                        assert!(
+                            (u - expected_u / 16.0).abs() < 1e-4 && (v - expected_v / 16.0).abs() < 1e-4,
+                            "x={} y={} face {}: {},{} instead of {},{}", x, y, face, u, v, expected_u / 16.0, expected_v / 16.0
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tinted_faces_take_the_block_tint() {
+        let dir = TempDir::new("block_model_tint");
+        let pack = dir.resource_pack(&[("models/block/grass.json", r##"{ "elements": [{ "from": [0, 0, 0], "to": [16, 16, 16], "faces": {
+            "up": { "texture": "grass_top", "tintindex": 0 }, "north": { "texture": "grass_side" }
+        } }] }"##)]);
+        assert!(shape(&pack, placement("grass", 1), None).is_err());
+
+        let shape = shape(&pack, placement("grass", 1), Some(Color::new(255, 128, 0))).unwrap();
+        let color = |face: usize| {
+            let point = unproject(face, 4.0, 4.0) / 16.0 + FACE_NORMALS[face];
+            shape.ray_intersect(&Vec3::zeros(), &Vec3::new(1.0, 1.0, 1.0), &point, &-FACE_NORMALS[face]).color
+        };
+        // The magenta of the missing texture, with its blue taken out on the tinted top only
+        assert_eq!(color(0), HdrColor::from(Color::new(248, 0, 0)));
+        assert_eq!(color(2), HdrColor::from(Color::new(248, 0, 248)));
+    }
+
+    #[test]
+    fn picks_follow_the_weights_and_the_position() {
+        let weights = [3, 1];
+        let mut counts = [0; 2];
+        for x in 0..64 {
+            for z in 0..64 {
+                let index = pick(&weights, x, 7, z);
+                assert_eq!(index, pick(&weights, x, 7, z));
+                counts[index] += 1;
+            }
+        }
+        let share = counts[0] as f32 / (64 * 64) as f32;
+        assert!((share - 0.75).abs() < 0.03, "{} of the blocks took the first model", share);
+
+        assert_eq!(pick(&[5], 3, 4, 5), 0);
+    }
+}
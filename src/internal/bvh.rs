@@ -0,0 +1,343 @@
+use nalgebra_glm::Vec3;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::entitiy::aabb::Aabb;
+use super::entitiy::intersect::Intersect;
+use super::entitiy::object::Object;
+
+const SAH_BUCKETS: usize = 12;
+const TRAVERSAL_COST: f32 = 1.0;   // relative cost of visiting a node
+const INTERSECTION_COST: f32 = 1.0; // relative cost of testing an object
+const MAX_LEAF_OBJECTS: usize = 4;
+
+// Bounding volume hierarchy over a list of objects, built with the surface area heuristic.
+// Traversal returns the closest hit, like testing every object would, and on hits at the same
+// distance the block placed first.
+pub struct Bvh {
+    objects: Vec<Box<dyn Object + Sync>>,
+    nodes: Vec<BvhNode>,
+    // Traversal counters for `stats`, only kept after `count_traversals` since every thread would
+    // otherwise fight over them on each ray
+    counting: bool,
+    rays: AtomicU64,
+    nodes_visited: AtomicU64,
+}
+
+struct BvhNode {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    // Objects `first..first + count` of the reordered object list
+    Leaf { first: usize, count: usize },
+    // The left child is always the next node, `axis` is the split axis
+    Interior { right: usize, axis: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BvhStats {
+    pub objects: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub depth: usize,
+    pub rays: u64,
+    pub nodes_visited: u64,
+}
+
+impl BvhStats {
+    pub fn average_nodes_visited(&self) -> f64 {
+        if self.rays == 0 {
+            0.0
+        } else {
+            self.nodes_visited as f64 / self.rays as f64
+        }
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BVH: {} objects, {} nodes ({} leaves), depth {}, {:.2} nodes visited per ray over {} rays",
+            self.objects, self.node_count, self.leaf_count, self.depth, self.average_nodes_visited(), self.rays
+        )
+    }
+}
+
+// Bounds of an object while building, `index` points into the original object list
+struct BuildItem {
+    bounds: Aabb,
+    centroid: Vec3,
+    index: usize,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Object + Sync>>) -> Bvh {
+        let mut items: Vec<BuildItem> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let bounds = object.bounding_box();
+                BuildItem { bounds, centroid: bounds.centroid(), index }
+            })
+            .collect();
+
+        let mut nodes = Vec::new();
+        if !items.is_empty() {
+            build(&mut items, 0, &mut nodes);
+        }
+
+        // Reorder objects so every leaf refers to a contiguous range
+        let mut slots: Vec<Option<Box<dyn Object + Sync>>> = objects.into_iter().map(Some).collect();
+        let objects = items.iter().map(|item| slots[item.index].take().unwrap()).collect();
+
+        Bvh {
+            objects,
+            nodes,
+            counting: false,
+            rays: AtomicU64::new(0),
+            nodes_visited: AtomicU64::new(0),
+        }
+    }
+
+    // Makes the rays and nodes visited in `stats` count from now on
+    pub fn count_traversals(&mut self) {
+        self.counting = true;
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        BvhStats {
+            objects: self.objects.len(),
+            node_count: self.nodes.len(),
+            leaf_count: self.nodes.iter().filter(|node| matches!(node.kind, NodeKind::Leaf { .. })).count(),
+            depth: if self.nodes.is_empty() { 0 } else { self.depth(0) },
+            rays: self.rays.load(Ordering::Relaxed),
+            nodes_visited: self.nodes_visited.load(Ordering::Relaxed),
+        }
+    }
+
+    fn depth(&self, node: usize) -> usize {
+        match self.nodes[node].kind {
+            NodeKind::Leaf { .. } => 1,
+            NodeKind::Interior { right, .. } => 1 + self.depth(node + 1).max(self.depth(right)),
+        }
+    }
+}
+
+// Builds the subtree for `items` and returns the index of its root node
+fn build(items: &mut [BuildItem], first: usize, nodes: &mut Vec<BvhNode>) -> usize {
+    let bounds = items.iter().fold(Aabb::empty(), |bounds, item| bounds.union(&item.bounds));
+    let node = nodes.len();
+    nodes.push(BvhNode { bounds, kind: NodeKind::Leaf { first, count: items.len() } });
+
+    if items.len() == 1 {
+        return node;
+    }
+
+    let centroid_bounds = items.iter().fold(Aabb::empty(), |bounds, item| bounds.union(&Aabb::new(item.centroid, item.centroid)));
+    let extent = centroid_bounds.max - centroid_bounds.min;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+
+    // Every centroid in the same place, there is nothing to split
+    if extent[axis] <= 0.0 {
+        return node;
+    }
+
+    // Bucket the centroids along the axis and find the cheapest split between buckets
+    let bucket_of = |item: &BuildItem| {
+        let offset = (item.centroid[axis] - centroid_bounds.min[axis]) / extent[axis];
+        ((offset * SAH_BUCKETS as f32) as usize).min(SAH_BUCKETS - 1)
+    };
+    let mut bucket_bounds = [Aabb::empty(); SAH_BUCKETS];
+    let mut bucket_counts = [0usize; SAH_BUCKETS];
+    for item in items.iter() {
+        let bucket = bucket_of(item);
+        bucket_counts[bucket] += 1;
+        bucket_bounds[bucket] = bucket_bounds[bucket].union(&item.bounds);
+    }
+
+    let mut best_cost = f32::INFINITY;
+    let mut best_split = 0;
+    for split in 1..SAH_BUCKETS {
+        let (left, right) = bucket_bounds.split_at(split);
+        let left_count: usize = bucket_counts[..split].iter().sum();
+        let right_count: usize = bucket_counts[split..].iter().sum();
+        if left_count == 0 || right_count == 0 {
+            continue;
+        }
+        let left_area = left.iter().fold(Aabb::empty(), |bounds, b| bounds.union(b)).surface_area();
+        let right_area = right.iter().fold(Aabb::empty(), |bounds, b| bounds.union(b)).surface_area();
+        let cost = TRAVERSAL_COST
+            + INTERSECTION_COST * (left_area * left_count as f32 + right_area * right_count as f32) / bounds.surface_area().max(f32::EPSILON);
+        if cost < best_cost {
+            best_cost = cost;
+            best_split = split;
+        }
+    }
+
+    let leaf_cost = INTERSECTION_COST * items.len() as f32;
+    if items.len() <= MAX_LEAF_OBJECTS && leaf_cost <= best_cost {
+        return node;
+    }
+
+    // Partition in place, items in buckets before the split go left
+    let mut middle = 0;
+    for i in 0..items.len() {
+        if bucket_of(&items[i]) < best_split {
+            items.swap(i, middle);
+            middle += 1;
+        }
+    }
+
+    let (left, right) = items.split_at_mut(middle);
+    build(left, first, nodes);
+    let right_node = build(right, first + middle, nodes);
+    nodes[node].kind = NodeKind::Interior { right: right_node, axis };
+    node
+}
+
+impl Object for Bvh {
+    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'_> {
+        let mut closest = Intersect::empty();
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let inverse_direction = Vec3::new(1.0 / ray_direction.x, 1.0 / ray_direction.y, 1.0 / ray_direction.z);
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        let mut visited = 0u64;
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            visited += 1;
+
+            if node.bounds.ray_entry(ray_origin, &inverse_direction, closest.distance).is_none() {
+                continue;
+            }
+
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for object in &self.objects[first..first + count] {
+                        let intersect = object.ray_intersect(ray_origin, ray_direction);
+                        if intersect.is_closer_than(&closest) {
+                            closest = intersect;
+                        }
+                    }
+                }
+                NodeKind::Interior { right, axis } => {
+                    let left = index + 1;
+                    // Visit the child nearer to the ray origin first, so the far one can be culled
+                    let (near, far) = if ray_direction[axis] < 0.0 { (right, left) } else { (left, right) };
+                    stack.push(far);
+                    stack.push(near);
+                }
+            }
+        }
+
+        if self.counting {
+            self.rays.fetch_add(1, Ordering::Relaxed);
+            self.nodes_visited.fetch_add(visited, Ordering::Relaxed);
+        }
+        closest
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes.first().map_or_else(Aabb::empty, |root| root.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::internal::entitiy::cube::Cube;
+    use crate::internal::entitiy::material::Material;
+    use crate::internal::sampling::Rng;
+
+    fn random_vector(rng: &mut Rng, scale: f32) -> Vec3 {
+        Vec3::new(rng.next_f32() * 2.0 - 1.0, rng.next_f32() * 2.0 - 1.0, rng.next_f32() * 2.0 - 1.0) * scale
+    }
+
+    // Boxes of all sizes scattered and overlapping, each with its own material to tell them apart
+    fn random_boxes(rng: &mut Rng, count: usize) -> Vec<(Vec3, Vec3, Arc<Material>)> {
+        (0..count)
+            .map(|_| {
+                let min = random_vector(rng, 10.0);
+                let max = min + Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 3.0 + Vec3::new(0.1, 0.1, 0.1);
+                (min, max, Arc::new(Material::black()))
+            })
+            .collect()
+    }
+
+    fn cubes(boxes: &[(Vec3, Vec3, Arc<Material>)]) -> Vec<Box<dyn Object + Sync>> {
+        boxes
+            .iter()
+            .map(|(min, max, material)| Box::new(Cube::new(*min, *max, Arc::clone(material))) as Box<dyn Object + Sync>)
+            .collect()
+    }
+
+    fn linear_scan<'a>(objects: &'a [Box<dyn Object + Sync>], ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'a> {
+        let mut closest = Intersect::empty();
+        for object in objects {
+            let intersect = object.ray_intersect(ray_origin, ray_direction);
+            if intersect.is_closer_than(&closest) {
+                closest = intersect;
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn hits_match_a_linear_scan() {
+        let mut rng = Rng::new(1, 0);
+        for count in [1, 2, 5, 40, 300] {
+            let boxes = random_boxes(&mut rng, count);
+            let listed = cubes(&boxes);
+            let bvh = Bvh::new(cubes(&boxes));
+            for _ in 0..2000 {
+                let origin = random_vector(&mut rng, 15.0);
+                let direction = random_vector(&mut rng, 1.0).normalize();
+                let expected = linear_scan(&listed, &origin, &direction);
+                let hit = bvh.ray_intersect(&origin, &direction);
+                assert_eq!(hit.is_intersecting, expected.is_intersecting);
+                if expected.is_intersecting {
+                    assert!(std::ptr::eq(hit.material, expected.material));
+                    assert_eq!(hit.distance, expected.distance);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tree_shape_and_traversal_counts() {
+        let mut rng = Rng::new(2, 0);
+        let empty = Bvh::new(Vec::new());
+        assert!(!empty.ray_intersect(&Vec3::zeros(), &Vec3::new(0.0, 0.0, 1.0)).is_intersecting);
+        assert_eq!(empty.stats().node_count, 0);
+
+        let mut bvh = Bvh::new(cubes(&random_boxes(&mut rng, 100)));
+        let stats = bvh.stats();
+        assert_eq!(stats.objects, 100);
+        assert_eq!(stats.node_count, 2 * stats.leaf_count - 1);
+        assert!(stats.depth > 1 && stats.depth < 100);
+
+        let direction = Vec3::new(0.0, 0.0, 1.0);
+        bvh.ray_intersect(&Vec3::new(0.0, 0.0, -20.0), &direction);
+        assert_eq!(bvh.stats().rays, 0);
+        bvh.count_traversals();
+        bvh.ray_intersect(&Vec3::new(0.0, 0.0, -20.0), &direction);
+        bvh.ray_intersect(&Vec3::new(30.0, 0.0, -20.0), &direction);
+        let stats = bvh.stats();
+        assert_eq!(stats.rays, 2);
+        assert!(stats.nodes_visited >= 2);
+    }
+}
@@ -0,0 +1,229 @@
+use nalgebra_glm::Vec3;
+
+use super::entitiy::texture::TextureFilter;
+use super::render::{Integrator, Shading};
+use super::sampling::{PixelFilter, SamplePattern};
+use super::tonemap::ToneMapping;
+
+pub const USAGE: &str = "\
+Usage:
+  CG-Minecraft [SCENE] [OPTIONS]   open the interactive viewer
+  CG-Minecraft render [OPTIONS]    render a single frame to an image file
+
+Viewer options:
+  --screenshot-width PIXELS    width of F3 high resolution screenshots (default: 1600)
+  --screenshot-height PIXELS   height of F3 high resolution screenshots (default: 1200)
+  --screenshot-samples N       rays per pixel for F3 screenshots (default: 16)
+  --resource-pack DIR          unpacked Java Edition resource pack to take block textures from,
+                               in place of the scene's own
+
+Render options:
+  --scene SCENE        scene name in scenes/ or path to a .scene file (default: house)
+  --width PIXELS       image width (default: 800)
+  --height PIXELS      image height (default: 600)
+  --day-angle RADIANS  time of day, 0 is 6:00 and PI is 18:00 (default: taken from the scene)
+  --eye X,Y,Z          camera position (default: taken from the scene)
+  --center X,Y,Z       point the camera looks at (default: taken from the scene)
+  --samples N          rays traced per pixel (default: 1)
+  --pattern PATTERN    grid, stratified, halton or sobol (default: grid)
+  --filter FILTER      box, tent or gaussian (default: box)
+  --seed N             seed for the random sample patterns (default: 0)
+  --shadow-samples N   shadow rays towards lights that have a size (default: 4)
+  --tone-mapping OP    clamp, reinhard or aces (default: aces)
+  --exposure STOPS     brightness adjustment before tone mapping (default: 0)
+  --integrator NAME    whitted, path for path tracing with bounced light, or ao to see only
+                       the ambient occlusion (default: whitted)
+  --ao-samples N       rays per hit looking for blocks that darken the ambient light, 0 turns
+                       ambient occlusion off (default: 8)
+  --ao-distance D      blocks farther away than this don't darken the ambient light (default: 2)
+  --bounces N          reflections and refractions traced after the camera ray, seeing through
+                       glass takes 2 (default: 0)
+  --resource-pack DIR  unpacked Java Edition resource pack to take block textures from, in place
+                       of the scene's own
+  --shading MODE       raytraced, levels for Minecraft's block and sky light levels without
+                       shadow rays, or smooth for levels blended across faces (default: raytraced)
+  --texture-filter F   nearest, bilinear or trilinear; the last two use mipmaps so far away
+                       blocks don't shimmer (default: nearest)
+  --out FILE           output image, .png .ppm .pfm .exr or .bmp (default: <scene>.png)
+  --stats              print acceleration structure statistics
+";
+
+pub enum Command {
+    View(ViewOptions),
+    Render(RenderOptions),
+    Help,
+}
+
+pub struct ViewOptions {
+    pub scene: String,
+    pub screenshot_width: usize,
+    pub screenshot_height: usize,
+    pub screenshot_samples: u32,
+    pub resource_pack: Option<String>,
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        ViewOptions {
+            scene: String::from("house"),
+            screenshot_width: 1600,
+            screenshot_height: 1200,
+            screenshot_samples: 16,
+            resource_pack: None,
+        }
+    }
+}
+
+pub struct RenderOptions {
+    pub scene: String,
+    pub width: usize,
+    pub height: usize,
+    pub day_angle: Option<f32>,
+    pub eye: Option<Vec3>,
+    pub center: Option<Vec3>,
+    pub samples: u32,
+    pub pattern: SamplePattern,
+    pub filter: PixelFilter,
+    pub seed: u64,
+    pub shadow_samples: u32,
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32,
+    pub integrator: Integrator,
+    pub ao_samples: u32,
+    pub ao_distance: f32,
+    pub bounces: u32,
+    pub shading: Shading,
+    pub texture_filter: TextureFilter,
+    pub resource_pack: Option<String>,
+    pub out: Option<String>,
+    pub stats: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            scene: String::from("house"),
+            width: 800,
+            height: 600,
+            day_angle: None,
+            eye: None,
+            center: None,
+            samples: 1,
+            pattern: SamplePattern::Grid,
+            filter: PixelFilter::Box,
+            seed: 0,
+            shadow_samples: 4,
+            tone_mapping: ToneMapping::Aces,
+            exposure: 0.0,
+            integrator: Integrator::Whitted,
+            ao_samples: 8,
+            ao_distance: 2.0,
+            bounces: 0,
+            shading: Shading::Raytraced,
+            texture_filter: TextureFilter::Nearest,
+            resource_pack: None,
+            out: None,
+            stats: false,
+        }
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("-h") | Some("--help") | Some("help") => Ok(Command::Help),
+        Some("render") => parse_render_options(&args[1..]).map(Command::Render),
+        _ => parse_view_options(args).map(Command::View),
+    }
+}
+
+fn parse_view_options(args: &[String]) -> Result<ViewOptions, String> {
+    let mut options = ViewOptions::default();
+    let mut scene_given = false;
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("`{}` expects a value", flag));
+        match flag.as_str() {
+            "--screenshot-width" => options.screenshot_width = parse_number(flag, value()?)?,
+            "--screenshot-height" => options.screenshot_height = parse_number(flag, value()?)?,
+            "--screenshot-samples" => options.screenshot_samples = parse_number(flag, value()?)?,
+            "--resource-pack" => options.resource_pack = Some(value()?.clone()),
+            scene if !scene.starts_with('-') && !scene_given => {
+                options.scene = scene.to_string();
+                scene_given = true;
+            }
+            other => return Err(format!("unexpected argument `{}`", other)),
+        }
+    }
+
+    if options.screenshot_width == 0 || options.screenshot_height == 0 {
+        return Err(String::from("screenshot size must be at least 1x1"));
+    }
+    if options.screenshot_samples == 0 {
+        return Err(String::from("`--screenshot-samples` must be at least 1"));
+    }
+    Ok(options)
+}
+
+fn parse_render_options(args: &[String]) -> Result<RenderOptions, String> {
+    let mut options = RenderOptions::default();
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("`{}` expects a value", flag));
+        match flag.as_str() {
+            "--scene" => options.scene = value()?.clone(),
+            "--width" => options.width = parse_number(flag, value()?)?,
+            "--height" => options.height = parse_number(flag, value()?)?,
+            "--day-angle" => options.day_angle = Some(parse_number(flag, value()?)?),
+            "--eye" => options.eye = Some(parse_vec3(flag, value()?)?),
+            "--center" => options.center = Some(parse_vec3(flag, value()?)?),
+            "--samples" => options.samples = parse_number(flag, value()?)?,
+            "--pattern" => options.pattern = value()?.parse()?,
+            "--filter" => options.filter = value()?.parse()?,
+            "--seed" => options.seed = parse_number(flag, value()?)?,
+            "--shadow-samples" => options.shadow_samples = parse_number(flag, value()?)?,
+            "--tone-mapping" => options.tone_mapping = value()?.parse()?,
+            "--exposure" => options.exposure = parse_number(flag, value()?)?,
+            "--integrator" => options.integrator = value()?.parse()?,
+            "--ao-samples" => options.ao_samples = parse_number(flag, value()?)?,
+            "--ao-distance" => options.ao_distance = parse_number(flag, value()?)?,
+            "--bounces" => options.bounces = parse_number(flag, value()?)?,
+            "--shading" => options.shading = value()?.parse()?,
+            "--texture-filter" => options.texture_filter = value()?.parse()?,
+            "--resource-pack" => options.resource_pack = Some(value()?.clone()),
+            "--out" => options.out = Some(value()?.clone()),
+            "--stats" => options.stats = true,
+            other => return Err(format!("unknown option `{}`", other)),
+        }
+    }
+
+    if options.width == 0 || options.height == 0 {
+        return Err(String::from("image size must be at least 1x1"));
+    }
+    if options.samples == 0 {
+        return Err(String::from("`--samples` must be at least 1"));
+    }
+    if options.shadow_samples == 0 {
+        return Err(String::from("`--shadow-samples` must be at least 1"));
+    }
+    if options.ao_distance <= 0.0 {
+        return Err(String::from("`--ao-distance` must be above 0"));
+    }
+    Ok(options)
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value `{}` for `{}`", value, flag))
+}
+
+fn parse_vec3(flag: &str, value: &str) -> Result<Vec3, String> {
+    let components = value
+        .split(',')
+        .map(|component| parse_number::<f32>(flag, component))
+        .collect::<Result<Vec<f32>, String>>()?;
+    match components[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("`{}` expects three comma separated numbers, found `{}`", flag, value)),
+    }
+}
@@ -0,0 +1,64 @@
+use nalgebra_glm::Vec3;
+
+// Axis aligned bounding box
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // A box containing nothing, growing it with `union` gives the other box
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        if size.x < 0.0 || size.y < 0.0 || size.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    // Distance where the ray enters the box, if it does so before `max_distance`.
+    // `inverse_direction` is 1 / ray_direction, computed once per ray.
+    pub fn ray_entry(&self, ray_origin: &Vec3, inverse_direction: &Vec3, max_distance: f32) -> Option<f32> {
+        let mut t_enter = 0.0f32;
+        // A little past it, multiplying by the inverse rounds differently from the division the
+        // objects use, and a box whose object ties with the nearest hit must not be skipped
+        let mut t_exit = max_distance * (1.0 + 1e-6);
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - ray_origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - ray_origin[axis]) * inverse_direction[axis];
+            // f32::min/max skip NaN, which keeps rays lying on a box face inside the slab
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+
+        if t_enter <= t_exit {
+            Some(t_enter)
+        } else {
+            None
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::ops::Add;
 use std::ops::Mul;
 use std::fmt;
+use once_cell::sync::Lazy;
 
 #[derive (Debug, Copy, Clone, PartialEq)]
 pub struct Color {
@@ -21,7 +22,7 @@ impl Color {
         Color { r, g, b }
     }
 
-    pub fn to_hex(&self) -> u32 {
+    pub fn to_hex(self) -> u32 {
         ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
     }
 }
@@ -52,4 +53,99 @@ impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Color(r: {}, g: {}, b: {})", self.r, self.g, self.b)
     }
-}
\ No newline at end of file
+}
+
+// Linear light color with unbounded f32 channels, used for all lighting math.
+// `Color` stays the 8 bit sRGB format of textures, scene files and the framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct HdrColor {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+// sRGB decoding of every 8 bit channel value, decoding happens for every ray hit
+static SRGB_TO_LINEAR: Lazy<[f32; 256]> = Lazy::new(|| {
+    let mut table = [0.0; 256];
+    for (value, linear) in table.iter_mut().enumerate() {
+        *linear = srgb_to_linear(value as f32 / 255.0);
+    }
+    table
+});
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl HdrColor {
+    pub fn new(r: f32, g: f32, b: f32) -> HdrColor {
+        HdrColor { r, g, b }
+    }
+
+    pub fn black() -> HdrColor {
+        HdrColor::new(0.0, 0.0, 0.0)
+    }
+
+    // Encodes to sRGB, channels outside [0, 1] are clipped
+    pub fn to_srgb(self) -> Color {
+        let encode = |value: f32| (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0 + 0.5) as u8;
+        Color::new(encode(self.r), encode(self.g), encode(self.b))
+    }
+
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+}
+
+impl From<Color> for HdrColor {
+    fn from(color: Color) -> HdrColor {
+        HdrColor {
+            r: SRGB_TO_LINEAR[color.r as usize],
+            g: SRGB_TO_LINEAR[color.g as usize],
+            b: SRGB_TO_LINEAR[color.b as usize],
+        }
+    }
+}
+
+impl Add for HdrColor {
+    type Output = HdrColor;
+
+    fn add(self, other: HdrColor) -> HdrColor {
+        HdrColor::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl Mul<f32> for HdrColor {
+    type Output = HdrColor;
+
+    fn mul(self, factor: f32) -> HdrColor {
+        HdrColor::new(self.r * factor, self.g * factor, self.b * factor)
+    }
+}
+
+// Channel by channel product, e.g. light color filtered by a surface color
+impl Mul for HdrColor {
+    type Output = HdrColor;
+
+    fn mul(self, other: HdrColor) -> HdrColor {
+        HdrColor::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+impl fmt::Display for HdrColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HdrColor(r: {}, g: {}, b: {})", self.r, self.g, self.b)
+    }
+}
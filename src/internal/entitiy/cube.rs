@@ -1,15 +1,11 @@
 use std::sync::Arc;
 use nalgebra_glm::Vec3;
-use super::material::{self, Diffuse, Material};
-use super::intersect::Intersect;
+use super::material::{Diffuse, Material};
+use super::aabb::Aabb;
+use super::intersect::{Intersect, TexCoords};
 use super::object::Object;
-use super::color::Color;
-use super::texture::Texture;
+use super::color::HdrColor;
 
-const NUM_FACE_COLUMNS: usize = 6; // Number of columns in the texture atlas
-const NUM_FACE_ROWS: usize = 1;     // Number of rows in the texture atlas
-const FACE_SIZE: f32 = 16.0;         // Size of each face in pixels
-                                     //
 pub struct Cube {
     pub min: Vec3,             // minimum corner of the cube
     pub max: Vec3,             // maximum corner of the cube
@@ -22,21 +18,14 @@ impl Cube{
     }
 }
 
-impl<'a> Object for Cube {
-    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect {
-        let mut tmin = (self.min.x - ray_origin.x) / ray_direction.x;
-        let mut tmax = (self.max.x - ray_origin.x) / ray_direction.x;
-
-        if tmin > tmax {
-            std::mem::swap(&mut tmin, &mut tmax);
+impl Object for Cube {
+    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'_> {
+        if let Some(shape) = &self.material.shape {
+            return shape.ray_intersect(&self.min, &self.max, ray_origin, ray_direction);
         }
 
-        let mut tymin = (self.min.y - ray_origin.y) / ray_direction.y;
-        let mut tymax = (self.max.y - ray_origin.y) / ray_direction.y;
-
-        if tymin > tymax {
-            std::mem::swap(&mut tymin, &mut tymax);
-        }
+        let (mut tmin, mut tmax) = slab(self.min.x, self.max.x, ray_origin.x, ray_direction.x);
+        let (tymin, tymax) = slab(self.min.y, self.max.y, ray_origin.y, ray_direction.y);
 
         if (tmin > tymax) || (tymin > tmax) {
             return Intersect::empty();
@@ -50,12 +39,7 @@ impl<'a> Object for Cube {
             tmax = tymax;
         }
 
-        let mut tzmin = (self.min.z - ray_origin.z) / ray_direction.z;
-        let mut tzmax = (self.max.z - ray_origin.z) / ray_direction.z;
-
-        if tzmin > tzmax {
-            std::mem::swap(&mut tzmin, &mut tzmax);
-        }
+        let (tzmin, tzmax) = slab(self.min.z, self.max.z, ray_origin.z, ray_direction.z);
 
         if (tmin > tzmax) || (tzmin > tmax) {
             return Intersect::empty();
@@ -69,42 +53,80 @@ impl<'a> Object for Cube {
             tmax = tzmax;
         }
 
-        // Si tmin es positivo, es una intersección en la dirección del rayo
+        // Si tmin es positivo, es una intersección en la dirección del rayo. A ray starting inside
+        // the block, like one refracted into glass, leaves through the far face instead, and so
+        // does one passing through a hole in the near face of a cutout or blended block
         if tmin > 0.0 {
-            let point = ray_origin + ray_direction * tmin;
-            let normal = self.calculate_normal(&point); // Calcula la normal en el punto de intersección
-            let distance = tmin;
-
-            // Calculate UV coordinates
-            let (u, v) = self.calculate_uv(&point, & normal);
-            
-            let surface_color = match &self.material.diffuse {
-                Diffuse::Color(color) => color.clone(),
-                Diffuse::Texture(texture) => {
-                    // Sample color from texture atlas based on UV coordinates
-                    let face = self.get_face_index(&normal).unwrap();
-                    self.sample_texture(texture, u, v, face)
-                }
-            };
-
-            // ERROR HERE
-            return Intersect::new(point, normal, distance, &self.material, surface_color); // Now using a reference to the material
+            if let Some(intersect) = self.surface_at(ray_origin, ray_direction, tmin, false) {
+                return intersect;
+            }
+        }
+        if tmax > 0.0 {
+            if let Some(intersect) = self.surface_at(ray_origin, ray_direction, tmax, true) {
+                return intersect;
+            }
         }
 
         Intersect::empty()
     }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.min, self.max)
+    }
+}
+
+// Where the ray enters and leaves the space between two parallel faces, nearest first. NaN comes
+// from a ray running exactly along one of the faces, it grazes the block so it counts as inside,
+// like the grid does for rays along the planes between cells.
+fn slab(min: f32, max: f32, origin: f32, direction: f32) -> (f32, f32) {
+    let t0 = (min - origin) / direction;
+    let t1 = (max - origin) / direction;
+    if t0.is_nan() || t1.is_nan() {
+        (f32::NEG_INFINITY, f32::INFINITY)
+    } else if t0 > t1 {
+        (t1, t0)
+    } else {
+        (t0, t1)
+    }
 }
 
 impl Cube {
 
+    // The hit `t` along the ray, None where the texel there is a hole
+    fn surface_at(&self, ray_origin: &Vec3, ray_direction: &Vec3, t: f32, far_face: bool) -> Option<Intersect<'_>> {
+        let point = ray_origin + ray_direction * t;
+        let normal = self.calculate_normal(&point); // Calcula la normal en el punto de intersección
+
+        // Calculate UV coordinates
+        let (u, v) = self.calculate_uv(&point, &normal);
+
+        let (surface_color, alpha, tex_coords) = match &self.material.diffuse {
+            Diffuse::Color(color) => (HdrColor::from(*color), 1.0, None),
+            Diffuse::Texture(faces) => {
+                // Sample color from the face's texture based on UV coordinates
+                let face = self.get_face_index(&normal).unwrap();
+                let (color, alpha) = faces.sample(face, u, v);
+                let size = self.max.x - self.min.x;
+                (HdrColor::from(color), alpha, Some(TexCoords { face, u, v, size }))
+            }
+        };
+        let coverage = self.material.coverage(alpha)?;
+
+        // Glass keeps the outward normal so refraction knows the ray is leaving, the far face of
+        // any other block is seen from inside and faces the ray
+        let normal = if far_face && self.material.transparency <= 0.0 { -normal } else { normal };
+        let intersect = Intersect::new(point, normal, t, &self.material, surface_color); // Now using a reference to the material
+        Some(Intersect { alpha: coverage, tex_coords, ..intersect })
+    }
+
     // Determine the face index based on the normal vector
     fn get_face_index(&self, normal: &Vec3) -> Option<usize> {
         if (normal.x).abs() > (normal.y).abs() && (normal.x).abs() > (normal.z).abs() {
-            return if normal.x < 0.0 { Some(5) } else { Some(4) }; // Left or Right face
+            if normal.x < 0.0 { Some(5) } else { Some(4) } // Left or Right face
         } else if (normal.y).abs() > (normal.x).abs() && (normal.y).abs() > (normal.z).abs() {
-            return if normal.y < 0.0 { Some(1) } else { Some(0) }; // Bottom or Top face
+            if normal.y < 0.0 { Some(1) } else { Some(0) } // Bottom or Top face
         } else {
-            return if normal.z < 0.0 { Some(2) } else { Some(3) }; // Back or Front face
+            if normal.z < 0.0 { Some(2) } else { Some(3) } // Back or Front face
         }
     }
     
@@ -134,35 +156,19 @@ impl Cube {
             // Left or right face (rotate by 180 degrees)
             let u = (point.z - self.min.z) / (self.max.z - self.min.z); // Z to U mapping, reversed
             let v = 1.0 - (point.y - self.min.y) / (self.max.y - self.min.y); // Y to V mapping
-            return (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0)); // Ensure u and v are within [0, 1]
+            (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0)) // Ensure u and v are within [0, 1]
         } else if (normal.y).abs() > (normal.x).abs() && (normal.y).abs() > (normal.z).abs() {
             // Bottom or top face
             let u = (point.x - self.min.x) / (self.max.x - self.min.x);
             let v = (point.z - self.min.z) / (self.max.z - self.min.z);
             // Ensure u and v are within [0, 1] range
-            return (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+            (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
         } else {
             // Front or back face
             let u = (point.x - self.min.x) / (self.max.x - self.min.x);
             let v = 1.0 - (point.y - self.min.y) / (self.max.y - self.min.y);
             // Ensure u and v are within [0, 1] range
-            return (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+            (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
         }
     }
-
-    fn sample_texture(&self, texture: &Arc<Texture>, u: f32, v: f32, face_index: usize) -> Color {
-        let tex_width = FACE_SIZE * NUM_FACE_COLUMNS as f32; // Total width for all columns
-        let tex_height = FACE_SIZE * NUM_FACE_ROWS as f32;   // Total height for all rows
-    
-        // Calculate the row and column based on the face index
-        let col = face_index % NUM_FACE_COLUMNS; // Number of columns
-        let row = face_index / NUM_FACE_COLUMNS;  // Number of rows
-    
-        // Sample the color based on UV coordinates
-        let x = (u * FACE_SIZE + (col as f32 * FACE_SIZE)).clamp(0.0, tex_width - 1.0) as usize;
-        let y = (v * FACE_SIZE + (row as f32 * FACE_SIZE)).clamp(0.0, tex_height - 1.0) as usize;
-    
-        // Use the method to get color from the texture atlas
-        texture.get_color(x, y) // Implement this method in your Texture struct
-    }
-}
\ No newline at end of file
+}
@@ -1,17 +1,38 @@
 use nalgebra_glm::Vec3;
 use std::sync::Arc;
 use super::material::Material;
+use super::aabb::Aabb;
 use super::cube::Cube;
-use super::object::Object; // Assuming you have an Object trait defined
+use super::intersect::Intersect;
+use super::light::AreaLight;
+use super::object::Object;
 
 pub struct Grid {
     pub cube_width: f32,
     pub height: usize,
     pub width: usize,
     pub depth: usize,
-    pub objects: Vec<Box<dyn Object + Sync>>, // Change to store objects instead of cubes
+    origin: Vec3,             // minimum corner
+    cells: Vec<Option<Cell>>, // dense x-z-y array of blocks, see `cell_index`
+    placed: usize,            // number of blocks added so far
 }
 
+struct Cell {
+    cube: Cube,
+    order: usize, // when the block was added, breaks ties between blocks hit at the same distance
+}
+
+// Cells a side of the pieces `into_chunks` cuts a grid into
+pub const CHUNK_SIZE: usize = 8;
+
+// Blocks sit one unit apart whatever their `cube_width`, so wider blocks overlap their neighbours
+// and narrower ones leave gaps. The cells the grid walks are this far apart too.
+pub const BLOCK_SPACING: f32 = 1.0;
+
+// Crossings closer than this are treated as simultaneous, so rays grazing an edge or
+// corner also test the blocks they touch there.
+const TIE_EPSILON: f32 = 1e-4;
+
 impl Grid {
     pub fn new(cube_width: f32, grid_height: usize, grid_width: usize, grid_depth: usize) -> Grid {
         Grid {
@@ -19,43 +40,483 @@ impl Grid {
             height: grid_height,
             width: grid_width,
             depth: grid_depth,
-            objects: Vec::new(), // Initialize objects vector
+            // the grid is centered around the origin
+            origin: -Vec3::new(grid_width as f32, grid_height as f32, grid_depth as f32) * (cube_width / 2.0),
+            cells: (0..grid_width * grid_height * grid_depth).map(|_| None).collect(),
+            placed: 0,
         }
     }
 
+    // Minimum corner of the grid
+    pub fn min(&self) -> Vec3 {
+        self.origin
+    }
+
+    // Maximum corner of the last block on each axis
+    pub fn max(&self) -> Vec3 {
+        let extent = |count: usize| if count == 0 { 0.0 } else { (count - 1) as f32 * BLOCK_SPACING + self.cube_width };
+        self.origin + Vec3::new(extent(self.width), extent(self.height), extent(self.depth))
+    }
+
+    // How many cells further along a block wider than the spacing reaches into
+    fn reach(&self) -> usize {
+        ((self.cube_width / BLOCK_SPACING).ceil() as usize).max(1) - 1
+    }
+
+    fn cell_index(&self, x: usize, y: usize, z: usize) -> usize {
+        (y * self.depth + z) * self.width + x
+    }
+
     pub fn add_cube(&mut self, x: usize, y: usize, z: usize, material: Arc<Material>) {
         // Check if coordinates are within the grid bounds
-        let grid_x_half = self.width as f32 / 2.0;
-        let grid_y_half = self.height as f32 / 2.0;
-        let grid_z_half = self.depth as f32 / 2.0;
-
         if x < self.width && y < self.height && z < self.depth {
-            let min = Vec3::new(
-                (x as f32) - grid_x_half * self.cube_width,
-                (y as f32) - grid_y_half * self.cube_width,
-                (z as f32) - grid_z_half * self.cube_width,
-            );
+            let min = self.min() + Vec3::new(x as f32, y as f32, z as f32) * BLOCK_SPACING;
             let max = Vec3::new(
                 min.x + self.cube_width,
                 min.y + self.cube_width,
                 min.z + self.cube_width,
             );
 
-            let cube = Cube::new(min, max, material);
-            self.objects.push(Box::new(cube)); // Store the cube as a Box<dyn Object + Sync>
+            let index = self.cell_index(x, y, z);
+            self.cells[index] = Some(Cell { cube: Cube::new(min, max, material), order: self.placed });
+            self.placed += 1;
         } else {
             eprintln!("Attempted to add a cube out of grid bounds.");
         }
     }
 
+    // Material of the block in a cell, None for air
+    pub fn block(&self, x: usize, y: usize, z: usize) -> Option<&Material> {
+        self.cells[self.cell_index(x, y, z)].as_ref().map(|cell| cell.cube.material.as_ref())
+    }
+
     pub fn add_layer(&mut self, layer: usize, list: &[(usize, usize, Arc<Material>)]) {
         // Check if the layer is within bounds
         if layer < self.height {
-            for &(x, z, ref material) in list {
-                self.add_cube(x, layer, z, Arc::clone(material));
+            for (x, z, material) in list {
+                self.add_cube(*x, layer, *z, Arc::clone(material));
             }
         } else {
             eprintln!("Attempted to add cubes to a layer out of grid bounds.");
         }
     }
-}
\ No newline at end of file
+
+    // Cuts the grid into grids of `size` cells a side (smaller at the far edges), leaving out the
+    // empty ones. Blocks keep their place in the world and the order they were added in.
+    pub fn into_chunks(mut self, size: usize) -> Vec<Grid> {
+        let mut chunks = Vec::new();
+        for y0 in (0..self.height).step_by(size) {
+            for z0 in (0..self.depth).step_by(size) {
+                for x0 in (0..self.width).step_by(size) {
+                    let (width, height, depth) = (size.min(self.width - x0), size.min(self.height - y0), size.min(self.depth - z0));
+                    let mut chunk = Grid {
+                        cube_width: self.cube_width,
+                        height,
+                        width,
+                        depth,
+                        origin: self.origin + Vec3::new(x0 as f32, y0 as f32, z0 as f32) * BLOCK_SPACING,
+                        cells: (0..width * height * depth).map(|_| None).collect(),
+                        placed: self.placed,
+                    };
+                    for y in 0..height {
+                        for z in 0..depth {
+                            for x in 0..width {
+                                let from = self.cell_index(x0 + x, y0 + y, z0 + z);
+                                let to = chunk.cell_index(x, y, z);
+                                chunk.cells[to] = self.cells[from].take();
+                            }
+                        }
+                    }
+                    if chunk.cells.iter().any(Option::is_some) {
+                        chunks.push(chunk);
+                    }
+                }
+            }
+        }
+        chunks
+    }
+
+    // One area light for every block made of an emissive material
+    pub fn emissive_blocks(&self) -> Vec<AreaLight> {
+        self.cells
+            .iter()
+            .flatten()
+            .filter(|cell| cell.cube.material.is_emissive())
+            .map(|cell| {
+                let material = &cell.cube.material;
+                AreaLight::new(cell.cube.min, cell.cube.max, material.emitted(material.average_color()))
+            })
+            .collect()
+    }
+
+    // Parametric distances where the ray enters and leaves the grid bounds
+    fn clip_ray(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Option<(f32, f32)> {
+        let min = self.min();
+        let max = self.max();
+        let mut t_enter = f32::NEG_INFINITY;
+        let mut t_exit = f32::INFINITY;
+
+        for axis in 0..3 {
+            let mut t0 = (min[axis] - ray_origin[axis]) / ray_direction[axis];
+            let mut t1 = (max[axis] - ray_origin[axis]) / ray_direction[axis];
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN comes from a ray parallel to and exactly on a bounding plane, treat it as inside
+            if !t0.is_nan() {
+                t_enter = t_enter.max(t0);
+            }
+            if !t1.is_nan() {
+                t_exit = t_exit.min(t1);
+            }
+        }
+
+        if t_enter > t_exit || t_exit < 0.0 {
+            None
+        } else {
+            Some((t_enter.max(0.0), t_exit))
+        }
+    }
+}
+
+impl Object for Grid {
+    // Amanatides & Woo voxel traversal: walk the cells pierced by the ray in order
+    // and stop at the first block it hits.
+    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'_> {
+        let (t_start, t_end) = match self.clip_ray(ray_origin, ray_direction) {
+            Some(range) if !self.cells.is_empty() => range,
+            _ => return Intersect::empty(),
+        };
+
+        let min = self.min();
+        // Blocks wider than the spacing stick out past the last cells, the walk goes on through
+        // empty cells there that still test the blocks reaching into them
+        let reach = self.reach();
+        let dimensions = [self.width + reach, self.height + reach, self.depth + reach];
+        let entry = ray_origin + ray_direction * t_start;
+
+        let mut cell = [0usize; 3];
+        let mut step = [0isize; 3];
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        // Axes where the ray runs exactly along a plane between two layers of cells, blocks
+        // on both sides of the plane count as touched
+        let mut on_plane = [false; 3];
+        // Axes where the ray enters on a plane between cells, it touches the cells on both sides there
+        let mut on_boundary = [false; 3];
+
+        for axis in 0..3 {
+            let local = (entry[axis] - min[axis]) / BLOCK_SPACING;
+            cell[axis] = (local.floor().max(0.0) as usize).min(dimensions[axis] - 1);
+            on_boundary[axis] = local.fract() == 0.0 && local > 0.0 && local < dimensions[axis] as f32;
+            on_plane[axis] = ray_direction[axis] == 0.0 && on_boundary[axis];
+
+            if ray_direction[axis] > 0.0 {
+                step[axis] = 1;
+                let boundary = min[axis] + (cell[axis] + 1) as f32 * BLOCK_SPACING;
+                t_max[axis] = (boundary - ray_origin[axis]) / ray_direction[axis];
+                t_delta[axis] = BLOCK_SPACING / ray_direction[axis];
+            } else if ray_direction[axis] < 0.0 {
+                step[axis] = -1;
+                let boundary = min[axis] + cell[axis] as f32 * BLOCK_SPACING;
+                t_max[axis] = (boundary - ray_origin[axis]) / ray_direction[axis];
+                t_delta[axis] = -BLOCK_SPACING / ray_direction[axis];
+            }
+        }
+
+        let mut nearest = Intersect::empty();
+        self.test_cell(cell, on_boundary, ray_origin, ray_direction, &mut nearest);
+
+        loop {
+            // Blocks in cells further along can't be hit before their boundary, but one right
+            // behind it can tie with the hit found, the block placed first then wins
+            let t_next = t_max[0].min(t_max[1]).min(t_max[2]);
+            if t_next > t_end {
+                break;
+            }
+            if nearest.is_intersecting && t_next - nearest.distance > TIE_EPSILON * nearest.distance.abs().max(1.0) {
+                break;
+            }
+
+            // Every axis whose boundary is crossed at (about) the same time is stepped together,
+            // after testing each cell the ray may clip on the way
+            let mut crossing = [0usize; 3];
+            let mut crossing_count = 0;
+            for (axis, t) in t_max.iter().enumerate() {
+                if t - t_next <= TIE_EPSILON * t_next.abs().max(1.0) {
+                    crossing[crossing_count] = axis;
+                    crossing_count += 1;
+                }
+            }
+            let crossing = &crossing[..crossing_count];
+
+            for mask in 1..(1 << crossing.len()) {
+                let mut neighbor = [cell[0] as isize, cell[1] as isize, cell[2] as isize];
+                for (bit, &axis) in crossing.iter().enumerate() {
+                    if mask & (1 << bit) != 0 {
+                        neighbor[axis] += step[axis];
+                    }
+                }
+                if (0..3).all(|axis| neighbor[axis] >= 0 && neighbor[axis] < dimensions[axis] as isize) {
+                    let neighbor = [neighbor[0] as usize, neighbor[1] as usize, neighbor[2] as usize];
+                    self.test_cell(neighbor, on_plane, ray_origin, ray_direction, &mut nearest);
+                }
+            }
+
+            for &axis in crossing {
+                let next = cell[axis] as isize + step[axis];
+                if next < 0 || next >= dimensions[axis] as isize {
+                    return nearest;
+                }
+                cell[axis] = next as usize;
+                t_max[axis] += t_delta[axis];
+            }
+        }
+
+        nearest
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.min(), self.max())
+    }
+}
+
+impl Grid {
+    // Tests every block reaching into `cell`: its own, those of the cells below it whose blocks
+    // are wide enough to reach it, and one more below across the planes marked in `on_plane`
+    fn test_cell<'a>(&'a self, cell: [usize; 3], on_plane: [bool; 3], ray_origin: &Vec3, ray_direction: &Vec3, nearest: &mut Intersect<'a>) {
+        let below = |axis: usize| (self.reach() + on_plane[axis] as usize).min(cell[axis]);
+        let last = |axis: usize, count: usize| cell[axis].min(count - 1);
+        for y in cell[1] - below(1)..=last(1, self.height) {
+            for z in cell[2] - below(2)..=last(2, self.depth) {
+                for x in cell[0] - below(0)..=last(0, self.width) {
+                    self.test_block([x, y, z], ray_origin, ray_direction, nearest);
+                }
+            }
+        }
+    }
+
+    // Keeps the closest hit, on equal distances the block added first wins
+    fn test_block<'a>(&'a self, cell: [usize; 3], ray_origin: &Vec3, ray_direction: &Vec3, nearest: &mut Intersect<'a>) {
+        let Some(block) = &self.cells[self.cell_index(cell[0], cell[1], cell[2])] else {
+            return;
+        };
+        let intersect = Intersect { order: block.order, ..block.cube.ray_intersect(ray_origin, ray_direction) };
+        if intersect.is_closer_than(nearest) {
+            *nearest = intersect;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::sampling::Rng;
+
+    fn materials(count: usize) -> Vec<Arc<Material>> {
+        (0..count).map(|_| Arc::new(Material::black())).collect()
+    }
+
+    #[test]
+    fn nearest_block_is_hit_whatever_the_placement_order() {
+        let materials = materials(2);
+        for order in [[0, 2], [2, 0]] {
+            let mut grid = Grid::new(1.0, 1, 3, 1);
+            for (x, material) in order.iter().zip(&materials) {
+                grid.add_cube(*x, 0, 0, Arc::clone(material));
+            }
+            let near = if order[0] == 0 { &materials[0] } else { &materials[1] };
+
+            let hit = grid.ray_intersect(&Vec3::new(-3.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+            assert!(hit.is_intersecting);
+            assert!(std::ptr::eq(hit.material, near.as_ref()));
+            assert_eq!(hit.distance, 1.5);
+        }
+    }
+
+    #[test]
+    fn blocks_sit_one_unit_apart_whatever_their_width() {
+        // A grid 3 blocks wide starts half of 3 cube widths left of the center
+        for (cube_width, near_face) in [(1.0, -0.5), (0.5, 0.25), (2.0, -2.0)] {
+            let mut grid = Grid::new(cube_width, 1, 3, 1);
+            let material = Arc::new(Material::black());
+            grid.add_cube(1, 0, 0, material);
+            let y = grid.min().y + cube_width / 2.0;
+            let z = grid.min().z + cube_width / 2.0;
+            let hit = grid.ray_intersect(&Vec3::new(-10.0, y, z), &Vec3::new(1.0, 0.0, 0.0));
+            assert!(hit.is_intersecting, "cube_width {}", cube_width);
+            assert_eq!(hit.point.x, near_face, "cube_width {}", cube_width);
+            let back = grid.ray_intersect(&Vec3::new(10.0, y, z), &Vec3::new(-1.0, 0.0, 0.0));
+            assert_eq!(back.point.x, near_face + cube_width, "cube_width {}", cube_width);
+        }
+    }
+
+    // A small grid with about a third of the cells filled, every block with its own material
+    fn random_grid(rng: &mut Rng, cube_width: f32) -> (Grid, Vec<Arc<Material>>) {
+        let (width, height, depth) = (5, 4, 6);
+        let mut grid = Grid::new(cube_width, height, width, depth);
+        let materials = materials(width * height * depth);
+        for (index, material) in materials.iter().enumerate() {
+            if rng.next_f32() < 0.35 {
+                grid.add_cube(index % width, index / (width * depth), (index / width) % depth, Arc::clone(material));
+            }
+        }
+        (grid, materials)
+    }
+
+    // Every block tested in placement order, keeping the nearest hit
+    fn brute_force<'a>(grid: &'a Grid, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'a> {
+        let mut cells: Vec<&Cell> = grid.cells.iter().flatten().collect();
+        cells.sort_by_key(|cell| cell.order);
+        let mut nearest = Intersect::empty();
+        for cell in cells {
+            let intersect = cell.cube.ray_intersect(ray_origin, ray_direction);
+            if intersect.is_intersecting && intersect.distance < nearest.distance {
+                nearest = intersect;
+            }
+        }
+        nearest
+    }
+
+    fn assert_same_hit(grid: &Grid, ray_origin: &Vec3, ray_direction: &Vec3) {
+        let walked = grid.ray_intersect(ray_origin, ray_direction);
+        let expected = brute_force(grid, ray_origin, ray_direction);
+        let ray = format!("ray from {:?} along {:?}", ray_origin.as_slice(), ray_direction.as_slice());
+        assert_eq!(walked.is_intersecting, expected.is_intersecting, "{}", ray);
+        if expected.is_intersecting {
+            assert!(std::ptr::eq(walked.material, expected.material), "different block for {}", ray);
+            assert_eq!(walked.distance, expected.distance, "{}", ray);
+            assert_eq!(walked.normal, expected.normal, "{}", ray);
+        }
+    }
+
+    fn random_direction(rng: &mut Rng) -> Vec3 {
+        loop {
+            let direction = Vec3::new(rng.next_f32() * 2.0 - 1.0, rng.next_f32() * 2.0 - 1.0, rng.next_f32() * 2.0 - 1.0);
+            if direction.norm() > 0.1 {
+                return direction.normalize();
+            }
+        }
+    }
+
+    // Any point in or around the grid, or with `snap` one on the corners between cells
+    fn random_origin(rng: &mut Rng, grid: &Grid, snap: bool) -> Vec3 {
+        let size = grid.max() - grid.min();
+        let mut origin = Vec3::zeros();
+        for axis in 0..3 {
+            let position = (rng.next_f32() * 2.0 - 0.5) * size[axis];
+            let position = if snap { (position / BLOCK_SPACING).round() * BLOCK_SPACING } else { position };
+            origin[axis] = grid.min()[axis] + position;
+        }
+        origin
+    }
+
+    #[test]
+    fn grid_walk_matches_brute_force_on_random_rays() {
+        let mut rng = Rng::new(3, 0);
+        for cube_width in [1.0, 0.5, 1.5, 2.0] {
+            for _ in 0..20 {
+                let (grid, _materials) = random_grid(&mut rng, cube_width);
+                for _ in 0..500 {
+                    let origin = random_origin(&mut rng, &grid, false);
+                    assert_same_hit(&grid, &origin, &random_direction(&mut rng));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_walk_matches_brute_force_along_cell_planes() {
+        let mut rng = Rng::new(5, 0);
+        for index in 0..30 {
+            let (grid, _materials) = random_grid(&mut rng, [1.0, 0.5, 2.0][index % 3]);
+            for _ in 0..300 {
+                // Starting on cell corners and leaving some axes out, so the ray runs along the
+                // planes and edges between cells
+                let origin = random_origin(&mut rng, &grid, true);
+                let mut direction = random_direction(&mut rng);
+                for axis in 0..3 {
+                    if rng.next_f32() < 0.4 {
+                        direction[axis] = 0.0;
+                    }
+                }
+                if direction == Vec3::zeros() {
+                    continue;
+                }
+                assert_same_hit(&grid, &origin, &direction.normalize());
+            }
+        }
+    }
+
+    #[test]
+    fn grid_walk_matches_brute_force_on_ties() {
+        let mut rng = Rng::new(7, 0);
+        // Diagonals from cell corners pass exactly through edges and corners, where blocks
+        // are hit at the same distance and the one placed first must win
+        let diagonals = [
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, -1.0, 1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, -1.0, 1.0),
+            Vec3::new(-1.0, -1.0, -1.0),
+        ];
+        for _ in 0..20 {
+            let (grid, _materials) = random_grid(&mut rng, 1.0);
+            for _ in 0..100 {
+                let origin = random_origin(&mut rng, &grid, true);
+                for direction in &diagonals {
+                    assert_same_hit(&grid, &origin, direction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_hit_what_the_whole_grid_hits() {
+        use crate::internal::bvh::Bvh;
+
+        let (width, height, depth) = (10, 9, 11);
+        let materials = materials(width * height * depth);
+        let mut rng = Rng::new(9, 0);
+        for cube_width in [1.0, 0.5, 2.0] {
+            let mut whole = Grid::new(cube_width, height, width, depth);
+            let mut split = Grid::new(cube_width, height, width, depth);
+            for (index, material) in materials.iter().enumerate() {
+                if rng.next_f32() < 0.2 {
+                    let (x, y, z) = (index % width, index / (width * depth), (index / width) % depth);
+                    whole.add_cube(x, y, z, Arc::clone(material));
+                    split.add_cube(x, y, z, Arc::clone(material));
+                }
+            }
+            let chunks: Vec<Box<dyn Object + Sync>> = split
+                .into_chunks(4)
+                .into_iter()
+                .map(|chunk| Box::new(chunk) as Box<dyn Object + Sync>)
+                .collect();
+            assert!(chunks.len() > 1 && chunks.len() <= 27);
+            let chunks = Bvh::new(chunks);
+
+            for snap in [false, true] {
+                for _ in 0..3000 {
+                    // From cell corners along diagonals, so rays pass through edges between chunks and tie
+                    let origin = random_origin(&mut rng, &whole, snap);
+                    let direction = if snap {
+                        let mut sign = || if rng.next_f32() < 0.5 { -1.0 } else { 1.0 };
+                        Vec3::new(sign(), sign(), sign())
+                    } else {
+                        random_direction(&mut rng)
+                    };
+                    let expected = whole.ray_intersect(&origin, &direction);
+                    let hit = chunks.ray_intersect(&origin, &direction);
+                    assert_eq!(hit.is_intersecting, expected.is_intersecting);
+                    if expected.is_intersecting {
+                        assert!(std::ptr::eq(hit.material, expected.material), "cube_width {}", cube_width);
+                        assert_eq!(hit.distance, expected.distance);
+                    }
+                }
+            }
+        }
+    }
+}
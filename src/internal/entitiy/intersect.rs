@@ -1,10 +1,19 @@
 use nalgebra_glm::Vec3;
 use super::material::Material;
-use std::f32::INFINITY;
-use super::color::Color;
+use super::color::HdrColor;
 use once_cell::sync::Lazy;
 
-static BLACK_MATERIAL: Lazy<Material> = Lazy::new(|| Material::black());
+static BLACK_MATERIAL: Lazy<Material> = Lazy::new(Material::black);
+
+// Where on a block's texture a hit landed, kept so the texture can be filtered once the
+// size of the ray's footprint is known
+#[derive(Debug, Clone, Copy)]
+pub struct TexCoords {
+    pub face: usize, // face index, top, bottom, north, south, east, west
+    pub u: f32,
+    pub v: f32,
+    pub size: f32,   // width of the face in world units
+}
 
 #[derive(Debug, Clone, Copy)]
 #[allow(dead_code)]
@@ -14,11 +23,14 @@ pub struct Intersect<'a> {
     pub distance: f32,          // distance of the ray
     pub is_intersecting: bool,  // true if hit an object
     pub material: &'a Material, // material of the surface hit
-    pub color: Color            // the actual color hit on the surface
+    pub color: HdrColor,        // the actual color hit on the surface, linear
+    pub alpha: f32,             // coverage of the texel hit, below 1 on alpha blended blocks
+    pub tex_coords: Option<TexCoords>, // on textured blocks
+    pub order: usize,           // when the block hit was placed, the first one wins hits at the same distance
 }
 
 impl<'a> Intersect<'a> {
-    pub fn new(point: Vec3, normal: Vec3, distance: f32, material: &'a Material, color: Color) -> Self {
+    pub fn new(point: Vec3, normal: Vec3, distance: f32, material: &'a Material, color: HdrColor) -> Self {
         Intersect {
             point,
             normal,
@@ -26,6 +38,9 @@ impl<'a> Intersect<'a> {
             is_intersecting: true,
             material,
             color,
+            alpha: 1.0,
+            tex_coords: None,
+            order: 0,
         }
     }
 
@@ -33,10 +48,18 @@ impl<'a> Intersect<'a> {
         Intersect {
             point: Vec3::zeros(),
             normal: Vec3::zeros(),
-            distance: INFINITY,
+            distance: f32::INFINITY,
             is_intersecting: false,
             material: &BLACK_MATERIAL,
-            color: Color::new(0, 0, 0),
+            color: HdrColor::black(),
+            alpha: 1.0,
+            tex_coords: None,
+            order: 0,
         }
     }
+
+    // A hit nearer than `other`, or as near and on a block placed before it
+    pub fn is_closer_than(&self, other: &Intersect) -> bool {
+        self.is_intersecting && (self.distance < other.distance || (self.distance == other.distance && self.order < other.order))
+    }
 }
@@ -1,51 +1,254 @@
 use nalgebra_glm::Vec3;
-use super::color::Color;
+use super::color::HdrColor;
+use super::texture::MOON;
 use std::f32::consts::PI;
+use crate::internal::sampling::Rng;
+use crate::internal::sky::{smoothstep, star_field, sun_direction, sun_transmittance, Sky, SunSettings};
+
+// How the light of a source fades with distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Falloff {
+    None,          // same strength everywhere, like the sun
+    InverseSquare, // physically based 1 / d²
+    Polynomial { constant: f32, linear: f32, quadratic: f32 }, // 1 / (c + l·d + q·d²)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub falloff: Falloff,
+    pub range: Option<f32>, // no light at all past this distance
+}
+
+impl Attenuation {
+    pub const NONE: Attenuation = Attenuation { falloff: Falloff::None, range: None };
+
+    // Multiplier for the light's intensity at `distance` from it
+    pub fn factor(&self, distance: f32) -> f32 {
+        let falloff = match self.falloff {
+            Falloff::None => 1.0,
+            Falloff::InverseSquare => 1.0 / (distance * distance).max(1e-4),
+            Falloff::Polynomial { constant, linear, quadratic } => {
+                1.0 / (constant + linear * distance + quadratic * distance * distance).max(1e-4)
+            }
+        };
+        match self.range {
+            // Fade to zero near the range instead of cutting off, (1 - (d/r)^4)^2
+            Some(range) => {
+                let ratio = (distance / range).powi(4);
+                falloff * (1.0 - ratio).clamp(0.0, 1.0).powi(2)
+            }
+            None => falloff,
+        }
+    }
+}
 
 pub trait Light : Sync {
-   fn get_position(&self) -> Vec3 ;
-   fn get_color(&self) -> Color;
+   fn get_color(&self) -> HdrColor;
    fn get_intensity(&self) -> f32;
+
+   // Unit direction from `point` towards the light and the distance to it,
+   // infinite for lights that have no position
+   fn direction_from(&self, point: &Vec3) -> (Vec3, f32);
+
+   // How the light fades with distance, every light type picks one
+   fn attenuation(&self) -> Attenuation {
+       Attenuation::NONE
+   }
+
+   // Share of the light sent along `direction` (pointing away from the light),
+   // 1 for lights that shine equally everywhere
+   fn cone_factor(&self, _direction: &Vec3) -> f32 {
+       1.0
+   }
+
+   // Direction and distance to a point on the light to aim a shadow ray at from `point`.
+   // Lights without a size always give their center and cast hard shadows.
+   fn sample_direction(&self, point: &Vec3, _rng: &mut Rng) -> (Vec3, f32) {
+       self.direction_from(point)
+   }
+
+   // Whether the light has a size, so its shadows have a penumbra worth several rays
+   fn is_soft(&self) -> bool {
+       false
+   }
 }
 
 pub struct PointLight {
     pub position: Vec3,
-    pub color: Color,
+    pub color: HdrColor,
     pub intensity: f32,
+    pub radius: f32, // size of the bulb, 0 for hard shadows
+    pub attenuation: Attenuation,
 }
 
 impl PointLight {
-    pub fn new(position: Vec3, color: Color, intensity: f32) -> Self {
+    pub fn new(position: Vec3, color: HdrColor, intensity: f32, radius: f32, attenuation: Attenuation) -> Self {
         PointLight {
             position,
             color,
             intensity,
+            radius,
+            attenuation,
+        }
+    }
+}
+
+// A point light that only shines inside a cone, like a lantern hood or a beam through a window.
+// Full strength inside `inner_angle`, fading smoothly to nothing at `outer_angle`.
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,  // unit axis of the cone, pointing away from the light
+    pub color: HdrColor,
+    pub intensity: f32,
+    pub inner_angle: f32, // half angles from the axis, in radians
+    pub outer_angle: f32,
+    pub radius: f32,      // size of the bulb, 0 for hard shadows
+    pub attenuation: Attenuation,
+}
+
+// Parallel rays from far away, the light has a direction but no position
+pub struct DirectionalLight {
+    pub direction: Vec3,       // unit direction the light travels in
+    pub color: HdrColor,
+    pub intensity: f32,
+    pub angular_diameter: f32, // apparent size of the source in radians, 0 for hard shadows
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, color: HdrColor, intensity: f32, angular_diameter: f32) -> Self {
+        DirectionalLight {
+            direction: direction.normalize(),
+            color,
+            intensity,
+            angular_diameter,
         }
     }
 }
 
-const DAY_START: f32 = 0.0;
-const DAY_MID: f32 = PI / 3.0;
-const DAY_END: f32 = 2.0 * PI / 3.0;
-const NIGHT_START: f32 = 3.0 * PI / 2.0;
+// Direction towards a random point of a spherical bulb of `radius` at `position`
+fn sample_bulb(position: &Vec3, radius: f32, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+    let target = if radius > 0.0 {
+        sample_disk(position, &(point - position).normalize(), radius, rng)
+    } else {
+        *position
+    };
+    direction_to(point, &target)
+}
+
+fn direction_to(point: &Vec3, target: &Vec3) -> (Vec3, f32) {
+    let to_target = target - point;
+    let distance = to_target.magnitude();
+    (to_target / distance, distance)
+}
+
+// Uniform point on a disk of `radius` around `center` that faces along `normal`.
+// A spherical light looks like such a disk from any point that sees it.
+fn sample_disk(center: &Vec3, normal: &Vec3, radius: f32, rng: &mut Rng) -> Vec3 {
+    let helper = if normal.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    let distance = radius * rng.next_f32().sqrt();
+    let angle = 2.0 * PI * rng.next_f32();
+    center + (tangent * angle.cos() + bitangent * angle.sin()) * distance
+}
 
+const SUN_ANGULAR_DIAMETER: f32 = 0.0093; // the real sun, the smallest disk drawn in the sky
+const SUN_DISK_BRIGHTNESS: f32 = 20.0;
+const MOON_BRIGHTNESS: f32 = 0.15;      // full moon intensity, as a share of the sun's peak
+const MOON_TINT: HdrColor = HdrColor { r: 0.55, g: 0.65, b: 1.0 };
+const MOON_DISC_DIAMETER: f32 = 0.08;   // drawn larger than life, like in Minecraft
+const MOON_DISC_BRIGHTNESS: f32 = 1.5;
+const MOONLIT_SKY: HdrColor = HdrColor { r: 0.02, g: 0.03, b: 0.06 };
+const EARTHSHINE: f32 = 0.04;           // the dark side of the moon is never quite black
+
+// The sun, moving over the sky with the time of day. Its direction comes from the date and
+// latitude, its color and intensity from how much light makes it through the atmosphere.
 pub struct DayLight {
-    pub position: Vec3,
+    pub position: Vec3, // where the sun is, `radius` away from `center`
     pub center: Vec3,
     pub radius: f32,
-    pub day_angle: f32,
-    pub color: Color,
+    pub day_angle: f32, // time of day, 0 at 6:00 and a whole day every 2*PI
+    pub sun: SunSettings,
+    pub peak_intensity: f32, // intensity with the sun straight overhead
+    pub color: HdrColor,
     pub intensity: f32,
+    pub angular_diameter: f32, // apparent size of the sun in radians, 0 for hard shadows
+    pub sky: Sky,
+    pub moon: Moon,
+}
+
+// The moon, always opposite the sun. It lights the night with a cool tint, as much as its phase allows.
+pub struct Moon {
+    pub position: Vec3,
+    pub direction: Vec3, // from the center of the scene
+    pub phase: f32,      // 0 new moon, 0.5 full moon, 1 new again
+    pub color: HdrColor,
+    pub intensity: f32,
+    pub angular_diameter: f32, // for its shadows, 0 for hard ones
+}
+
+impl Moon {
+    pub fn new(phase: f32, angular_diameter: f32) -> Self {
+        Moon {
+            position: Vec3::zeros(),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            phase: phase.rem_euclid(1.0),
+            color: MOON_TINT,
+            intensity: 0.0,
+            angular_diameter,
+        }
+    }
+
+    // Share of the disc the sun lights
+    pub fn illuminated_fraction(&self) -> f32 {
+        (1.0 - (2.0 * PI * self.phase).cos()) / 2.0
+    }
+
+    fn follow_sun(&mut self, center: &Vec3, radius: f32, sun_direction: &Vec3, full_intensity: f32) {
+        self.direction = -sun_direction;
+        self.position = center + self.direction * radius;
+        let above_horizon = smoothstep(-0.01, 0.05, self.direction.y);
+        self.intensity = full_intensity * self.illuminated_fraction() * above_horizon;
+    }
+
+    // The textured disc when `direction` looks at the moon. Its phase is drawn as a terminator
+    // across the disc, lit from the side the sun would be on.
+    fn disc_color(&self, direction: &Vec3) -> Option<HdrColor> {
+        let disc_radius = MOON_DISC_DIAMETER / 2.0;
+        if self.direction.y < -disc_radius || direction.dot(&self.direction) < disc_radius.cos() {
+            return None;
+        }
+
+        let helper = if self.direction.y.abs() > 0.99 { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+        let right = self.direction.cross(&helper).normalize();
+        let up = right.cross(&self.direction);
+        let scale = disc_radius.sin();
+        let (u, v) = (direction.dot(&right) / scale, direction.dot(&up) / scale);
+        let depth = 1.0 - u * u - v * v;
+        if depth <= 0.0 {
+            return None;
+        }
+
+        let x = (((u + 1.0) / 2.0) * MOON.width as f32) as usize;
+        let y = (((1.0 - v) / 2.0) * MOON.height as f32) as usize;
+        let albedo = HdrColor::from(MOON.get_color(x.min(MOON.width - 1), y.min(MOON.height - 1)));
+
+        // Light coming from behind the moon at new moon and from the viewer at full moon
+        let angle = 2.0 * PI * self.phase;
+        let lit = u * angle.sin() + depth.sqrt() * -angle.cos();
+        let shade = EARTHSHINE + (1.0 - EARTHSHINE) * smoothstep(-0.05, 0.1, lit);
+        Some(albedo * (shade * MOON_DISC_BRIGHTNESS))
+    }
 }
 
 
 pub struct AmbientLight{
-    pub color: Color,
+    pub color: HdrColor,
     pub intensity: f32,
 }
 
 impl AmbientLight {
-    pub fn new(color: Color, intensity: f32) -> Self {
+    pub fn new(color: HdrColor, intensity: f32) -> Self {
         AmbientLight {
             color,
             intensity,
@@ -53,116 +256,405 @@ impl AmbientLight {
     }
 }
 impl Light for PointLight {
-    fn get_position(&self) -> Vec3 {
-        self.position
+    fn get_color(&self) -> HdrColor {
+        self.color
+    }
+
+    fn get_intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    fn direction_from(&self, point: &Vec3) -> (Vec3, f32) {
+        direction_to(point, &self.position)
+    }
+
+    fn attenuation(&self) -> Attenuation {
+        self.attenuation
     }
 
-    fn get_color(&self) -> Color {
+    fn sample_direction(&self, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+        sample_bulb(&self.position, self.radius, point, rng)
+    }
+
+    fn is_soft(&self) -> bool {
+        self.radius > 0.0
+    }
+}
+
+impl Light for SpotLight {
+    fn get_color(&self) -> HdrColor {
         self.color
     }
 
     fn get_intensity(&self) -> f32 {
         self.intensity
     }
-}
 
-impl Light for AmbientLight {
-    fn get_position(&self) -> Vec3 {
-        // Ambient light does not have a position, so we can return a zero vector or a default.
-        Vec3::new(0.0, 0.0, 0.0)
+    fn direction_from(&self, point: &Vec3) -> (Vec3, f32) {
+        direction_to(point, &self.position)
+    }
+
+    fn attenuation(&self) -> Attenuation {
+        self.attenuation
+    }
+
+    // Smoothstep between the cosines of the outer and inner angles
+    fn cone_factor(&self, direction: &Vec3) -> f32 {
+        let cos_angle = self.direction.dot(direction);
+        let cos_inner = self.inner_angle.cos();
+        let cos_outer = self.outer_angle.cos();
+        if cos_inner - cos_outer <= f32::EPSILON {
+            return if cos_angle >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
     }
 
-    fn get_color(&self) -> Color {
+    fn sample_direction(&self, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+        sample_bulb(&self.position, self.radius, point, rng)
+    }
+
+    fn is_soft(&self) -> bool {
+        self.radius > 0.0
+    }
+}
+
+impl Light for DirectionalLight {
+    fn get_color(&self) -> HdrColor {
         self.color
     }
 
     fn get_intensity(&self) -> f32 {
         self.intensity
     }
+
+    fn direction_from(&self, _point: &Vec3) -> (Vec3, f32) {
+        (-self.direction, f32::INFINITY)
+    }
+
+    // A direction inside the cone the source covers, through a disk one unit away
+    fn sample_direction(&self, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+        if self.angular_diameter <= 0.0 {
+            return self.direction_from(point);
+        }
+        let radius = (self.angular_diameter / 2.0).tan();
+        let target = sample_disk(&-self.direction, &self.direction, radius, rng);
+        (target.normalize(), f32::INFINITY)
+    }
+
+    fn is_soft(&self) -> bool {
+        self.angular_diameter > 0.0
+    }
 }
 
 impl Light for DayLight {
-    fn get_position(&self) -> Vec3 {
-        self.position
+    fn get_color(&self) -> HdrColor {
+        self.color
+    }
+
+    fn get_intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    fn direction_from(&self, point: &Vec3) -> (Vec3, f32) {
+        direction_to(point, &self.position)
+    }
+
+    fn sample_direction(&self, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+        sample_sky_disk(&self.position, self.angular_diameter, point, rng)
     }
 
-    fn get_color(&self) -> Color {
+    fn is_soft(&self) -> bool {
+        self.angular_diameter > 0.0
+    }
+}
+
+impl Light for Moon {
+    fn get_color(&self) -> HdrColor {
         self.color
     }
 
     fn get_intensity(&self) -> f32 {
         self.intensity
     }
+
+    fn direction_from(&self, point: &Vec3) -> (Vec3, f32) {
+        direction_to(point, &self.position)
+    }
+
+    fn sample_direction(&self, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+        sample_sky_disk(&self.position, self.angular_diameter, point, rng)
+    }
+
+    fn is_soft(&self) -> bool {
+        self.angular_diameter > 0.0
+    }
+}
+
+// The sun and the moon cover the same angle from everywhere, so their disk grows with the distance to them
+fn sample_sky_disk(position: &Vec3, angular_diameter: f32, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+    if angular_diameter <= 0.0 {
+        return direction_to(point, position);
+    }
+    let to_point = point - position;
+    let distance = to_point.magnitude();
+    let radius = distance * (angular_diameter / 2.0).tan();
+    direction_to(point, &sample_disk(position, &(to_point / distance), radius, rng))
 }
 
 impl DayLight {
-    pub fn new(position: Vec3, center: Vec3, radius: f32, day_angle:f32, color: Color, intensity: f32) -> Self {
-        DayLight {
-            position,
+    pub fn new(center: Vec3, radius: f32, day_angle: f32, sun: SunSettings, moon_phase: f32, peak_intensity: f32, angular_diameter: f32) -> Self {
+        let mut day_light = DayLight {
+            position: center,
             center,
             radius,
-            day_angle,
-            color,
-            intensity,
-        }
+            day_angle: 0.0,
+            sun,
+            peak_intensity,
+            color: HdrColor::black(),
+            intensity: 0.0,
+            angular_diameter,
+            sky: Sky::new(&Vec3::new(0.0, 1.0, 0.0), sun.turbidity),
+            moon: Moon::new(moon_phase, angular_diameter),
+        };
+        day_light.translate_day_light(day_angle);
+        day_light
     }
+
     pub fn translate_day_light(&mut self, delta_angle: f32) {
-        // Update day_angle within the range [0, 2*PI] to keep it bounded to one day cycle
-        self.day_angle = (self.day_angle + delta_angle) % (2.0 * PI);
-
-        // Calculate the new position on the circular path around `center`
-        self.position.x = self.center.x + self.radius * self.day_angle.cos();
-        self.position.y = self.center.y + self.radius * self.day_angle.sin();
-
-        // Adjust intensity based on day_angle
-        if self.day_angle >= DAY_START && self.day_angle <= DAY_END {
-            let ratio = if self.day_angle < DAY_MID {
-                self.day_angle / DAY_MID
-            } else {
-                1.0 - ((self.day_angle - DAY_MID) / (DAY_END - DAY_MID))
-            };
-            self.intensity = ratio; // Intensity varies from 0 to 1 between DAY_START and DAY_END
-
-        } else if self.day_angle >= DAY_END && self.day_angle <= NIGHT_START {
-            // Dimming toward night
-            self.intensity = 0.0;
-        }
-    }
-
-    fn interpolate_color(&self, start: (u8, u8, u8), end: (u8, u8, u8), ratio: f32) -> (u8, u8, u8) {
-        (
-            (start.0 as f32 * (1.0 - ratio) + end.0 as f32 * ratio) as u8,
-            (start.1 as f32 * (1.0 - ratio) + end.1 as f32 * ratio) as u8,
-            (start.2 as f32 * (1.0 - ratio) + end.2 as f32 * ratio) as u8,
-        )
-    }
-
-    pub fn calculate_background_color(&self, day_angle: f32) -> Color {
-        
-        const DARK_NIGHT : (u8, u8, u8) = (0, 0, 50);
-        const DAWN : (u8, u8, u8) = (237, 135, 88);
-        const DAY : (u8, u8, u8) = (135, 206, 250);
-
-        let (r, g, b) = if day_angle >= DAY_START && day_angle < DAY_MID {
-            let ratio = day_angle / DAY_MID;
-            if ratio < 0.5 {
-                self.interpolate_color(DARK_NIGHT, DAWN, ratio * 2.0)
-            } else {
-                self.interpolate_color(DAWN, DAY, (ratio - 0.5) * 2.0)
-            }
-        } else if day_angle >= DAY_MID && day_angle < DAY_END {
-            (135, 206, 250)  // Midday color
-        } else if day_angle >= DAY_END && day_angle < NIGHT_START {
-            let ratio = (day_angle - DAY_END) / (NIGHT_START - DAY_END);
-            if ratio < 0.5 {
-                self.interpolate_color(DAY, DAWN, ratio * 2.0)
-            } else {
-                self.interpolate_color(DAWN, DARK_NIGHT, (ratio - 0.5) * 2.0)
-            }
+        // Keep day_angle within [0, 2*PI) so it stays a time of day
+        self.day_angle = (self.day_angle + delta_angle).rem_euclid(2.0 * PI);
+
+        let direction = sun_direction(&self.sun, self.hours());
+        self.position = self.center + direction * self.radius;
+        self.sky = Sky::new(&direction, self.sun.turbidity);
+
+        // The color of the sunlight comes out of the atmosphere, its brightest channel sets the intensity
+        let transmittance = sun_transmittance(&direction, self.sun.turbidity);
+        let brightest = transmittance.r.max(transmittance.g).max(transmittance.b);
+        if brightest > 0.0 {
+            self.color = transmittance * (1.0 / brightest);
+        }
+        self.intensity = self.peak_intensity * brightest;
+
+        self.moon.follow_sun(&self.center, self.radius, &direction, self.peak_intensity * MOON_BRIGHTNESS);
+    }
+
+    // Local solar time the day angle stands for, 0 is sunrise at 6:00
+    pub fn hours(&self) -> f32 {
+        (6.0 + self.day_angle * 12.0 / PI) % 24.0
+    }
+
+    // What a ray that leaves the scene towards `direction` sees: the sky with the sun's disk,
+    // and at night the moon in front of the stars
+    pub fn background_color(&self, direction: &Vec3) -> HdrColor {
+        let sun_direction = (self.position - self.center).normalize();
+        let sun_radius = self.angular_diameter.max(SUN_ANGULAR_DIAMETER) / 2.0;
+        if direction.dot(&sun_direction) > sun_radius.cos() {
+            let sky = self.sky.radiance(direction) + MOONLIT_SKY * self.moon.intensity;
+            return sky + self.color * (self.intensity * SUN_DISK_BRIGHTNESS);
+        }
+        self.sky_color(direction)
+    }
+
+    // The background without the sun's disk, for rays whose sunlight is already counted as direct light
+    pub fn sky_color(&self, direction: &Vec3) -> HdrColor {
+        let sky = self.sky.radiance(direction) + MOONLIT_SKY * self.moon.intensity;
+        if let Some(moon) = self.moon.disc_color(direction) {
+            return sky + moon;
+        }
+
+        let darkness = self.sky.darkness();
+        if darkness > 0.0 {
+            sky + star_field(direction, self.sun.latitude, self.day_angle) * darkness
+        } else {
+            sky
+        }
+    }
+}
+
+// An emissive block used as a light source, every face of the box glows
+pub struct AreaLight {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub radiance: HdrColor, // light leaving each point of the surface
+}
+
+impl AreaLight {
+    pub fn new(min: Vec3, max: Vec3, radiance: HdrColor) -> Self {
+        AreaLight { min, max, radiance }
+    }
+
+    pub fn area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    // Roughly how much light the block sends to `point` on a surface facing `normal`, its power over
+    // the squared distance, and nothing when the whole block is behind the surface
+    pub fn importance(&self, point: &Vec3, normal: &Vec3) -> f32 {
+        let half_size = (self.max - self.min) * 0.5;
+        let to_center = (self.min + self.max) * 0.5 - point;
+        let reach = normal.x.abs() * half_size.x + normal.y.abs() * half_size.y + normal.z.abs() * half_size.z;
+        if normal.dot(&to_center) + reach <= 0.0 {
+            return 0.0;
+        }
+        // Close up the block fills the view, it can't grow brighter than it does at its own size
+        let distance_squared = to_center.norm_squared().max(half_size.norm_squared());
+        self.radiance.luminance() * self.area() / distance_squared
+    }
+
+    // Uniformly distributed point on the surface of the box, with the face normal there
+    pub fn sample_surface(&self, rng: &mut Rng) -> (Vec3, Vec3) {
+        let size = self.max - self.min;
+        // Faces perpendicular to x, y and z, picked in proportion to their area
+        let areas = [size.y * size.z, size.z * size.x, size.x * size.y];
+        let mut pick = rng.next_f32() * (areas[0] + areas[1] + areas[2]);
+        let mut axis = 0;
+        while axis < 2 && pick >= areas[axis] {
+            pick -= areas[axis];
+            axis += 1;
+        }
+
+        let mut point = self.min + Vec3::new(rng.next_f32() * size.x, rng.next_f32() * size.y, rng.next_f32() * size.z);
+        let mut normal = Vec3::zeros();
+        if rng.next_f32() < 0.5 {
+            point[axis] = self.min[axis];
+            normal[axis] = -1.0;
         } else {
-            (0, 0, 50)  // Night color
+            point[axis] = self.max[axis];
+            normal[axis] = 1.0;
+        }
+        (point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use super::super::cube::Cube;
+    use super::super::material::Material;
+    use super::super::object::Object;
+
+    #[test]
+    fn falloffs_follow_their_formulas() {
+        assert_eq!(Attenuation::NONE.factor(0.5), 1.0);
+        assert_eq!(Attenuation::NONE.factor(1000.0), 1.0);
+
+        let inverse_square = Attenuation { falloff: Falloff::InverseSquare, range: None };
+        assert_eq!(inverse_square.factor(1.0), 1.0);
+        assert!((inverse_square.factor(4.0) - 1.0 / 16.0).abs() < 1e-7);
+        // Capped right at the light instead of going to infinity
+        assert_eq!(inverse_square.factor(0.0), 1e4);
+
+        let polynomial = Attenuation { falloff: Falloff::Polynomial { constant: 1.0, linear: 0.5, quadratic: 0.25 }, range: None };
+        assert!((polynomial.factor(2.0) - 1.0 / 3.0).abs() < 1e-7);
+        assert_eq!(polynomial.factor(0.0), 1.0);
+    }
+
+    #[test]
+    fn range_fades_the_light_out_smoothly() {
+        let ranged = |falloff| Attenuation { falloff, range: Some(4.0) };
+        let flat = ranged(Falloff::None);
+        assert_eq!(flat.factor(0.0), 1.0);
+        // (1 - (d / r)^4)^2 at half the range
+        assert!((flat.factor(2.0) - (15.0f32 / 16.0).powi(2)).abs() < 1e-6);
+        assert_eq!(flat.factor(4.0), 0.0);
+        assert_eq!(flat.factor(10.0), 0.0);
+        let mut last = 1.0;
+        for step in 1..=40 {
+            let factor = flat.factor(step as f32 * 0.1);
+            assert!(factor <= last);
+            last = factor;
+        }
+        // The window multiplies the falloff
+        let inverse_square = ranged(Falloff::InverseSquare);
+        assert!((inverse_square.factor(2.0) - flat.factor(2.0) / 4.0).abs() < 1e-7);
+    }
+
+    fn bulb(radius: f32) -> PointLight {
+        PointLight::new(Vec3::new(1.0, 5.0, 0.5), HdrColor::new(1.0, 1.0, 1.0), 1.0, radius, Attenuation::NONE)
+    }
+
+    #[test]
+    fn lights_without_a_size_aim_at_their_center() {
+        let mut rng = Rng::new(1, 0);
+        let point = Vec3::new(0.2, -1.0, 0.3);
+        let light = bulb(0.0);
+        assert!(!light.is_soft());
+        assert_eq!(light.sample_direction(&point, &mut rng), light.direction_from(&point));
+        let sun = DirectionalLight::new(Vec3::new(-1.0, -2.0, -1.0), HdrColor::new(1.0, 1.0, 1.0), 1.0, 0.0);
+        assert_eq!(sun.sample_direction(&point, &mut rng), sun.direction_from(&point));
+    }
+
+    #[test]
+    fn bulb_samples_cover_the_disk_facing_the_point() {
+        let mut rng = Rng::new(2, 0);
+        let light = bulb(0.5);
+        let point = Vec3::new(1.0, -1.0, 0.5);
+        let count = 4000;
+        let (mut inner, mut mean) = (0, Vec3::zeros());
+        for _ in 0..count {
+            let (direction, distance) = light.sample_direction(&point, &mut rng);
+            let offset = point + direction * distance - light.position;
+            assert!(offset.y.abs() < 1e-4, "{} is off the disk", offset);
+            assert!(offset.magnitude() <= 0.5 + 1e-4);
+            inner += (offset.magnitude() < 0.25) as usize;
+            mean += offset / count as f32;
+        }
+        // Uniform over the area: a quarter of it is within half the radius
+        assert!((inner as f32 / count as f32 - 0.25).abs() < 0.03);
+        assert!(mean.magnitude() < 0.03);
+    }
+
+    #[test]
+    fn sun_samples_stay_inside_its_disk() {
+        let mut rng = Rng::new(3, 0);
+        let sun = DirectionalLight::new(Vec3::new(-1.0, -2.0, -1.0), HdrColor::new(1.0, 1.0, 1.0), 1.0, 0.1);
+        let mut widest: f32 = 0.0;
+        for _ in 0..2000 {
+            let (direction, distance) = sun.sample_direction(&Vec3::zeros(), &mut rng);
+            assert_eq!(distance, f32::INFINITY);
+            widest = widest.max(direction.dot(&-sun.direction).min(1.0).acos());
+        }
+        assert!(widest <= 0.05 + 1e-4 && widest > 0.045, "{}", widest);
+
+        // The sun in the sky covers the same angle from near and far
+        for point in [Vec3::zeros(), Vec3::new(30.0, -20.0, 5.0)] {
+            let position = Vec3::new(0.0, 100.0, 0.0);
+            let center = (position - point).normalize();
+            for _ in 0..500 {
+                let (direction, _) = sample_sky_disk(&position, 0.1, &point, &mut rng);
+                assert!(direction.dot(&center).min(1.0).acos() <= 0.05 + 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn block_edge_under_a_bulb_casts_a_half_shadow() {
+        // The bulb sits right above the east edge of a block, the point right under it: every
+        // shadow ray to the west half of the bulb crosses the block
+        let mut rng = Rng::new(4, 0);
+        let block = Cube::new(Vec3::zeros(), Vec3::new(1.0, 1.0, 1.0), Arc::new(Material::black()));
+        let point = Vec3::new(1.0, -1.0, 0.5);
+        let lit_share = |light: &PointLight, rng: &mut Rng| {
+            let count = 4000;
+            let lit = (0..count)
+                .filter(|_| {
+                    let (direction, distance) = light.sample_direction(&point, rng);
+                    let hit = block.ray_intersect(&point, &direction);
+                    !hit.is_intersecting || hit.distance > distance
+                })
+                .count();
+            lit as f32 / count as f32
         };
+        assert!((lit_share(&bulb(0.5), &mut rng) - 0.5).abs() < 0.03);
 
-        Color::new(r, g, b)
+        // Moved a bulb radius to the east it lights the point fully, to the west not at all
+        let east = PointLight { position: Vec3::new(1.6, 5.0, 0.5), ..bulb(0.5) };
+        assert_eq!(lit_share(&east, &mut rng), 1.0);
+        let west = PointLight { position: Vec3::new(0.4, 5.0, 0.5), ..bulb(0.5) };
+        assert_eq!(lit_share(&west, &mut rng), 0.0);
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,8 @@
-use once_cell::sync::Lazy;
-
-use super::color::Color;
+use super::color::{Color, HdrColor};
 use std::sync::Arc;
-use super::texture::Texture;
+use super::intersect::TexCoords;
+use super::model::BlockShape;
+use super::texture::{FaceTextures, TextureFilter};
 
 // use once_cell::sync::Lazy;
 // use std::sync::Arc;
@@ -12,7 +12,15 @@ use super::texture::Texture;
 #[derive(Debug, Clone)]
 pub enum Diffuse {
     Color(Color),
-    Texture(Arc<Texture>)
+    Texture(Box<FaceTextures>)
+}
+
+// How the alpha channel of a texture is used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,                    // alpha is ignored
+    Cutout { threshold: f32 }, // texels below the threshold are holes, the rest is solid, like leaves
+    Blend,                     // texels are mixed with whatever is behind them by their alpha
 }
 
 #[derive(Debug, Clone)]
@@ -22,7 +30,12 @@ pub struct Material {
     pub albedo: [f32; 2],
     pub reflectivity: f32,
     pub transparency : f32,
-    pub refractive_index : f32
+    pub refractive_index : f32,
+    pub absorption: HdrColor,   // Beer-Lambert coefficients per unit of distance inside, black for clear glass
+    pub emission: HdrColor,     // tint of the light the block gives off, black if it doesn't glow
+    pub emission_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub shape: Option<Arc<BlockShape>>, // model elements drawn in place of the full cube
 }
 impl Material {
     pub fn black() -> Material {
@@ -32,7 +45,63 @@ impl Material {
             albedo: [0.0, 0.0],
             reflectivity: 0.0,
             transparency : 0.0,
-            refractive_index: 0.0
+            refractive_index: 0.0,
+            absorption: HdrColor::black(),
+            emission: HdrColor::black(),
+            emission_strength: 0.0,
+            alpha_mode: AlphaMode::Opaque,
+            shape: None,
+        }
+    }
+
+    // Share of the light left after travelling `distance` inside the block, stained glass tints it
+    pub fn transmittance(&self, distance: f32) -> HdrColor {
+        HdrColor::new(
+            (-self.absorption.r * distance).exp(),
+            (-self.absorption.g * distance).exp(),
+            (-self.absorption.b * distance).exp(),
+        )
+    }
+
+    // How much of a texel with `alpha` the surface covers, None where rays go through it
+    pub fn coverage(&self, alpha: f32) -> Option<f32> {
+        match self.alpha_mode {
+            AlphaMode::Opaque => Some(1.0),
+            AlphaMode::Cutout { threshold } => (alpha >= threshold).then_some(1.0),
+            AlphaMode::Blend => (alpha > 0.0).then_some(alpha),
+        }
+    }
+
+    // Blocks that let light past them, through glass, through holes in their texture or around
+    // a shape that doesn't fill the block
+    pub fn lets_light_through(&self) -> bool {
+        self.transparency > 0.0
+            || self.alpha_mode != AlphaMode::Opaque
+            || self.shape.as_ref().is_some_and(|shape| !shape.full_cube)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission_strength > 0.0 && self.emission.luminance() > 0.0
+    }
+
+    // Radiance leaving a point of color `surface_color`, the emission tints the block's own texture
+    pub fn emitted(&self, surface_color: HdrColor) -> HdrColor {
+        surface_color * self.emission * self.emission_strength
+    }
+
+    // Mean surface color, what an emissive block looks like from far away
+    pub fn average_color(&self) -> HdrColor {
+        match &self.diffuse {
+            Diffuse::Color(color) => HdrColor::from(*color),
+            Diffuse::Texture(faces) => faces.average_color(),
+        }
+    }
+
+    // Texture color at a hit seen over `footprint` world units, None on plainly colored blocks
+    pub fn filtered_color(&self, coords: &TexCoords, footprint: f32, filter: TextureFilter) -> Option<HdrColor> {
+        match &self.diffuse {
+            Diffuse::Color(_) => None,
+            Diffuse::Texture(faces) => Some(faces.filtered(coords.face, coords.u, coords.v, footprint / coords.size, filter)),
         }
     }
 }
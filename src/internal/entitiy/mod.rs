@@ -2,8 +2,10 @@ pub mod color;
 pub mod material;
 pub mod light;
 pub mod cube;
+pub mod model;
 pub mod object;
 pub mod sphere;
 pub mod grid;
 pub mod intersect;
 pub mod texture;
+pub mod aabb;
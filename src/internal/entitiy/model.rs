@@ -0,0 +1,212 @@
+use std::sync::Arc;
+use nalgebra_glm::{Mat3, Vec3};
+use super::material::{Diffuse, Material};
+use super::intersect::{Intersect, TexCoords};
+use super::color::HdrColor;
+
+// Minecraft models measure a block in sixteenths
+pub const MODEL_UNITS: f32 = 16.0;
+
+// The boxes a Minecraft block model is made of, like the two steps of a stair or the post of a
+// fence. Blocks whose material has a shape are drawn with it instead of as a full cube.
+#[derive(Debug)]
+pub struct BlockShape {
+    pub elements: Vec<ModelElement>,
+    pub full_cube: bool, // one unrotated box filling the whole block, it hides what is behind like a cube
+}
+
+// One box of a model, in model units. `to_element` and `offset` take a point of the block
+// (0 to 16 on each axis) into the box's own space, undoing the element's and the blockstate's rotations.
+#[derive(Debug)]
+pub struct ModelElement {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub to_element: Mat3,
+    pub offset: Vec3,
+    pub faces: [Option<ElementFace>; 6], // top, bottom, north, south, east, west, missing faces are open
+    pub material: Arc<Material>,         // the block's material with this element's face textures
+}
+
+// The part of the face's texture shown, in fractions of the texture
+#[derive(Debug, Clone, Copy)]
+pub struct ElementFace {
+    pub uv: [f32; 4],   // u and v where the face starts, then where it ends, flipped if the end is smaller
+    pub rotation: u32,  // the texture turned clockwise on the face, 0, 90, 180 or 270 degrees
+}
+
+impl BlockShape {
+    // The nearest element hit by a ray, the block spanning `min` to `max`
+    pub fn ray_intersect(&self, min: &Vec3, max: &Vec3, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'_> {
+        let scale = MODEL_UNITS / (max.x - min.x);
+        let origin = (ray_origin - min) * scale;
+        let direction = ray_direction * scale;
+
+        let mut nearest = Intersect::empty();
+        for element in &self.elements {
+            if let Some(intersect) = element.ray_intersect(&origin, &direction, ray_origin, ray_direction, max.x - min.x) {
+                if intersect.distance < nearest.distance {
+                    nearest = intersect;
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl ModelElement {
+    // Ray in block model units, plus the world ray to place the hit, t is the same in both
+    fn ray_intersect(&self, origin: &Vec3, direction: &Vec3, ray_origin: &Vec3, ray_direction: &Vec3, block_size: f32) -> Option<Intersect<'_>> {
+        let local_origin = self.to_element * (origin - self.offset);
+        let local_direction = self.to_element * direction;
+
+        // Slabs, remembering which axis the ray enters and leaves through
+        let mut t_enter = f32::NEG_INFINITY;
+        let mut t_exit = f32::INFINITY;
+        let mut enter_axis = 0;
+        let mut exit_axis = 0;
+        for axis in 0..3 {
+            let mut t0 = (self.from[axis] - local_origin[axis]) / local_direction[axis];
+            let mut t1 = (self.to[axis] - local_origin[axis]) / local_direction[axis];
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            if t0.is_nan() || t1.is_nan() {
+                return None;
+            }
+            if t0 > t_enter {
+                t_enter = t0;
+                enter_axis = axis;
+            }
+            if t1 < t_exit {
+                t_exit = t1;
+                exit_axis = axis;
+            }
+        }
+        if t_enter > t_exit || t_exit <= 0.0 {
+            return None;
+        }
+
+        // Like a cube, a ray from inside or through an open or see-through face leaves by the far face
+        if t_enter > 0.0 {
+            let outward = -local_direction[enter_axis].signum();
+            if let Some(intersect) = self.surface_at(&local_origin, &local_direction, t_enter, enter_axis, outward, false, ray_origin, ray_direction, block_size) {
+                return Some(intersect);
+            }
+        }
+        let outward = local_direction[exit_axis].signum();
+        self.surface_at(&local_origin, &local_direction, t_exit, exit_axis, outward, true, ray_origin, ray_direction, block_size)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn surface_at(&self, origin: &Vec3, direction: &Vec3, t: f32, axis: usize, outward: f32, far_face: bool, ray_origin: &Vec3, ray_direction: &Vec3, block_size: f32) -> Option<Intersect<'_>> {
+        let face = face_index(axis, outward);
+        let element_face = self.faces[face]?;
+        let point = origin + direction * t;
+        let (u, v) = self.face_uv(&point, face, &element_face);
+
+        let (color, alpha, tex_coords) = match &self.material.diffuse {
+            Diffuse::Color(color) => (HdrColor::from(*color), 1.0, None),
+            Diffuse::Texture(faces) => {
+                let (color, alpha) = faces.sample(face, u, v);
+                (HdrColor::from(color), alpha, Some(TexCoords { face, u, v, size: block_size }))
+            }
+        };
+        let coverage = self.material.coverage(alpha)?;
+
+        // Normals go back to the block with the inverse transpose, rescaled elements aren't rotations
+        let mut local_normal = Vec3::zeros();
+        local_normal[axis] = outward;
+        let normal = (self.to_element.transpose() * local_normal).normalize();
+        let normal = if far_face && self.material.transparency <= 0.0 { -normal } else { normal };
+
+        let world_point = ray_origin + ray_direction * t;
+        let intersect = Intersect::new(world_point, normal, t, &self.material, color);
+        Some(Intersect { alpha: coverage, tex_coords, ..intersect })
+    }
+
+    // Where `point` on face `face` falls in the texture. Across the face `s` and `t` run the way
+    // Minecraft lays textures out by default, so an element without `uv` shows the part of the
+    // texture its position covers.
+    fn face_uv(&self, point: &Vec3, face: usize, element_face: &ElementFace) -> (f32, f32) {
+        let size = self.to - self.from;
+        let across = |axis: usize, reversed: bool| {
+            let fraction = (point[axis] - self.from[axis]) / size[axis];
+            let fraction = if size[axis] > 0.0 { fraction.clamp(0.0, 1.0) } else { 0.0 };
+            if reversed { 1.0 - fraction } else { fraction }
+        };
+        let (s, t) = match face {
+            0 => (across(0, false), across(2, false)), // up
+            1 => (across(0, false), across(2, true)),  // down
+            2 => (across(0, true), across(1, true)),   // north
+            3 => (across(0, false), across(1, true)),  // south
+            4 => (across(2, true), across(1, true)),   // east
+            _ => (across(2, false), across(1, true)),  // west
+        };
+        element_face.at(s, t)
+    }
+}
+
+impl ElementFace {
+    // The texture coordinates at `s`, `t` across the face, turned by the face's rotation
+    pub fn at(&self, s: f32, t: f32) -> (f32, f32) {
+        let (s, t) = match self.rotation {
+            90 => (t, 1.0 - s),
+            180 => (1.0 - s, 1.0 - t),
+            270 => (1.0 - t, s),
+            _ => (s, t),
+        };
+        let [u0, v0, u1, v1] = self.uv;
+        (u0 + (u1 - u0) * s, v0 + (v1 - v0) * t)
+    }
+}
+
+// The face an axis and side stand for, indexed like `Cube::get_face_index`
+pub fn face_index(axis: usize, outward: f32) -> usize {
+    match (axis, outward > 0.0) {
+        (0, true) => 4,
+        (0, false) => 5,
+        (1, true) => 0,
+        (1, false) => 1,
+        (2, true) => 3,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::entitiy::color::Color;
+
+    // A bottom slab, all faces shown, in a flat color
+    fn slab(color: Color) -> BlockShape {
+        let face = ElementFace { uv: [0.0, 0.0, 1.0, 1.0], rotation: 0 };
+        let element = ModelElement {
+            from: Vec3::zeros(),
+            to: Vec3::new(16.0, 8.0, 16.0),
+            to_element: Mat3::identity(),
+            offset: Vec3::zeros(),
+            faces: [Some(face); 6],
+            material: Arc::new(Material { diffuse: Diffuse::Color(color), ..Material::black() }),
+        };
+        BlockShape { elements: vec![element], full_cube: false }
+    }
+
+    #[test]
+    fn flat_colored_elements_show_their_color() {
+        let shape = slab(Color::new(255, 0, 0));
+        let (min, max) = (Vec3::new(2.0, 0.0, 0.0), Vec3::new(4.0, 2.0, 2.0));
+
+        let hit = shape.ray_intersect(&min, &max, &Vec3::new(3.0, 5.0, 1.0), &Vec3::new(0.0, -1.0, 0.0));
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!((hit.point.y - 1.0).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(hit.color, HdrColor::from(Color::new(255, 0, 0)));
+        assert_eq!(hit.alpha, 1.0);
+        assert!(hit.tex_coords.is_none());
+
+        // Over the slab the upper half of the block is empty
+        let miss = shape.ray_intersect(&min, &max, &Vec3::new(0.0, 1.5, 1.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert!(!miss.is_intersecting);
+    }
+}
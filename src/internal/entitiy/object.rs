@@ -1,6 +1,8 @@
 use nalgebra_glm::Vec3;
+use super::aabb::Aabb;
 use super::intersect::Intersect;
 
 pub trait Object : Sync {
-   fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect;
+   fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect<'_>;
+   fn bounding_box(&self) -> Aabb;
 }
@@ -1,8 +1,9 @@
 use once_cell::sync::Lazy;
 use std::sync::Arc;
-use image::{ImageReader, Pixel, DynamicImage, GenericImageView};
+use image::{ImageReader, ImageError, Pixel, DynamicImage, GenericImageView, Rgba, RgbaImage};
 use std::fmt;
-use super::color::Color;
+use std::str::FromStr;
+use super::color::{Color, HdrColor};
 
 pub static BOOK_SHELF: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::new("assets/bookshelf.png")));
 pub static CHEST: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::new("assets/chest.png")));
@@ -22,29 +23,144 @@ pub static GLOWSTONE: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::new("a
 
 pub static GRASS: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::new("assets/grass.png")));
 
+pub static MOON: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::new("assets/moon.png")));
+
+pub static OAK_LEAVES: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::new("assets/oak_leaves.png")));
+
+// Shown wherever a texture couldn't be found, hard to miss
+pub static MISSING: Lazy<Arc<Texture>> = Lazy::new(|| Arc::new(Texture::missing()));
+
+// Looks up one of the bundled textures by the name of its file in `assets/`.
+pub fn builtin(name: &str) -> Option<Arc<Texture>> {
+  let texture = match name {
+    "bookshelf" => &BOOK_SHELF,
+    "chest" => &CHEST,
+    "jukebox" => &JUKEBOX,
+    "furnace" => &FURNACE,
+    "crafting_table" => &CRAFTING_TABLE,
+    "cobblestone" => &COBBLESTONE,
+    "dark_oak_planks" => &DARK_OAK_PLANKS,
+    "oak_planks" => &OAK_PLANKS,
+    "oak_log" => &OAK_LOG,
+    "glass" => &GLASS,
+    "glowstone" => &GLOWSTONE,
+    "grass" => &GRASS,
+    "moon" => &MOON,
+    "oak_leaves" => &OAK_LEAVES,
+    _ => return None,
+  };
+  Some(Arc::clone(texture))
+}
+
 #[derive(Clone)]
 pub struct Texture {
   image: DynamicImage,
   pub width: usize,
   pub height: usize,
   color_array: Vec<Color>,
+  alpha_array: Vec<u8>, // coverage of each texel, 255 where the image has no alpha channel
+  mips: Vec<MipLevel>,  // the image halved again and again down to 1x1, level 0 is full size
+}
+
+// One level of a mip chain, in linear space so averaging texels is right
+#[derive(Clone)]
+struct MipLevel {
+  width: usize,
+  height: usize,
+  colors: Vec<HdrColor>,
+  alphas: Vec<f32>,
+}
+
+impl MipLevel {
+  // Every texel the mean of the 2x2 block under it, weighed by alpha so the holes of a cutout
+  // texture don't darken its far away look
+  fn downsample(&self) -> MipLevel {
+    let width = (self.width / 2).max(1);
+    let height = (self.height / 2).max(1);
+    let mut colors = Vec::with_capacity(width * height);
+    let mut alphas = Vec::with_capacity(width * height);
+    for y in 0..height {
+      for x in 0..width {
+        let mut color = HdrColor::black();
+        let mut plain = HdrColor::black();
+        let mut alpha = 0.0;
+        for (sx, sy) in [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+          let index = sy.min(self.height - 1) * self.width + sx.min(self.width - 1);
+          color = color + self.colors[index] * self.alphas[index];
+          plain = plain + self.colors[index];
+          alpha += self.alphas[index];
+        }
+        colors.push(if alpha > 0.0 { color * (1.0 / alpha) } else { plain * 0.25 });
+        alphas.push(alpha * 0.25);
+      }
+    }
+    MipLevel { width, height, colors, alphas }
+  }
+}
+
+// How a texture is sampled where a texel covers less than a pixel. Up close, where one texel
+// covers several pixels, every filter keeps the sharp nearest texel like Minecraft does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+  Nearest,   // the texel under the hit at full resolution, far away blocks shimmer
+  Bilinear,  // the four texels around the hit, from the mip level that fits the footprint
+  Trilinear, // bilinear from the two mip levels around the footprint, blended
+}
+
+impl TextureFilter {
+  pub const ALL: [TextureFilter; 3] = [TextureFilter::Nearest, TextureFilter::Bilinear, TextureFilter::Trilinear];
+
+  // The filter after this one, used to switch between them in the viewer
+  pub fn next(self) -> TextureFilter {
+    let index = TextureFilter::ALL.iter().position(|&filter| filter == self).unwrap();
+    TextureFilter::ALL[(index + 1) % TextureFilter::ALL.len()]
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      TextureFilter::Nearest => "nearest",
+      TextureFilter::Bilinear => "bilinear",
+      TextureFilter::Trilinear => "trilinear",
+    }
+  }
+}
+
+impl fmt::Display for TextureFilter {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl FromStr for TextureFilter {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<TextureFilter, String> {
+    TextureFilter::ALL
+      .iter()
+      .find(|filter| filter.name().eq_ignore_ascii_case(name))
+      .copied()
+      .ok_or_else(|| format!("unknown texture filter `{}`, expected nearest, bilinear or trilinear", name))
+  }
 }
 
 impl Texture {
+  // Loads an image, falling back to the missing texture if it can't be read
   pub fn new(file_path: &str) -> Texture {
-    let img = match ImageReader::open(file_path) {
-        Ok(reader) => match reader.decode() {
-            Ok(image) => image,
-            Err(e) => {
-                println!("Error decoding image: {}", e);
-                return Texture::black(); // Usar una textura negra como respaldo
-            }
-        },
-        Err(e) => {
-            println!("Error opening image file: {}", e);
-            return Texture::black(); // Usar una textura negra como respaldo
-        }
-    };
+    match Texture::load(file_path) {
+      Ok(texture) => texture,
+      Err(e) => {
+        eprintln!("could not load texture '{}': {}", file_path, e);
+        Texture::missing()
+      }
+    }
+  }
+
+  pub fn load(file_path: &str) -> Result<Texture, ImageError> {
+    let image = ImageReader::open(file_path)?.decode()?;
+    Ok(Texture::from_image(image))
+  }
+
+  fn from_image(img: DynamicImage) -> Texture {
     let width = img.width() as usize;
     let height = img.height() as usize;
     let mut texture = Texture {
@@ -52,21 +168,64 @@ impl Texture {
       width,
       height,
       color_array: vec![Color::new(0,0,0); width * height],
+      alpha_array: vec![255; width * height],
+      mips: Vec::new(),
     };
     texture.load_color_array();
+    texture.build_mips();
     texture
   }
 
   fn load_color_array(&mut self) {
     for x in 0..self.width {
         for y in 0..self.height {
-            let pixel = self.image.get_pixel(x as u32, y as u32).to_rgb();
+            let pixel = self.image.get_pixel(x as u32, y as u32).to_rgba();
             let color = ((pixel[0] as u32) << 16) | ((pixel[1] as u32) << 8) | (pixel[2] as u32);
             self.color_array[y * self.width + x] = Color::from_hex(color);
+            self.alpha_array[y * self.width + x] = pixel[3];
         }
     }
 }
 
+  fn build_mips(&mut self) {
+    let mut level = MipLevel {
+      width: self.width,
+      height: self.height,
+      colors: self.color_array.iter().map(|color| HdrColor::from(*color)).collect(),
+      alphas: self.alpha_array.iter().map(|alpha| *alpha as f32 / 255.0).collect(),
+    };
+    let mut mips = Vec::new();
+    while level.width > 1 || level.height > 1 {
+      let next = level.downsample();
+      mips.push(level);
+      level = next;
+    }
+    mips.push(level);
+    self.mips = mips;
+  }
+
+  // Blend of the four texels of mip `level` around `x`, `y` (in full size texels), kept inside
+  // `rect` so neighbouring faces of an atlas don't bleed in
+  fn bilinear(&self, rect: &TextureRect, level: usize, x: f32, y: f32) -> HdrColor {
+    let level = level.min(self.mips.len() - 1);
+    let mip = &self.mips[level];
+    let scale = (1 << level) as f32;
+    let clamp_to = |start: usize, size: usize, limit: usize, value: f32| {
+      let low = (start as f32 / scale).floor();
+      let high = (((start + size) as f32 / scale).ceil() - 1.0).max(low).min(limit as f32 - 1.0);
+      value.clamp(low, high) as usize
+    };
+    let (fx, fy) = (x / scale - 0.5, y / scale - 0.5);
+    let (x0, y0) = (fx.floor(), fy.floor());
+    let (tx, ty) = (fx - x0, fy - y0);
+    let (left, right) = (clamp_to(rect.x, rect.width, mip.width, x0), clamp_to(rect.x, rect.width, mip.width, x0 + 1.0));
+    let (top, bottom) = (clamp_to(rect.y, rect.height, mip.height, y0), clamp_to(rect.y, rect.height, mip.height, y0 + 1.0));
+    let texel = |x: usize, y: usize| mip.colors[y * mip.width + x];
+    let upper = texel(left, top) * (1.0 - tx) + texel(right, top) * tx;
+    let lower = texel(left, bottom) * (1.0 - tx) + texel(right, bottom) * tx;
+    upper * (1.0 - ty) + lower * ty
+  }
+
   pub fn get_color(&self, x: usize, y: usize) -> Color {
     if x >= self.width || y >= self.height {
       Color::from_hex(0xFF00FF)
@@ -75,18 +234,162 @@ impl Texture {
     }
   }
 
-  pub fn black() -> Texture {
-    let width = 1; // Ancho de 1 píxel
-    let height = 1; // Alto de 1 píxel
-    let mut texture = Texture {
-        image: DynamicImage::new_rgb8(width as u32, height as u32),
-        width,
-        height,
-        color_array: vec![Color::new(0, 0, 0); width * height], // Colores negros
-    };
-    texture.load_color_array(); // Cargar el color negro
-    texture
+  // Coverage of a texel from 0 for a hole to 1 for solid
+  pub fn get_alpha(&self, x: usize, y: usize) -> f32 {
+    if x >= self.width || y >= self.height {
+      1.0
+    } else {
+      self.alpha_array[y * self.width + x] as f32 / 255.0
+    }
+  }
+
+  // The whole image as one rectangle
+  pub fn full_rect(&self) -> TextureRect {
+    TextureRect { x: 0, y: 0, width: self.width, height: self.height }
+  }
+
+  // The top square of an animated texture, whose frames are stacked, otherwise the whole image
+  pub fn first_frame(&self) -> TextureRect {
+    if self.height > self.width {
+      TextureRect { x: 0, y: 0, width: self.width, height: self.width }
+    } else {
+      self.full_rect()
+    }
+  }
+
+  // Mean of the texels in `rect` in linear space
+  pub fn average_color(&self, rect: &TextureRect) -> HdrColor {
+    let mut sum = HdrColor::black();
+    let mut count = 0;
+    for y in rect.y..(rect.y + rect.height).min(self.height) {
+      for x in rect.x..(rect.x + rect.width).min(self.width) {
+        sum = sum + HdrColor::from(self.color_array[y * self.width + x]);
+        count += 1;
+      }
+    }
+    if count == 0 {
+      return HdrColor::black();
+    }
+    sum * (1.0 / count as f32)
+  }
+
+  // Every texel multiplied by `tint`, the way the game colors grass and leaves by biome
+  pub fn tinted(&self, tint: Color) -> Texture {
+    let tint = tint.to_hex();
+    let channels = [(tint >> 16) & 0xFF, (tint >> 8) & 0xFF, tint & 0xFF];
+    let mut image = self.image.to_rgba8();
+    for pixel in image.pixels_mut() {
+      for (channel, tint) in channels.iter().enumerate() {
+        pixel[channel] = (pixel[channel] as u32 * tint / 255) as u8;
+      }
+    }
+    Texture::from_image(DynamicImage::ImageRgba8(image))
+  }
+
+  // The classic magenta and black checkerboard
+  pub fn missing() -> Texture {
+    let image = RgbaImage::from_fn(16, 16, |x, y| {
+      if (x < 8) == (y < 8) { Rgba([248, 0, 248, 255]) } else { Rgba([0, 0, 0, 255]) }
+    });
+    Texture::from_image(DynamicImage::ImageRgba8(image))
+  }
+}
+
+// Area of an image in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureRect {
+  pub x: usize,
+  pub y: usize,
+  pub width: usize,
+  pub height: usize,
+}
+
+// The part of an image one face of a block shows
+#[derive(Debug, Clone)]
+pub struct FaceTexture {
+  pub texture: Arc<Texture>,
+  pub rect: TextureRect,
 }
+
+// What each face of a block shows, indexed like `Cube::get_face_index`:
+// top, bottom, north (-z), south (+z), east (+x) and west (-x)
+#[derive(Debug, Clone)]
+pub struct FaceTextures {
+  pub faces: [FaceTexture; 6],
+}
+
+pub const FACE_NAMES: [&str; 6] = ["top", "bottom", "north", "south", "east", "west"];
+
+impl FaceTextures {
+  // Six square faces side by side in the order above, the bundled textures' layout
+  pub fn strip(texture: Arc<Texture>) -> FaceTextures {
+    let face_width = texture.width / 6;
+    FaceTextures::atlas(texture.clone(), std::array::from_fn(|face| TextureRect { x: face * face_width, y: 0, width: face_width, height: texture.height }))
+  }
+
+  // The layout a lone image most likely has: six times wider than tall is a strip of faces, taller
+  // than wide is an animated texture whose frames are stacked, of which the first is shown, and
+  // anything else goes on every face
+  pub fn fit(texture: Arc<Texture>) -> FaceTextures {
+    if texture.width == 6 * texture.height {
+      FaceTextures::strip(texture)
+    } else if texture.height > texture.width {
+      let frame = texture.first_frame();
+      FaceTextures::atlas(texture, [frame; 6])
+    } else {
+      FaceTextures::single(texture)
+    }
+  }
+
+  // The same image on every face
+  pub fn single(texture: Arc<Texture>) -> FaceTextures {
+    FaceTextures::separate(std::array::from_fn(|_| texture.clone()))
+  }
+
+  // A whole image for each face
+  pub fn separate(textures: [Arc<Texture>; 6]) -> FaceTextures {
+    FaceTextures { faces: textures.map(|texture| FaceTexture { rect: texture.full_rect(), texture }) }
+  }
+
+  // Rectangles of one shared image
+  pub fn atlas(texture: Arc<Texture>, rects: [TextureRect; 6]) -> FaceTextures {
+    FaceTextures { faces: rects.map(|rect| FaceTexture { texture: texture.clone(), rect }) }
+  }
+
+  // Color and alpha at `u`, `v` in [0, 1] across face `face`
+  pub fn sample(&self, face: usize, u: f32, v: f32) -> (Color, f32) {
+    let FaceTexture { texture, rect } = &self.faces[face];
+    let x = rect.x + ((u * rect.width as f32) as usize).min(rect.width.saturating_sub(1));
+    let y = rect.y + ((v * rect.height as f32) as usize).min(rect.height.saturating_sub(1));
+    (texture.get_color(x, y), texture.get_alpha(x, y))
+  }
+
+  // Color at `u`, `v` across face `face` when the pixel covers `footprint` of the face's width
+  pub fn filtered(&self, face: usize, u: f32, v: f32, footprint: f32, filter: TextureFilter) -> HdrColor {
+    let FaceTexture { texture, rect } = &self.faces[face];
+    // Mip level where one texel covers about the footprint, below 0 a texel is bigger than the pixel
+    let lod = (footprint * rect.width.max(rect.height) as f32).log2();
+    if filter == TextureFilter::Nearest || lod.is_nan() || lod <= 0.0 {
+      return HdrColor::from(self.sample(face, u, v).0);
+    }
+    let x = rect.x as f32 + u * rect.width as f32;
+    let y = rect.y as f32 + v * rect.height as f32;
+    match filter {
+      TextureFilter::Trilinear => {
+        let level = lod.floor();
+        let blend = lod - level;
+        let level = level as usize;
+        texture.bilinear(rect, level, x, y) * (1.0 - blend) + texture.bilinear(rect, level + 1, x, y) * blend
+      }
+      _ => texture.bilinear(rect, lod.round() as usize, x, y),
+    }
+  }
+
+  // Mean color over all six faces, what the block looks like from far away
+  pub fn average_color(&self) -> HdrColor {
+    let sum = self.faces.iter().fold(HdrColor::black(), |sum, face| sum + face.texture.average_color(&face.rect));
+    sum * (1.0 / 6.0)
+  }
 }
 
 impl fmt::Debug for Texture {
@@ -0,0 +1,208 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+
+use super::entitiy::color::HdrColor;
+use super::sampling::Rng;
+
+// Equirectangular HDR environment map. Escaping rays see it as the background, and it lights
+// the scene from every direction, sampled where it is brightest.
+pub struct Environment {
+    width: usize,
+    height: usize,
+    pixels: Vec<HdrColor>,
+    pub rotation: f32,  // radians around the vertical axis
+    pub intensity: f32,
+    row_cdf: Vec<f32>,    // picks a row, in proportion to its share of the light
+    column_cdfs: Vec<f32>, // then a column inside that row, `width` entries per row
+    total_weight: f32,
+}
+
+impl Environment {
+    pub fn load(path: &str, rotation: f32, intensity: f32) -> Result<Environment, image::ImageError> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|pixel| HdrColor::new(pixel[0], pixel[1], pixel[2])).collect();
+        Ok(Environment::new(width, height, pixels, rotation, intensity))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<HdrColor>, rotation: f32, intensity: f32) -> Environment {
+        // Texels near the poles cover less of the sphere, sin(theta) evens that out
+        let mut column_cdfs = vec![0.0; width * height];
+        let mut row_weights = vec![0.0; height];
+        for y in 0..height {
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * PI).sin();
+            let mut sum = 0.0;
+            for x in 0..width {
+                sum += pixels[y * width + x].luminance().max(0.0) * sin_theta;
+                column_cdfs[y * width + x] = sum;
+            }
+            row_weights[y] = sum;
+        }
+
+        let mut row_cdf = vec![0.0; height];
+        let mut total_weight = 0.0;
+        for (y, weight) in row_weights.iter().enumerate() {
+            total_weight += weight;
+            row_cdf[y] = total_weight;
+        }
+
+        Environment { width, height, pixels, rotation, intensity, row_cdf, column_cdfs, total_weight }
+    }
+
+    // Radiance arriving from `direction`
+    pub fn radiance(&self, direction: &Vec3) -> HdrColor {
+        let (x, y) = self.texel(direction);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    // Random direction towards the map with its radiance and probability density per solid angle.
+    // Bright texels are picked more often, so a small sun in the map gets most of the rays.
+    pub fn sample(&self, rng: &mut Rng) -> Option<(Vec3, HdrColor, f32)> {
+        if self.total_weight <= 0.0 {
+            return None;
+        }
+        let y = find(&self.row_cdf, rng.next_f32() * self.total_weight);
+        let row = &self.column_cdfs[y * self.width..(y + 1) * self.width];
+        let x = find(row, rng.next_f32() * row[self.width - 1]);
+
+        let u = (x as f32 + rng.next_f32()) / self.width as f32;
+        let v = (y as f32 + rng.next_f32()) / self.height as f32;
+        let direction = self.direction(u, v);
+        let pdf = self.pdf(x, y);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((direction, self.pixels[y * self.width + x] * self.intensity, pdf))
+    }
+
+    // Density per solid angle of sampling texel (x, y)
+    fn pdf(&self, x: usize, y: usize) -> f32 {
+        let row = &self.column_cdfs[y * self.width..(y + 1) * self.width];
+        let weight = row[x] - if x > 0 { row[x - 1] } else { 0.0 };
+        let sin_theta = ((y as f32 + 0.5) / self.height as f32 * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // Weight share of the texel over the solid angle it covers
+        weight / self.total_weight * (self.width * self.height) as f32 / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn texel(&self, direction: &Vec3) -> (usize, usize) {
+        let phi = direction.x.atan2(-direction.z) + self.rotation;
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = theta / PI;
+        (
+            ((u * self.width as f32) as usize).min(self.width - 1),
+            ((v * self.height as f32) as usize).min(self.height - 1),
+        )
+    }
+
+    // Inverse of `texel`, from map coordinates in [0, 1) to a unit direction
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI - self.rotation;
+        let theta = v * PI;
+        Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+}
+
+// First index whose cumulative value is above `value`
+fn find(cdf: &[f32], value: f32) -> usize {
+    cdf.partition_point(|&sum| sum <= value).min(cdf.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 16;
+
+    fn constant(radiance: f32, rotation: f32) -> Environment {
+        Environment::new(WIDTH, HEIGHT, vec![HdrColor::new(radiance, radiance, radiance); WIDTH * HEIGHT], rotation, 2.0)
+    }
+
+    // Black everywhere but texel (x, y)
+    fn hot_texel(x: usize, y: usize, rotation: f32) -> Environment {
+        let mut pixels = vec![HdrColor::black(); WIDTH * HEIGHT];
+        pixels[y * WIDTH + x] = HdrColor::new(100.0, 100.0, 100.0);
+        Environment::new(WIDTH, HEIGHT, pixels, rotation, 2.0)
+    }
+
+    // Solid angle texel row `y` covers, one texel of it
+    fn texel_solid_angle(y: usize) -> f32 {
+        let theta = |v: usize| v as f32 / HEIGHT as f32 * PI;
+        2.0 * PI / WIDTH as f32 * (theta(y).cos() - theta(y + 1).cos())
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_the_sphere() {
+        for environment in [constant(1.0, 0.0), hot_texel(5, 4, 0.0), hot_texel(20, 0, 0.0)] {
+            let total: f32 = (0..HEIGHT)
+                .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+                .map(|(x, y)| environment.pdf(x, y) * texel_solid_angle(y))
+                .sum();
+            assert!((total - 1.0).abs() < 1e-2, "{}", total);
+        }
+        // A uniform map is sampled uniformly over the sphere
+        let uniform = constant(1.0, 0.0);
+        assert!((uniform.pdf(3, 7) - 1.0 / (4.0 * PI)).abs() < 1e-3);
+    }
+
+    // Mean of radiance * cos / pdf over `count` samples, the irradiance on a surface facing `normal`
+    fn estimated_irradiance(environment: &Environment, normal: &Vec3, count: usize) -> f32 {
+        let mut rng = Rng::new(7, 0);
+        let mut sum = 0.0;
+        for _ in 0..count {
+            let (direction, radiance, pdf) = environment.sample(&mut rng).unwrap();
+            sum += radiance.r * direction.dot(normal).max(0.0) / pdf;
+        }
+        sum / count as f32
+    }
+
+    #[test]
+    fn samples_estimate_the_irradiance() {
+        // A uniform sky of radiance L gives pi * L on any surface
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let estimate = estimated_irradiance(&constant(1.0, 0.4), &up, 20000);
+        assert!((estimate - PI * 2.0).abs() < 0.05 * PI * 2.0, "{}", estimate);
+
+        // A single bright texel gives its radiance times its solid angle times the cosine towards it
+        let (x, y) = (5, 4);
+        let environment = hot_texel(x, y, 0.4);
+        let center = environment.direction((x as f32 + 0.5) / WIDTH as f32, (y as f32 + 0.5) / HEIGHT as f32);
+        let expected = 200.0 * texel_solid_angle(y) * center.dot(&up);
+        let estimate = estimated_irradiance(&environment, &up, 2000);
+        assert!((estimate - expected).abs() < 0.02 * expected, "{} against {}", estimate, expected);
+
+        // Every sample comes from the bright texel, and the map shows it in that direction
+        let mut rng = Rng::new(3, 0);
+        for _ in 0..100 {
+            let (direction, radiance, _) = environment.sample(&mut rng).unwrap();
+            assert_eq!(environment.texel(&direction), (x, y));
+            assert_eq!(environment.radiance(&direction), radiance);
+        }
+    }
+
+    #[test]
+    fn directions_and_texels_invert_each_other() {
+        let environment = constant(1.0, 1.3);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let direction = environment.direction((x as f32 + 0.5) / WIDTH as f32, (y as f32 + 0.5) / HEIGHT as f32);
+                assert!((direction.norm() - 1.0).abs() < 1e-5);
+                assert_eq!(environment.texel(&direction), (x, y));
+            }
+        }
+
+        // Going through a texel and back lands within half a texel of where it started
+        let mut rng = Rng::new(11, 0);
+        let half_texel = PI / HEIGHT as f32;
+        for _ in 0..1000 {
+            let direction = Vec3::new(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5).normalize();
+            let (x, y) = environment.texel(&direction);
+            let back = environment.direction((x as f32 + 0.5) / WIDTH as f32, (y as f32 + 0.5) / HEIGHT as f32);
+            assert!(back.dot(&direction).clamp(-1.0, 1.0).acos() < half_texel, "{:?}", direction.as_slice());
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::{ImageBuffer, Rgb, Rgb32FImage};
+
+use super::camera::Camera;
+use super::framebuffer::{Framebuffer, RenderableToFile};
+
+// Image formats the framebuffer can be saved as, picked from the file extension.
+// PNG, PPM and BMP store the tone mapped image; PFM and EXR store the linear HDR radiance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Pfm,
+    Exr,
+    Bmp,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::Exr),
+            "bmp" => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+}
+
+// Optional information stored next to the pixels, in formats that have room for it
+#[derive(Debug, Clone, Default)]
+pub struct ImageMetadata {
+    pub scene: Option<String>,
+    pub camera: Option<String>,
+    pub render_time: Option<Duration>,
+}
+
+impl ImageMetadata {
+    pub fn new(scene: &str, camera: &Camera, render_time: Option<Duration>) -> ImageMetadata {
+        let vector = |v: &nalgebra_glm::Vec3| format!("{},{},{}", v.x, v.y, v.z);
+        ImageMetadata {
+            scene: Some(scene.to_string()),
+            camera: Some(format!("eye={} center={} up={}", vector(&camera.eye), vector(&camera.center), vector(&camera.up))),
+            render_time,
+        }
+    }
+
+    // Key/value pairs, keys follow the PNG text chunk conventions
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![("Software", String::from("CG-Minecraft"))];
+        if let Some(scene) = &self.scene {
+            entries.push(("Title", scene.clone()));
+        }
+        if let Some(camera) = &self.camera {
+            entries.push(("Camera", camera.clone()));
+        }
+        if let Some(render_time) = self.render_time {
+            entries.push(("Render Time", format!("{:.3}s", render_time.as_secs_f64())));
+        }
+        entries
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    UnsupportedFormat(String),
+    Io(io::Error),
+    Image(image::ImageError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::UnsupportedFormat(path) => {
+                write!(f, "can't tell the image format of '{}', use .png, .ppm, .pfm, .exr or .bmp", path)
+            }
+            ExportError::Io(e) => write!(f, "{}", e),
+            ExportError::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> ExportError {
+        ExportError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(e: image::ImageError) -> ExportError {
+        ExportError::Image(e)
+    }
+}
+
+pub fn save_image(framebuffer: &Framebuffer, path: &str, metadata: &ImageMetadata) -> Result<(), ExportError> {
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => write_png(framebuffer, path, metadata),
+        Some(ImageFormat::Ppm) => write_ppm(framebuffer, path, metadata),
+        Some(ImageFormat::Pfm) => write_pfm(framebuffer, path),
+        Some(ImageFormat::Exr) => write_exr(framebuffer, path),
+        Some(ImageFormat::Bmp) => Ok(framebuffer.render_buffer(path)?),
+        None => Err(ExportError::UnsupportedFormat(path.to_string())),
+    }
+}
+
+// `screenshots/<scene>-YYYYMMDD-HHMMSS.png` with the current UTC time, numbered if it already exists
+pub fn screenshot_path(scene: &str) -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+    let base = format!(
+        "screenshots/{}-{:04}{:02}{:02}-{:02}{:02}{:02}",
+        scene, year, month, day, time / 3600, time / 60 % 60, time % 60
+    );
+
+    let mut path = format!("{}.png", base);
+    let mut copy = 1;
+    while Path::new(&path).exists() {
+        copy += 1;
+        path = format!("{}-{}.png", base, copy);
+    }
+    path
+}
+
+// Days since 1970-01-01 to a (year, month, day) date, Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = (if month_index < 10 { month_index + 3 } else { month_index - 9 }) as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Tone mapped pixels as 8 bit RGB, top row first
+fn rgb_bytes(framebuffer: &Framebuffer) -> Vec<u8> {
+    framebuffer
+        .buffer
+        .iter()
+        .flat_map(|hex| [(hex >> 16) as u8, (hex >> 8) as u8, *hex as u8])
+        .collect()
+}
+
+// The metadata goes in tEXt chunks right after the header, the image crate's encoder writes none
+fn write_png(framebuffer: &Framebuffer, path: &str, metadata: &ImageMetadata) -> Result<(), ExportError> {
+    let image: ImageBuffer<Rgb<u8>, _> = ImageBuffer::from_raw(framebuffer.width as u32, framebuffer.height as u32, rgb_bytes(framebuffer))
+        .expect("the framebuffer holds width * height pixels");
+    let mut encoded = Cursor::new(Vec::new());
+    image.write_to(&mut encoded, image::ImageFormat::Png)?;
+    let encoded = encoded.into_inner();
+
+    // 8 bytes of signature, then IHDR: length, type, 13 bytes of data and the CRC
+    let after_header = 8 + 4 + 4 + 13 + 4;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&encoded[..after_header])?;
+    for (key, value) in metadata.entries() {
+        let data: Vec<u8> = key.bytes().chain([0]).chain(value.bytes()).collect();
+        write_png_chunk(&mut file, b"tEXt", &data)?;
+    }
+    file.write_all(&encoded[after_header..])?;
+    file.flush()?;
+    Ok(())
+}
+
+fn write_png_chunk(file: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    file.write_all(&(data.len() as u32).to_be_bytes())?;
+    file.write_all(kind)?;
+    file.write_all(data)?;
+    file.write_all(&crc32(kind.iter().chain(data)).to_be_bytes())
+}
+
+// The CRC-32 PNG chunks end with, bit by bit, chunks are small enough to skip the table
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Binary PPM (P6), the metadata goes in header comments
+fn write_ppm(framebuffer: &Framebuffer, path: &str, metadata: &ImageMetadata) -> Result<(), ExportError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "P6")?;
+    for (key, value) in metadata.entries() {
+        writeln!(file, "# {}: {}", key, value)?;
+    }
+    writeln!(file, "{} {}", framebuffer.width, framebuffer.height)?;
+    writeln!(file, "255")?;
+    file.write_all(&rgb_bytes(framebuffer))?;
+    file.flush()?;
+    Ok(())
+}
+
+// Portable float map: linear RGB floats, little endian (negative scale), bottom row first.
+// The format has no place for metadata.
+fn write_pfm(framebuffer: &Framebuffer, path: &str) -> Result<(), ExportError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", framebuffer.width, framebuffer.height)?;
+    for row in framebuffer.hdr_buffer.chunks(framebuffer.width).rev() {
+        for color in row {
+            for channel in [color.r, color.g, color.b] {
+                file.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+// 32 bit float OpenEXR with the linear radiance, before exposure and tone mapping. The image
+// crate writes no header attributes, so the metadata is left out.
+fn write_exr(framebuffer: &Framebuffer, path: &str) -> Result<(), ExportError> {
+    let radiance = framebuffer.hdr_buffer.iter().flat_map(|color| [color.r, color.g, color.b]).collect();
+    let image = Rgb32FImage::from_raw(framebuffer.width as u32, framebuffer.height as u32, radiance)
+        .expect("the framebuffer holds width * height pixels");
+    Ok(image.save_with_format(path, image::ImageFormat::OpenExr)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::internal::entitiy::color::{Color, HdrColor};
+    use crate::internal::temp_dir::TempDir;
+
+    // Red, green, blue and white corners, top row first, in both the tone mapped and the HDR buffer
+    const CORNERS: [(u8, u8, u8); 4] = [(255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 255, 255)];
+    const RADIANCE: [[f32; 3]; 4] = [[2.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 4.0], [0.5, 0.25, 0.125]];
+
+    fn corners() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(2, 2, Color::new(0, 0, 0));
+        for (index, (r, g, b)) in CORNERS.iter().enumerate() {
+            framebuffer.buffer[index] = Color::new(*r, *g, *b).to_hex();
+            let [r, g, b] = RADIANCE[index];
+            framebuffer.hdr_buffer[index] = HdrColor::new(r, g, b);
+        }
+        framebuffer
+    }
+
+    fn metadata() -> ImageMetadata {
+        ImageMetadata { scene: Some(String::from("corners")), camera: None, render_time: Some(Duration::from_millis(1500)) }
+    }
+
+    // Writes the corners and reads them back as 8 bit RGB with the image crate
+    fn round_trip(dir: &TempDir, file: &str) -> image::RgbImage {
+        let path = dir.file(file);
+        save_image(&corners(), &path, &metadata()).unwrap();
+        image::open(&path).unwrap().to_rgb8()
+    }
+
+    fn assert_corners(image: &image::RgbImage, format: &str) {
+        assert_eq!(image.dimensions(), (2, 2), "{}", format);
+        for (index, (r, g, b)) in CORNERS.iter().enumerate() {
+            let (x, y) = (index as u32 % 2, index as u32 / 2);
+            assert_eq!(image.get_pixel(x, y).0, [*r, *g, *b], "{} pixel {},{}", format, x, y);
+        }
+    }
+
+    #[test]
+    fn tone_mapped_formats_keep_the_top_row_on_top() {
+        let dir = TempDir::new("export_ldr");
+        for file in ["corners.png", "corners.bmp", "corners.ppm"] {
+            assert_corners(&round_trip(&dir, file), file);
+        }
+    }
+
+    #[test]
+    fn png_and_ppm_record_the_metadata() {
+        let dir = TempDir::new("export_metadata");
+        for file in ["corners.png", "corners.ppm"] {
+            round_trip(&dir, file);
+            let bytes = fs::read(dir.path().join(file)).unwrap();
+            let text = String::from_utf8_lossy(&bytes);
+            assert!(text.contains("corners") && text.contains("1.500s"), "{}", file);
+        }
+        let png = fs::read(dir.path().join("corners.png")).unwrap();
+        assert!(png.windows(10).any(|bytes| bytes == b"tEXtTitle\0"));
+    }
+
+    #[test]
+    fn pfm_stores_the_bottom_row_first() {
+        let dir = TempDir::new("export_pfm");
+        let path = dir.file("corners.pfm");
+        save_image(&corners(), &path, &metadata()).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let floats: Vec<f32> = bytes[header.len()..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        let pixels: Vec<&[f32]> = floats.chunks(3).collect();
+        assert_eq!(pixels, [&RADIANCE[2][..], &RADIANCE[3], &RADIANCE[0], &RADIANCE[1]]);
+    }
+
+    #[test]
+    fn exr_stores_linear_rgb_top_row_first() {
+        let dir = TempDir::new("export_exr");
+        let path = dir.file("corners.exr");
+        save_image(&corners(), &path, &metadata()).unwrap();
+
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.color(), image::ColorType::Rgb32F);
+        let image = image.to_rgb32f();
+        for (index, radiance) in RADIANCE.iter().enumerate() {
+            assert_eq!(&image.get_pixel(index as u32 % 2, index as u32 / 2).0, radiance, "pixel {}", index);
+        }
+    }
+}
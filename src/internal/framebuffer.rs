@@ -2,14 +2,16 @@ use std::fs::File;
 use std::io::{self, Write};
 use std::path::Path;
 
-use crate::internal::entitiy::color::Color;
+use rayon::prelude::*;
+
+use crate::internal::entitiy::color::{Color, HdrColor};
+use crate::internal::tonemap::{self, ToneMapping};
 
 pub struct Framebuffer {
     pub width : usize, 
     pub height: usize,
     pub buffer : Vec<u32>,
-    background_color : Color,
-    current_color : Color
+    pub hdr_buffer : Vec<HdrColor>, // linear radiance of the last render, `buffer` is its tone mapped image
 }
 
 pub trait RenderableToFile {
@@ -27,63 +29,16 @@ impl Framebuffer {
             width,
             height,
             buffer,
-            background_color,
-            current_color: Color::new(0, 0, 0), // Default current color to black
-        }
-    }
-
-    // Constructor to create a new Framebuffer
-    pub fn new_default(width: usize, height: usize) -> Self {
-        let white_color = Color::new(255, 255, 255);
-        Self::new(width, height, white_color)
-    }
-
-    // Function to clear the framebuffer with the background color
-    pub fn clear(&mut self) {
-        let background_hex = self.background_color.to_hex();
-        for pixel in self.buffer.iter_mut() {
-            *pixel = background_hex;
+            hdr_buffer: vec![HdrColor::black(); buffer_size],
         }
     }
 
-    // Function to draw a point at (x, y) using the current color
-    pub fn draw_point(&mut self, x: usize, y: usize) {
-        if  0 < x  
-            && x < self.width 
-            && 0 < y 
-            && y < self.height {
-            let index = y * self.width + x;
-            self.buffer[index] = self.current_color.to_hex();
-        }
-    }
-
-    // Function to draw a point at (x, y) using the current color
-    /// owo
-    pub fn get_point_color(&mut self, x: usize, y: usize) -> Color{
-        if x < self.width && y < self.height {
-            let index = y * self.width + x;
-            let color = Color::from_hex(self.buffer[index]);
-            return  color
-        }
-        return Color::new(0, 0, 0);
-    }
-
-    // Function to set the background color
-    pub fn set_background_color(&mut self, color: Color) {
-        self.background_color = color;
-        self.clear(); // Clear buffer with the new background color
-    }
-    
-    pub fn set_background_color_hex(&mut self, hex: u32){
-        self.background_color = Color::from_hex(hex);
-    }
-
-    // Function to set the current drawing color
-    pub fn set_current_color(&mut self, color: Color) {
-        self.current_color = color;
-    }
-    pub fn set_current_color_hex(&mut self, hex: u32) {
-        self.current_color = Color::from_hex(hex);
+    // Fills `buffer` from `hdr_buffer`, so exposure and operator can change without rendering again
+    pub fn tone_map(&mut self, tone_mapping: ToneMapping, exposure: f32) {
+        self.buffer
+            .par_iter_mut()
+            .zip(self.hdr_buffer.par_iter())
+            .for_each(|(pixel, color)| *pixel = tonemap::tone_map(*color, tone_mapping, exposure).to_hex());
     }
 
 }
@@ -91,7 +46,7 @@ impl Framebuffer {
 impl RenderableToFile for Framebuffer {
    fn render_buffer(&self, filename: &str) -> io::Result<()> {
         let path = Path::new(filename);
-        let mut file = File::create(&path)?;
+        let mut file = File::create(path)?;
 
         // Write the BMP header
         self.write_bmp_header(&mut file)?;
@@ -140,7 +95,7 @@ impl RenderableToFile for Framebuffer {
 }
 
 fn write_pixel_data(&self, file: &mut File) -> io::Result<()> {
-    for y in 0..self.height { // BMP files are bottom to top
+    for y in (0..self.height).rev() { // BMP files are bottom to top
         for x in 0..self.width {
             let index = y * self.width + x;
             let color_hex = self.buffer[index];
@@ -0,0 +1,332 @@
+use nalgebra_glm::Vec3;
+use std::collections::VecDeque;
+
+use super::entitiy::color::HdrColor;
+use super::entitiy::grid::{Grid, BLOCK_SPACING};
+
+// Minecraft's lighting model: every cell of the grid holds a block light level and a sky light level
+// from 0 to 15, flood-filled from glowing blocks and from the open sky and dropping by one per cell.
+// Faces are lit by the levels of the air in front of them, so no shadow rays are needed.
+
+const MAX_LEVEL: u8 = 15;
+const BLOCK_LIGHT: HdrColor = HdrColor { r: 1.0, g: 0.85, b: 0.6 };     // warm, like torches and glowstone
+const NIGHT_SKY_LIGHT: HdrColor = HdrColor { r: 0.2, g: 0.23, b: 0.35 }; // full sky light under the moon
+const MIN_BRIGHTNESS: f32 = 0.03; // even level 0 isn't pitch black
+
+pub struct LightMap {
+    width: usize,
+    height: usize,
+    depth: usize,
+    min: Vec3,
+    cube_width: f32,
+    opaque: Vec<bool>, // blocks that stop light, glass, leaves and other see-through blocks let it pass
+    block: Vec<u8>,
+    sky: Vec<u8>,
+}
+
+impl LightMap {
+    pub fn new(grid: &Grid) -> LightMap {
+        let (width, height, depth) = (grid.width, grid.height, grid.depth);
+        let count = width * height * depth;
+        let mut light_map = LightMap {
+            width,
+            height,
+            depth,
+            min: grid.min(),
+            cube_width: grid.cube_width,
+            opaque: vec![false; count],
+            block: Vec::new(),
+            sky: Vec::new(),
+        };
+        let mut block = vec![0; count];
+        let mut sky = vec![0; count];
+
+        let mut block_sources = Vec::new();
+        for y in 0..height {
+            for z in 0..depth {
+                for x in 0..width {
+                    let Some(material) = grid.block(x, y, z) else {
+                        continue;
+                    };
+                    let index = light_map.index(x, y, z);
+                    light_map.opaque[index] = !material.lets_light_through();
+                    if material.is_emissive() {
+                        block[index] = MAX_LEVEL;
+                        block_sources.push(index);
+                    }
+                }
+            }
+        }
+
+        // Sky light comes straight down each column until the first opaque block, and in from
+        // the sides, since everything around the diorama is open air
+        let mut sky_sources = Vec::new();
+        for z in 0..depth {
+            for x in 0..width {
+                for y in (0..height).rev() {
+                    let index = light_map.index(x, y, z);
+                    if light_map.opaque[index] {
+                        break;
+                    }
+                    sky[index] = MAX_LEVEL;
+                    sky_sources.push(index);
+                }
+            }
+        }
+        for y in 0..height {
+            for z in 0..depth {
+                for x in 0..width {
+                    let index = light_map.index(x, y, z);
+                    let on_side = x == 0 || x == width - 1 || z == 0 || z == depth - 1 || y == 0;
+                    if on_side && !light_map.opaque[index] && sky[index] < MAX_LEVEL - 1 {
+                        sky[index] = MAX_LEVEL - 1;
+                        sky_sources.push(index);
+                    }
+                }
+            }
+        }
+
+        light_map.flood_fill(&mut block, block_sources);
+        light_map.flood_fill(&mut sky, sky_sources);
+        light_map.block = block;
+        light_map.sky = sky;
+        light_map
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (y * self.depth + z) * self.width + x
+    }
+
+    // Spreads the levels breadth first, each step into a cell light can pass costs one level
+    fn flood_fill(&self, levels: &mut [u8], sources: Vec<usize>) {
+        let mut queue = VecDeque::from(sources);
+        while let Some(index) = queue.pop_front() {
+            let level = levels[index];
+            if level <= 1 {
+                continue;
+            }
+            let x = index % self.width;
+            let z = (index / self.width) % self.depth;
+            let y = index / (self.width * self.depth);
+            for (dx, dy, dz) in [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)] {
+                let Some(neighbor) = self.cell(x as isize + dx, y as isize + dy, z as isize + dz) else {
+                    continue;
+                };
+                if !self.opaque[neighbor] && levels[neighbor] < level - 1 {
+                    levels[neighbor] = level - 1;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    fn cell(&self, x: isize, y: isize, z: isize) -> Option<usize> {
+        let inside = x >= 0 && y >= 0 && z >= 0 && (x as usize) < self.width && (y as usize) < self.height && (z as usize) < self.depth;
+        inside.then(|| self.index(x as usize, y as usize, z as usize))
+    }
+
+    // Block and sky level of a cell and whether it stops light, cells outside the grid are open sky
+    fn levels(&self, cell: [isize; 3]) -> (f32, f32, bool) {
+        match self.cell(cell[0], cell[1], cell[2]) {
+            Some(index) => (self.block[index] as f32, self.sky[index] as f32, self.opaque[index]),
+            None => (0.0, MAX_LEVEL as f32, false),
+        }
+    }
+
+    // Light falling on the face through `point` with outward `normal`. `daylight` goes from 0 at
+    // night to 1 at day and dims the sky light. With `smooth` the levels are blended between the
+    // corners of the face, which also darkens the inside corners where blocks meet.
+    pub fn light(&self, point: &Vec3, normal: &Vec3, smooth: bool, daylight: f32) -> HdrColor {
+        let local = self.to_cells(point) + normal * 0.5;
+        let air = [local.x.floor() as isize, local.y.floor() as isize, local.z.floor() as isize];
+
+        let (block, sky) = if smooth {
+            self.smooth_levels(&local, air, normal)
+        } else {
+            let (block, sky, _) = self.levels(air);
+            (block, sky)
+        };
+
+        let daylight = daylight.clamp(0.0, 1.0);
+        let sky_light = NIGHT_SKY_LIGHT * (1.0 - daylight) + HdrColor::new(daylight, daylight, daylight);
+        let color = BLOCK_LIGHT * brightness(block) + sky_light * brightness(sky);
+        let light = HdrColor::new(color.r.min(1.0), color.g.min(1.0), color.b.min(1.0));
+        light * face_shade(normal)
+    }
+
+    // `point` counted in cells, where a block narrower or wider than the spacing between cells
+    // counts as one whole cell, so block faces fall on whole numbers
+    fn to_cells(&self, point: &Vec3) -> Vec3 {
+        (point - self.min).map(|offset| {
+            let cell = (offset / BLOCK_SPACING).floor();
+            cell + (offset - cell * BLOCK_SPACING) / self.cube_width
+        })
+    }
+
+    // Levels at the four corners of the face, each the mean of the four cells touching that corner
+    // on the air side, blended bilinearly across the face
+    fn smooth_levels(&self, local: &Vec3, air: [isize; 3], normal: &Vec3) -> (f32, f32) {
+        let axis = normal.iamax();
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let fu = (local[u_axis] - air[u_axis] as f32).clamp(0.0, 1.0);
+        let fv = (local[v_axis] - air[v_axis] as f32).clamp(0.0, 1.0);
+
+        let (center_block, center_sky, _) = self.levels(air);
+        let mut block = 0.0;
+        let mut sky = 0.0;
+        for (su, wu) in [(-1, 1.0 - fu), (1, fu)] {
+            for (sv, wv) in [(-1, 1.0 - fv), (1, fv)] {
+                let mut side_u = air;
+                side_u[u_axis] += su;
+                let mut side_v = air;
+                side_v[v_axis] += sv;
+                let mut corner = side_u;
+                corner[v_axis] += sv;
+
+                // Opaque cells count as darkness, and a corner hidden behind both sides is never seen
+                let (side_u, side_v, corner) = (self.levels(side_u), self.levels(side_v), self.levels(corner));
+                let corner = if side_u.2 && side_v.2 { (0.0, 0.0, true) } else { corner };
+                let mut corner_block = center_block;
+                let mut corner_sky = center_sky;
+                for (cell_block, cell_sky, opaque) in [side_u, side_v, corner] {
+                    if !opaque {
+                        corner_block += cell_block;
+                        corner_sky += cell_sky;
+                    }
+                }
+                block += corner_block / 4.0 * wu * wv;
+                sky += corner_sky / 4.0 * wu * wv;
+            }
+        }
+        (block, sky)
+    }
+}
+
+// Minecraft's curve from light level to brightness, dim levels fall off quickly
+fn brightness(level: f32) -> f32 {
+    let fraction = level / MAX_LEVEL as f32;
+    MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * fraction / (4.0 - 3.0 * fraction)
+}
+
+// Fixed shading per face direction, so the sides of a block stay apart even in flat light
+fn face_shade(normal: &Vec3) -> f32 {
+    if normal.y > 0.5 {
+        1.0
+    } else if normal.y < -0.5 {
+        0.5
+    } else if normal.z.abs() > 0.5 {
+        0.8
+    } else {
+        0.6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::internal::entitiy::material::Material;
+
+    fn stone() -> Arc<Material> {
+        Arc::new(Material::black())
+    }
+
+    fn glowstone() -> Arc<Material> {
+        Arc::new(Material { emission: HdrColor::new(1.0, 1.0, 1.0), emission_strength: 1.0, ..Material::black() })
+    }
+
+    fn glass() -> Arc<Material> {
+        Arc::new(Material { transparency: 1.0, ..Material::black() })
+    }
+
+    // Block and sky level of a cell
+    fn levels(light_map: &LightMap, x: usize, y: usize, z: usize) -> (u8, u8) {
+        let index = light_map.index(x, y, z);
+        (light_map.block[index], light_map.sky[index])
+    }
+
+    #[test]
+    fn block_light_drops_by_one_per_cell() {
+        let mut grid = Grid::new(1.0, 3, 11, 11);
+        grid.add_cube(5, 1, 5, glowstone());
+        let light_map = LightMap::new(&grid);
+        for z in 0..11usize {
+            for x in 0..11usize {
+                let distance = x.abs_diff(5) + z.abs_diff(5);
+                assert_eq!(levels(&light_map, x, 1, z).0 as usize, 15 - distance, "at {},{}", x, z);
+            }
+        }
+        assert_eq!(levels(&light_map, 5, 0, 5).0, 14);
+        assert_eq!(levels(&light_map, 4, 2, 6).0, 12);
+    }
+
+    #[test]
+    fn block_light_goes_around_walls_and_through_glass() {
+        // A wall at x = 2 with a gap at z = 2, the light has to go around it
+        let mut grid = Grid::new(1.0, 1, 5, 3);
+        grid.add_cube(0, 0, 1, glowstone());
+        grid.add_cube(2, 0, 0, stone());
+        grid.add_cube(2, 0, 1, stone());
+        let light_map = LightMap::new(&grid);
+        assert_eq!(levels(&light_map, 1, 0, 1).0, 14);
+        assert_eq!(levels(&light_map, 2, 0, 1).0, 0);
+        assert_eq!(levels(&light_map, 3, 0, 1).0, 10);
+
+        // The same wall made of glass lets it straight through
+        let mut grid = Grid::new(1.0, 1, 5, 3);
+        grid.add_cube(0, 0, 1, glowstone());
+        grid.add_cube(2, 0, 0, glass());
+        grid.add_cube(2, 0, 1, glass());
+        let light_map = LightMap::new(&grid);
+        assert_eq!(levels(&light_map, 3, 0, 1).0, 12);
+    }
+
+    #[test]
+    fn sky_light_fills_open_columns_and_spreads_under_roofs() {
+        let mut grid = Grid::new(1.0, 4, 5, 5);
+        grid.add_cube(2, 3, 2, stone());
+        grid.add_cube(1, 3, 1, glass());
+        // A cell walled in on all six sides gets no light at all
+        for (x, y, z) in [(3, 0, 3), (3, 2, 3), (2, 1, 3), (4, 1, 3), (3, 1, 2), (3, 1, 4)] {
+            grid.add_cube(x, y, z, stone());
+        }
+        let light_map = LightMap::new(&grid);
+
+        assert_eq!(levels(&light_map, 0, 0, 0).1, 15);
+        assert_eq!(levels(&light_map, 1, 0, 1).1, 15);
+        assert_eq!(levels(&light_map, 2, 3, 2).1, 0);
+        assert_eq!(levels(&light_map, 2, 2, 2).1, 14);
+        assert_eq!(levels(&light_map, 3, 1, 3), (0, 0));
+    }
+
+    #[test]
+    fn faces_in_full_daylight_are_fully_lit() {
+        let mut grid = Grid::new(1.0, 2, 3, 3);
+        grid.add_cube(1, 0, 1, stone());
+        let light_map = LightMap::new(&grid);
+        let top = Vec3::new(0.0, 0.0, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+
+        let day = light_map.light(&top, &up, false, 1.0);
+        assert_eq!((day.r, day.g, day.b), (1.0, 1.0, 1.0));
+        let smooth_day = light_map.light(&top, &up, true, 1.0);
+        assert!((smooth_day.r - 1.0).abs() < 1e-6);
+
+        let night = light_map.light(&top, &up, false, 0.0);
+        let expected = NIGHT_SKY_LIGHT + BLOCK_LIGHT * MIN_BRIGHTNESS;
+        assert!((night.r - expected.r).abs() < 1e-6 && (night.b - expected.b).abs() < 1e-6);
+
+        // The sides are shaded down even in full light
+        let side = light_map.light(&Vec3::new(0.5, -0.5, 0.0), &Vec3::new(1.0, 0.0, 0.0), false, 1.0);
+        assert!((side.r - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn brightness_curve() {
+        assert_eq!(brightness(0.0), MIN_BRIGHTNESS);
+        assert!((brightness(15.0) - 1.0).abs() < 1e-6);
+        for level in 1..=15 {
+            assert!(brightness(level as f32) > brightness(level as f32 - 1.0));
+        }
+    }
+}
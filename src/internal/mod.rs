@@ -1,4 +1,19 @@
 pub mod framebuffer;
+pub mod export;
 pub mod render;
+pub mod pathtracer;
+pub mod tonemap;
+pub mod sampling;
+pub mod sky;
+pub mod environment;
+pub mod lightmap;
+pub mod bvh;
+pub mod world;
 pub mod entitiy;
 pub mod camera;
+pub mod resource_pack;
+pub mod block_model;
+pub mod scene;
+pub mod cli;
+#[cfg(test)]
+pub mod temp_dir;
@@ -0,0 +1,101 @@
+use nalgebra_glm::Vec3;
+
+use super::entitiy::color::HdrColor;
+use super::entitiy::object::Object;
+use super::render::{direct_light, filter_texture, offset_origin, reflect, refract, surface_shares, RayCone, RenderSettings};
+use super::sampling::{cosine_hemisphere, Rng};
+use super::world::World;
+
+const MAX_BOUNCES: u32 = 8;
+const ROULETTE_START: u32 = 3; // bounces every path gets before it may be cut short
+const MAX_SURVIVAL: f32 = 0.95;
+
+// Follows one path of light backwards from the camera. At every hit the light sources are sampled
+// directly (next-event estimation), then the path goes on in a single direction: a mirror reflection,
+// a refraction, or a cosine-weighted bounce off the diffuse surface. Bounced light is what lights
+// the corners the sun never reaches, so the ambient term isn't used here.
+pub fn trace_path(origin: &Vec3, direction: &Vec3, cone: &RayCone, world: &World, settings: &RenderSettings, rng: &mut Rng) -> HdrColor {
+    let mut radiance = HdrColor::black();
+    let mut throughput = HdrColor::new(1.0, 1.0, 1.0);
+    let mut origin = *origin;
+    let mut direction = *direction;
+    let mut cone = *cone;
+    // After a diffuse bounce the light sources were already sampled at the last hit,
+    // so hitting them again would count their light twice
+    let mut sampled_lights = false;
+
+    for bounce in 0..MAX_BOUNCES {
+        let mut intersect = world.objects.ray_intersect(&origin, &direction);
+        if !intersect.is_intersecting {
+            let background = match (&world.environment, sampled_lights) {
+                (Some(_), true) => HdrColor::black(),
+                (Some(environment), false) => environment.radiance(&direction),
+                (None, true) => world.day_light.sky_color(&direction),
+                (None, false) => world.day_light.background_color(&direction),
+            };
+            radiance = radiance + throughput * background;
+            break;
+        }
+
+        filter_texture(&mut intersect, &direction, &cone, settings.texture_filter);
+        cone = cone.at(intersect.distance);
+
+        let material = &intersect.material;
+        // Alpha blended texels are passed straight through as often as they are see-through
+        if intersect.alpha < 1.0 && rng.next_f32() >= intersect.alpha {
+            origin = offset_origin(&intersect, &direction);
+            continue;
+        }
+
+        // Leaving a block from the inside, stained glass took its share of the light on the way through
+        if direction.dot(&intersect.normal) > 0.0 {
+            throughput = throughput * material.transmittance(intersect.distance);
+        }
+        if !sampled_lights {
+            radiance = radiance + throughput * material.emitted(intersect.color);
+        }
+
+        let (surface, reflectivity, transparency) = surface_shares(&direction, &intersect);
+        let view_dir = -direction;
+        if surface > 0.0 {
+            let direct = direct_light(&intersect, &view_dir, world, settings, rng);
+            radiance = radiance + throughput * direct * surface;
+        }
+
+        // Pick where the path goes on in proportion to how much light each way carries
+        let diffuse = intersect.color * (material.albedo[0] * surface);
+        let diffuse_weight = diffuse.luminance();
+        let total = reflectivity + transparency + diffuse_weight;
+        if total <= 0.0 {
+            break;
+        }
+
+        let pick = rng.next_f32() * total;
+        if pick < reflectivity {
+            direction = reflect(&direction, &intersect.normal).normalize();
+            throughput = throughput * total;
+            sampled_lights = false;
+        } else if pick < reflectivity + transparency {
+            direction = refract(&direction, &intersect.normal, material.refractive_index).normalize();
+            throughput = throughput * total;
+            sampled_lights = false;
+        } else {
+            // Lambert's cos / PI over the cosine-weighted density leaves just the surface color
+            direction = cosine_hemisphere(&intersect.normal, rng);
+            throughput = throughput * diffuse * (total / diffuse_weight);
+            sampled_lights = true;
+        }
+        origin = offset_origin(&intersect, &direction);
+
+        // Russian roulette: dim paths stop early, the survivors carry their share
+        if bounce >= ROULETTE_START {
+            let survival = throughput.r.max(throughput.g).max(throughput.b).min(MAX_SURVIVAL);
+            if rng.next_f32() >= survival {
+                break;
+            }
+            throughput = throughput * (1.0 / survival);
+        }
+    }
+
+    radiance
+}
@@ -2,147 +2,492 @@ use rayon::prelude::*;
 
 use nalgebra_glm::Vec3;
 use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
 
+use super::bvh::Bvh;
 use super::camera::Camera;
+use super::environment::Environment;
 use super::framebuffer::Framebuffer;
-use super::entitiy::color::Color;
+use super::pathtracer::trace_path;
+use super::entitiy::color::{Color, HdrColor};
 use super::entitiy::intersect::Intersect;
 use super::entitiy::object::Object;
-use super::entitiy::light::{AmbientLight, DayLight, Light};
+use super::entitiy::light::{AreaLight, Light};
+use super::entitiy::texture::TextureFilter;
+use super::sampling::{cosine_hemisphere, PixelFilter, Rng, SamplePattern};
+use super::tonemap::ToneMapping;
+use super::world::World;
 
-const REFLECTION_DEPTH: u32 = 3;
 const ORIGIN_BIAS: f32 = 1e-4;
+const SHADOW_EPSILON: f32 = 1e-3;
+const MAX_SHADOW_CROSSINGS: u32 = 16; // glass surfaces a shadow ray goes through before giving up
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub samples: u32,              // rays traced per pixel
+    pub pattern: SamplePattern,    // where those rays cross the pixel
+    pub filter: PixelFilter,       // how the samples are weighed into the pixel color
+    pub seed: u64,                 // same seed, same image
+    pub shadow_samples: u32,       // shadow rays towards each light that has a size
+    pub tone_mapping: ToneMapping, // how radiance above 1 is brought into display range
+    pub exposure: f32,             // in stops, applied before tone mapping
+    pub integrator: Integrator,    // how the light arriving along each ray is computed
+    pub ao_samples: u32,           // rays looking for nearby blocks that hide the ambient light, 0 turns it off
+    pub ao_distance: f32,          // blocks farther away than this don't occlude
+    pub shading: Shading,          // how the Whitted renderer lights the surfaces it hits
+    pub texture_filter: TextureFilter, // how textures are sampled where a texel is smaller than a pixel
+    pub bounces: u32,              // reflections and refractions traced after the camera ray
+}
+
+// The width of the bundle of rays one pixel stands for, growing with the distance travelled. It
+// tells how much of a texture a pixel covers where a ray hits, to pick the mip level.
+#[derive(Debug, Clone, Copy)]
+pub struct RayCone {
+    pub width: f32,  // at the ray origin
+    pub spread: f32, // added to the width per unit of distance, the angle of a pixel for camera rays
+}
 
-pub fn cast_ray(
-    ray_origin: &Vec3,
-    ray_direction: &Vec3,
-    objects: &[Box<dyn Object + Sync>],
-    lights: &[Box<dyn Light + Sync>],
-    day_light: &DayLight,
-    ambient_light: &AmbientLight,
-    depth: u32,
-) -> Color {
-    if depth > 3 {
-        return Color::new(25, 25, 120);
+impl RayCone {
+    // The cone again `distance` further along, where a reflected or refracted ray starts
+    pub fn at(&self, distance: f32) -> RayCone {
+        RayCone { width: self.width + self.spread * distance, spread: self.spread }
     }
+}
 
-    let mut intersect = Intersect::empty();
+// The two ways a ray's color can be computed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    Whitted,    // direct light, mirror reflection and refraction, ambient for the rest; fast
+    PathTracer, // also light bouncing between diffuse surfaces; noisy until many passes add up
+    AmbientOcclusion, // only the ambient occlusion term in grey, to inspect it
+}
 
-    for object in objects {
-        let i = object.ray_intersect(ray_origin, ray_direction);
-        if i.is_intersecting && i.distance < intersect.distance {
-            intersect = i;
+impl Integrator {
+    pub const ALL: [Integrator; 3] = [Integrator::Whitted, Integrator::PathTracer, Integrator::AmbientOcclusion];
+
+    // The integrator after this one, used to switch between them in the viewer
+    pub fn next(self) -> Integrator {
+        let index = Integrator::ALL.iter().position(|&integrator| integrator == self).unwrap();
+        Integrator::ALL[(index + 1) % Integrator::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Integrator::Whitted => "whitted",
+            Integrator::PathTracer => "path",
+            Integrator::AmbientOcclusion => "ao",
         }
     }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
 
-    if !intersect.is_intersecting {
-        return day_light.calculate_background_color(day_light.day_angle);
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Integrator, String> {
+        Integrator::ALL
+            .iter()
+            .find(|integrator| integrator.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("unknown integrator `{}`, expected whitted, path or ao", name))
+    }
+}
+
+// How surfaces are lit by the Whitted renderer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shading {
+    Raytraced,         // ambient light plus every light source, with shadow rays
+    LightLevels,       // Minecraft's block and sky light levels, flat across each face
+    SmoothLightLevels, // the same levels blended across face corners, like smooth lighting
+}
+
+impl Shading {
+    pub const ALL: [Shading; 3] = [Shading::Raytraced, Shading::LightLevels, Shading::SmoothLightLevels];
+
+    // The shading after this one, used to switch between them in the viewer
+    pub fn next(self) -> Shading {
+        let index = Shading::ALL.iter().position(|&shading| shading == self).unwrap();
+        Shading::ALL[(index + 1) % Shading::ALL.len()]
     }
 
-    // Start with ambient light contribution (scaled by ambient intensity)
-    let mut final_color = intersect.color * ambient_light.intensity;
+    pub fn name(self) -> &'static str {
+        match self {
+            Shading::Raytraced => "raytraced",
+            Shading::LightLevels => "levels",
+            Shading::SmoothLightLevels => "smooth",
+        }
+    }
+}
 
-    // Iterate over each light and accumulate contributions
-    let view_dir = (ray_origin - intersect.point).normalize();
-    for light in lights {
-        // Calculate diffuse and specular light for each light source
-        let light_dir = (light.get_position() - intersect.point).normalize();
-        let reflect_dir = reflect(&-light_dir, &intersect.normal); // Reflect direction calculated per light
+impl fmt::Display for Shading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
 
-        let shadow_intensity = cast_shadow(&intersect, light, objects);
-        let light_intensity = light.get_intensity() * (1.0 - shadow_intensity);
+impl FromStr for Shading {
+    type Err = String;
 
-        let diffuse_intensity = intersect.normal.dot(&light_dir).clamp(0.0, 1.0);
-        let diffuse = intersect.color * intersect.material.albedo[0] * diffuse_intensity * light_intensity;
+    fn from_str(name: &str) -> Result<Shading, String> {
+        Shading::ALL
+            .iter()
+            .find(|shading| shading.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("unknown shading `{}`, expected raytraced, levels or smooth", name))
+    }
+}
 
-        let specular_intensity = view_dir.dot(&reflect_dir).max(0.0).powf(intersect.material.specular);
-        let specular = light.get_color() * intersect.material.albedo[1] * specular_intensity * light_intensity;
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            samples: 1,
+            pattern: SamplePattern::Grid,
+            filter: PixelFilter::Box,
+            seed: 0,
+            shadow_samples: 4,
+            tone_mapping: ToneMapping::Aces,
+            exposure: 0.0,
+            integrator: Integrator::Whitted,
+            ao_samples: 8,
+            ao_distance: 2.0,
+            shading: Shading::Raytraced,
+            texture_filter: TextureFilter::Nearest,
+            bounces: 0,
+        }
+    }
+}
 
-        // Add diffuse and specular components to the final color
-        final_color = final_color + diffuse + specular;
+pub fn cast_ray(ray_origin: &Vec3, ray_direction: &Vec3, cone: &RayCone, world: &World, settings: &RenderSettings, depth: u32, rng: &mut Rng) -> HdrColor {
+    // Camera rays have depth 0, rays past the last bounce see a flat dark blue. Seeing through a
+    // pane takes two bounces, one into the glass and one out of it.
+    if depth > settings.bounces {
+        return Color::new(25, 25, 120).into();
     }
-    
-    // Calculate DayLight contribution
-    let day_light_dir = (day_light.get_position() - intersect.point).normalize();
-    let day_light_shadow_intensity = cast_day_shadow(&intersect, day_light, objects);
-    let day_light_intensity = day_light.get_intensity() * (1.0 - day_light_shadow_intensity);
 
-    let day_light_diffuse_intensity = intersect.normal.dot(&day_light_dir).clamp(0.0, 1.0);
-    let day_light_diffuse = intersect.color * intersect.material.albedo[0] * day_light_diffuse_intensity * day_light_intensity;
+    let World { objects, ambient_light, day_light, environment, .. } = world;
+    let mut intersect = objects.ray_intersect(ray_origin, ray_direction);
 
-    let day_light_specular_intensity = view_dir.dot(&reflect(&-day_light_dir, &intersect.normal)).max(0.0).powf(intersect.material.specular);
-    let day_light_specular = day_light.get_color() * intersect.material.albedo[1] * day_light_specular_intensity * day_light_intensity;
+    if !intersect.is_intersecting {
+        return match environment {
+            Some(environment) => environment.radiance(ray_direction),
+            None => day_light.background_color(ray_direction),
+        };
+    }
+    filter_texture(&mut intersect, ray_direction, cone, settings.texture_filter);
+    let cone = cone.at(intersect.distance);
+
+    let final_color = match settings.shading {
+        Shading::Raytraced => {
+            // Ambient light contribution, tinted by the ambient color and darkened in corners, then every light source
+            let view_dir = (ray_origin - intersect.point).normalize();
+            let ambient_visibility = 1.0 - ambient_occlusion(&intersect, objects, settings, rng);
+            intersect.color * ambient_light.color * (ambient_light.intensity * ambient_visibility)
+                + direct_light(&intersect, &view_dir, world, settings, rng)
+        }
+        Shading::LightLevels | Shading::SmoothLightLevels => {
+            // Light levels already know where light reaches, no shadow rays needed
+            let smooth = settings.shading == Shading::SmoothLightLevels;
+            let daylight = 1.0 - day_light.sky.darkness();
+            intersect.color * world.light_map.light(&intersect.point, &intersect.normal, smooth, daylight)
+        }
+    };
 
-    // Add DayLight contributions to final color
-    final_color = final_color + day_light_diffuse + day_light_specular;
+    // Glass splits what isn't lit on the surface between reflection and refraction by the
+    // Fresnel term, the shares always add up to 1 so it can't get brighter than its surroundings
+    let material = intersect.material;
+    let (surface, reflectivity, transparency) = surface_shares(ray_direction, &intersect);
+    // Reflections and refractions are seen dimmed, one shadow ray and one occlusion ray each is plenty
+    let bounce_settings = RenderSettings { shadow_samples: 1, ao_samples: settings.ao_samples.min(1), ..*settings };
 
-    // Calculate reflection (move reflect calculation outside of the loop)
-    let mut reflect_color = Color::new(0, 0, 0);
-    let reflectivity = intersect.material.reflectivity;
+    // Calculate reflection
+    let mut reflect_color = HdrColor::black();
     if reflectivity > 0.0 {
-        let reflect_dir = reflect(&-ray_direction, &intersect.normal).normalize(); // Now using ray direction for reflection
-        let reflect_origin = intersect.point;
-        reflect_color = cast_ray(&reflect_origin, &reflect_dir, objects, lights, day_light, ambient_light, depth + 1);
+        let reflect_dir = reflect(ray_direction, &intersect.normal).normalize();
+        let reflect_origin = offset_origin(&intersect, &reflect_dir);
+        reflect_color = cast_ray(&reflect_origin, &reflect_dir, &cone, world, &bounce_settings, depth + 1, rng);
     }
 
     // Calculate refraction
-    let mut refract_color = Color::new(0, 0, 0);
-    let transparency = intersect.material.transparency;
+    let mut refract_color = HdrColor::black();
     if transparency > 0.0 {
-        let refract_dir = refract(ray_direction, &intersect.normal, intersect.material.refractive_index);
+        let refract_dir = refract(ray_direction, &intersect.normal, material.refractive_index);
         let refract_origin = offset_origin(&intersect, &refract_dir);
-        refract_color = cast_ray(&refract_origin, &refract_dir, objects, lights, day_light, ambient_light, depth + 1);
+        refract_color = cast_ray(&refract_origin, &refract_dir, &cone, world, &bounce_settings, depth + 1, rng);
     }
 
-    // Combine the results of lighting, reflection, and refraction
-    (final_color) * (1.0 - reflectivity)
-        + (reflect_color * reflectivity)
-        + (refract_color * transparency)
+    // Combine the results of lighting, reflection, and refraction, plus the light the surface gives off
+    let color = final_color * surface
+        + reflect_color * reflectivity
+        + refract_color * transparency
+        + material.emitted(intersect.color);
+
+    // Alpha blended texels only cover part of what is behind them
+    let color = if intersect.alpha < 1.0 {
+        let behind_origin = offset_origin(&intersect, ray_direction);
+        let behind = cast_ray(&behind_origin, ray_direction, &cone, world, &bounce_settings, depth + 1, rng);
+        color * intersect.alpha + behind * (1.0 - intersect.alpha)
+    } else {
+        color
+    };
+
+    // A ray leaving a block from the inside crossed it, stained glass absorbs some of the light on the way
+    if ray_direction.dot(&intersect.normal) > 0.0 {
+        color * material.transmittance(intersect.distance)
+    } else {
+        color
+    }
+}
+
+// Replaces the nearest texel a hit was found with by the texture filtered over the pixel's footprint
+pub fn filter_texture(intersect: &mut Intersect, ray_direction: &Vec3, cone: &RayCone, filter: TextureFilter) {
+    if filter == TextureFilter::Nearest {
+        return;
+    }
+    let Some(coords) = intersect.tex_coords else {
+        return;
+    };
+    // At a grazing angle the footprint stretches along the face by 1 / cos, a single mip level
+    // can't follow that, so take the mean of both directions rather than blur it all away
+    let cosine = ray_direction.dot(&intersect.normal).abs().max(1e-3);
+    let footprint = cone.at(intersect.distance).width / cosine.sqrt();
+    if let Some(color) = intersect.material.filtered_color(&coords, footprint, filter) {
+        intersect.color = color;
+    }
+}
+
+// How the light leaving a hit point divides between the lit surface, the mirror reflection and the
+// refraction. Opaque blocks reflect their `reflectivity`, the rest of the light that reaches glass
+// is reflected or let through as the Fresnel equations say.
+pub fn surface_shares(ray_direction: &Vec3, intersect: &Intersect) -> (f32, f32, f32) {
+    let material = intersect.material;
+    let mirror = material.reflectivity.clamp(0.0, 1.0);
+    let glass = material.transparency.clamp(0.0, 1.0) * (1.0 - mirror);
+    if glass <= 0.0 {
+        return (1.0 - mirror, mirror, 0.0);
+    }
+    let fresnel = fresnel(ray_direction, &intersect.normal, material.refractive_index);
+    (1.0 - mirror - glass, mirror + glass * fresnel, glass * (1.0 - fresnel))
 }
 
-pub fn render(
-    framebuffer: &mut Framebuffer,
-    objects: &[Box<dyn Object + Sync>],
-    camera: &Camera,
-    lights: &[Box<dyn Light + Sync>],
-    day_light: &DayLight,
-    ambient_light: &AmbientLight,
-) {
+// Light reaching the hit point straight from every light source, with shadows, as seen from `view_dir`.
+// An environment map brings its own sky and sun, so it lights the scene in place of the day light and the moon.
+pub fn direct_light(intersect: &Intersect, view_dir: &Vec3, world: &World, settings: &RenderSettings, rng: &mut Rng) -> HdrColor {
+    let World { objects, lights, area_lights, day_light, environment, .. } = world;
+    let mut color = HdrColor::black();
+
+    let sky_lights: &[&(dyn Light + Sync)] = if environment.is_some() { &[] } else { &[day_light, &day_light.moon] };
+    for light in lights.iter().map(|light| light.as_ref()).chain(sky_lights.iter().copied()) {
+        color = color + light_contribution(intersect, view_dir, light, objects, settings, rng);
+    }
+    if let Some(environment) = environment {
+        color = color + environment_contribution(intersect, view_dir, environment, objects, settings.shadow_samples, rng);
+    }
+
+    // Light from emissive blocks
+    color + area_light_contribution(intersect, view_dir, area_lights, objects, settings.shadow_samples, rng)
+}
+
+pub fn render(framebuffer: &mut Framebuffer, world: &World, camera: &Camera, settings: &RenderSettings) {
+    render_pass(framebuffer, world, camera, settings, 0);
+}
+
+// Renders the frame again and averages it into the earlier passes, for progressive rendering
+// while the camera is still. Pass 0 starts over.
+pub fn render_pass(framebuffer: &mut Framebuffer, world: &World, camera: &Camera, settings: &RenderSettings, pass: u32) {
     const FIELD_OF_VIEW: f32 = PI / 3.0;
     let perspective_scale: f32 = (FIELD_OF_VIEW / 2.0).tan();
 
     let width = framebuffer.width as f32;
     let height = framebuffer.height as f32;
     let aspect_ratio = width / height;
+    let camera_cone = RayCone { width: 0.0, spread: 2.0 * perspective_scale / height };
 
     // Process each row of the framebuffer in parallel
     framebuffer
-        .buffer
-        .par_chunks_mut(framebuffer.width as usize)
+        .hdr_buffer
+        .par_chunks_mut(framebuffer.width)
         .enumerate()
         .for_each(|(y, row)| {
-            // Map the pixel coordinate to screen space [-1, 1]
-            let screen_y = -(2.0 * y as f32) / height + 1.0;
-            let screen_y = screen_y * perspective_scale;
-
             row.iter_mut().enumerate().for_each(|(x, pixel)| {
-                // Map the pixel coordinate to screen space [-1, 1]
-                let screen_x = (2.0 * x as f32) / width - 1.0;
-                let screen_x = screen_x * aspect_ratio * perspective_scale;
-
-                // Calculate the direction of the ray for this pixel
-                let ray_direction = Vec3::new(screen_x, screen_y, -1.0).normalize();
-                let rotated_direction = camera.change_basis(&ray_direction);
-
-                // Cast the ray and get the pixel color
-                let pixel_color =
-                    cast_ray(&camera.eye, &rotated_direction, objects, lights, day_light, ambient_light, REFLECTION_DEPTH);
-                *pixel = pixel_color.to_hex(); // Convert color to u32 and assign to pixel
+                let trace = |offset_x: f32, offset_y: f32, rng: &mut Rng| {
+                    // Map the pixel coordinate to screen space [-1, 1]
+                    let screen_x = (2.0 * (x as f32 + offset_x)) / width - 1.0;
+                    let screen_x = screen_x * aspect_ratio * perspective_scale;
+                    let screen_y = -(2.0 * (y as f32 + offset_y)) / height + 1.0;
+                    let screen_y = screen_y * perspective_scale;
+
+                    // Calculate the direction of the ray for this sample
+                    let ray_direction = Vec3::new(screen_x, screen_y, -1.0).normalize();
+                    let rotated_direction = camera.change_basis(&ray_direction);
+
+                    match settings.integrator {
+                        Integrator::Whitted => cast_ray(&camera.eye, &rotated_direction, &camera_cone, world, settings, 0, rng),
+                        Integrator::PathTracer => trace_path(&camera.eye, &rotated_direction, &camera_cone, world, settings, rng),
+                        Integrator::AmbientOcclusion => trace_ambient_occlusion(&camera.eye, &rotated_direction, world, settings, rng),
+                    }
+                };
+
+                let mut rng = Rng::for_pixel(settings.seed.wrapping_add(pass as u64), x, y);
+                let color = if settings.samples == 1 {
+                    // A single Whitted sample keeps the pixel corner so one-sample renders stay unchanged,
+                    // progressive passes move it around the pixel instead
+                    if pass == 0 && settings.integrator == Integrator::Whitted {
+                        trace(0.0, 0.0, &mut rng)
+                    } else {
+                        let (offset_x, offset_y) = (rng.next_f32(), rng.next_f32());
+                        trace(offset_x, offset_y, &mut rng)
+                    }
+                } else {
+                    // Samples are spread over the filter footprint around the pixel center
+                    let radius = settings.filter.radius();
+                    let mut sum = HdrColor::black();
+                    let mut total_weight = 0.0;
+                    let mut unweighted = HdrColor::black();
+                    for (u, v) in settings.pattern.points(settings.samples, &mut rng) {
+                        let dx = (2.0 * u - 1.0) * radius;
+                        let dy = (2.0 * v - 1.0) * radius;
+                        let color = trace(0.5 + dx, 0.5 + dy, &mut rng);
+                        let weight = settings.filter.weight(dx, dy);
+                        sum = sum + color * weight;
+                        total_weight += weight;
+                        unweighted = unweighted + color;
+                    }
+
+                    if total_weight > 0.0 {
+                        sum * (1.0 / total_weight)
+                    } else {
+                        unweighted * (1.0 / settings.samples as f32)
+                    }
+                };
+
+                // Running mean over the passes so far
+                *pixel = if pass == 0 {
+                    color
+                } else {
+                    *pixel * (pass as f32 / (pass + 1) as f32) + color * (1.0 / (pass + 1) as f32)
+                };
             });
         });
+
+    framebuffer.tone_map(settings.tone_mapping, settings.exposure);
 }
 
-fn offset_origin(intersect: &Intersect, direction: &Vec3) -> Vec3 {
+// Diffuse and specular light from one source, tinted by its color and faded by its attenuation
+fn light_contribution(intersect: &Intersect, view_dir: &Vec3, light: &dyn Light, objects: &Bvh, settings: &RenderSettings, rng: &mut Rng) -> HdrColor {
+    let (light_dir, distance) = light.direction_from(&intersect.point);
+    let strength = light.get_intensity() * light.attenuation().factor(distance) * light.cone_factor(&-light_dir);
+    if strength <= 0.0 {
+        return HdrColor::black();
+    }
+
+    let reflect_dir = reflect(&-light_dir, &intersect.normal);
+    let diffuse_intensity = intersect.normal.dot(&light_dir).clamp(0.0, 1.0);
+    let specular_intensity = view_dir.dot(&reflect_dir).max(0.0).powf(intersect.material.specular);
+    if diffuse_intensity <= 0.0 && specular_intensity <= 0.0 {
+        return HdrColor::black(); // no need for shadow rays
+    }
+
+    // Light that made it past the blocks in the way, tinted by any glass it went through
+    let light_color = light.get_color() * cast_shadow(intersect, light, objects, settings.shadow_samples, rng);
+
+    let diffuse = intersect.color * light_color * (intersect.material.albedo[0] * diffuse_intensity * strength);
+    let specular = light_color * (intersect.material.albedo[1] * specular_intensity * strength);
+    diffuse + specular
+}
+
+// Light from the emissive blocks. Each sample picks one block by how much light it could send
+// here and a point on its surface, so a scene full of glowstone costs as much as a single block.
+fn area_light_contribution(intersect: &Intersect, view_dir: &Vec3, lights: &[AreaLight], objects: &Bvh, samples: u32, rng: &mut Rng) -> HdrColor {
+    if lights.is_empty() {
+        return HdrColor::black();
+    }
+    let origin = intersect.point + intersect.normal * ORIGIN_BIAS;
+    let weights: Vec<f32> = lights.iter().map(|light| light.importance(&origin, &intersect.normal)).collect();
+    let total: f32 = weights.iter().sum();
+    if total <= 0.0 {
+        return HdrColor::black();
+    }
+
+    let mut sum = HdrColor::black();
+    for _ in 0..samples {
+        let mut pick = rng.next_f32() * total;
+        let index = weights.iter().position(|&weight| {
+            pick -= weight;
+            pick < 0.0
+        });
+        // Rounding can leave the pick just past the last weight
+        let index = index.unwrap_or_else(|| weights.iter().rposition(|&weight| weight > 0.0).unwrap());
+        let light = &lights[index];
+
+        let (sample, sample_normal) = light.sample_surface(rng);
+        let to_light = sample - origin;
+        let distance = to_light.magnitude();
+        let light_dir = to_light / distance;
+
+        // Both the surface and the light face have to look at each other
+        let cos_surface = intersect.normal.dot(&light_dir);
+        let cos_light = -sample_normal.dot(&light_dir);
+        if cos_surface <= 0.0 || cos_light <= 0.0 {
+            continue;
+        }
+
+        // Anything opaque hit before the light's own surface blocks the sample, glass tints it
+        let transmitted = transmission(objects, &origin, &light_dir, distance - SHADOW_EPSILON);
+        if transmitted.luminance() <= 0.0 {
+            continue;
+        }
+
+        let geometry = cos_surface * cos_light / (distance * distance);
+        let diffuse = intersect.color * transmitted * (intersect.material.albedo[0] * geometry / PI);
+        let specular_intensity = view_dir.dot(&reflect(&-light_dir, &intersect.normal)).max(0.0).powf(intersect.material.specular);
+        let specular = transmitted * (intersect.material.albedo[1] * specular_intensity * geometry / PI);
+
+        // The point stands for the whole surface of the block, and the block for all of them
+        // in the odds it was picked with
+        let probability = weights[index] / total;
+        sum = sum + light.radiance * (diffuse + specular) * (light.area() / probability);
+    }
+
+    sum * (1.0 / samples as f32)
+}
+
+// Light from the environment map, estimated with shadow rays towards directions picked by its brightness
+fn environment_contribution(intersect: &Intersect, view_dir: &Vec3, environment: &Environment, objects: &Bvh, samples: u32, rng: &mut Rng) -> HdrColor {
+    let origin = intersect.point + intersect.normal * ORIGIN_BIAS;
+    let samples = samples.max(1);
+    let mut sum = HdrColor::black();
+
+    for _ in 0..samples {
+        let Some((direction, radiance, pdf)) = environment.sample(rng) else {
+            continue;
+        };
+        let cos_surface = intersect.normal.dot(&direction);
+        if cos_surface <= 0.0 {
+            continue;
+        }
+        let transmitted = transmission(objects, &origin, &direction, f32::INFINITY);
+        if transmitted.luminance() <= 0.0 {
+            continue;
+        }
+
+        // Same diffuse and Phong lobes as the emissive blocks, weighed by how likely the direction was
+        let specular_intensity = view_dir.dot(&reflect(&-direction, &intersect.normal)).max(0.0).powf(intersect.material.specular);
+        let diffuse = intersect.color * intersect.material.albedo[0];
+        let specular = intersect.material.albedo[1] * specular_intensity;
+        let reflected = diffuse + HdrColor::new(specular, specular, specular);
+        sum = sum + radiance * transmitted * reflected * (cos_surface / (PI * pdf));
+    }
+
+    sum * (1.0 / samples as f32)
+}
+
+pub fn offset_origin(intersect: &Intersect, direction: &Vec3) -> Vec3 {
     let offset = intersect.normal * ORIGIN_BIAS;
     if direction.dot(&intersect.normal) < 0.0 {
         intersect.point - offset
@@ -151,24 +496,24 @@ fn offset_origin(intersect: &Intersect, direction: &Vec3) -> Vec3 {
     }
 }
 
-fn reflect(incident: &Vec3, normal: &Vec3) -> Vec3 {
+pub fn reflect(incident: &Vec3, normal: &Vec3) -> Vec3 {
     incident - 2.0 * incident.dot(normal) * normal
 }
 
-fn refract(incident: &Vec3, normal: &Vec3, eta_t: f32) -> Vec3 {
+pub fn refract(incident: &Vec3, normal: &Vec3, eta_t: f32) -> Vec3 {
     let cosi = -incident.dot(normal).clamp(-1.0, 1.0); // Clamp cosine of incident angle between -1 and 1
     let (n_cosi, eta, n_normal);
 
-    if cosi < 0.0 {
-        // Ray is entering the object
-        n_cosi = -cosi;
+    if cosi > 0.0 {
+        // Ray is entering the object, against the outward normal
+        n_cosi = cosi;
         eta = 1.0 / eta_t; // Refractive index ratio for entering the material
-        n_normal = -normal;
+        n_normal = *normal;
     } else {
         // Ray is leaving the object
-        n_cosi = cosi;
+        n_cosi = -cosi;
         eta = eta_t;  // Assume the ray is exiting into air or vacuum (n=1)
-        n_normal = *normal;
+        n_normal = -normal;
     }
 
     let k = 1.0 - eta * eta * (1.0 - n_cosi * n_cosi);
@@ -181,53 +526,326 @@ fn refract(incident: &Vec3, normal: &Vec3, eta_t: f32) -> Vec3 {
     }
 }
 
-fn cast_shadow(intersect: &Intersect, light: &Box<dyn Light + Sync>, objects: &[Box<dyn Object + Sync>]) -> f32 {
-    let light_dir = (light.get_position() - intersect.point).normalize();
-    let shadow_ray_origin = intersect.point + intersect.normal * 1e-4; // Avoid self-shadowing bias
-    let light_distance = (light.get_position() - shadow_ray_origin).magnitude(); // Distance from light to intersection
+// Share of the light a surface with refractive index `eta_t` reflects, the rest goes through.
+// Fresnel equations for unpolarized light, 1 under total internal reflection.
+pub fn fresnel(incident: &Vec3, normal: &Vec3, eta_t: f32) -> f32 {
+    let cosi = -incident.dot(normal).clamp(-1.0, 1.0);
+    let (eta_i, eta_t, cosi) = if cosi > 0.0 { (1.0, eta_t, cosi) } else { (eta_t, 1.0, -cosi) };
 
-    let mut shadow_intensity = 0.0; // Start with no shadow
+    let sint = eta_i / eta_t * (1.0 - cosi * cosi).max(0.0).sqrt();
+    if sint >= 1.0 {
+        return 1.0;
+    }
+    let cost = (1.0 - sint * sint).max(0.0).sqrt();
+    let perpendicular = (eta_t * cosi - eta_i * cost) / (eta_t * cosi + eta_i * cost);
+    let parallel = (eta_i * cosi - eta_t * cost) / (eta_i * cosi + eta_t * cost);
+    (perpendicular * perpendicular + parallel * parallel) / 2.0
+}
 
-    for object in objects {
-        let shadow_intersect = object.ray_intersect(&shadow_ray_origin, &light_dir);
-        if shadow_intersect.is_intersecting {
-            // Compute the distance from the intersection to the shadow-casting object
-            let occlusion_distance = shadow_intersect.distance;
+// Fraction of the ambient light hidden by blocks around the hit point, 0 out in the open and 1 deep in a corner.
+// Rays leave the surface in a cosine-weighted hemisphere and are blocked by what they hit closer
+// than `ao_distance`, so far away walls don't darken the floor. Glass and holes in leaves only
+// hide what they don't let through, like they do for shadows.
+fn ambient_occlusion(intersect: &Intersect, objects: &Bvh, settings: &RenderSettings, rng: &mut Rng) -> f32 {
+    if settings.ao_samples == 0 {
+        return 0.0;
+    }
+    let origin = intersect.point + intersect.normal * ORIGIN_BIAS;
 
-            // Calculate shadow attenuation based on the distance between the object and the light source
-            let attenuation = (occlusion_distance / light_distance).clamp(0.0, 1.0); // The farther the object, the smaller the shadow
+    let mut occluded = 0.0;
+    for _ in 0..settings.ao_samples {
+        let direction = cosine_hemisphere(&intersect.normal, rng);
+        occluded += 1.0 - transmission(objects, &origin, &direction, settings.ao_distance).luminance();
+    }
+
+    occluded / settings.ao_samples as f32
+}
+
+// The ambient occlusion term on its own, white where nothing is hidden and black where all of it is
+fn trace_ambient_occlusion(origin: &Vec3, direction: &Vec3, world: &World, settings: &RenderSettings, rng: &mut Rng) -> HdrColor {
+    let intersect = world.objects.ray_intersect(origin, direction);
+    if !intersect.is_intersecting {
+        return HdrColor::new(1.0, 1.0, 1.0);
+    }
+    let visibility = 1.0 - ambient_occlusion(&intersect, &world.objects, settings, rng);
+    HdrColor::new(visibility, visibility, visibility)
+}
+
+// Share of the light reaching the hit point, white when fully lit and black in full shadow.
+// Lights with a size are sampled at several points, so shadows soften into a penumbra
+// that widens the farther the occluder is from the surface.
+fn cast_shadow(intersect: &Intersect, light: &dyn Light, objects: &Bvh, samples: u32, rng: &mut Rng) -> HdrColor {
+    let shadow_ray_origin = intersect.point + intersect.normal * ORIGIN_BIAS; // Avoid self-shadowing bias
+    let samples = if light.is_soft() { samples.max(1) } else { 1 };
+
+    let mut lit = HdrColor::black();
+    for _ in 0..samples {
+        // Only blocks between the surface and the light cast a shadow, lights without a
+        // position are infinitely far so anything along the ray does
+        let (light_dir, light_distance) = light.sample_direction(&shadow_ray_origin, rng);
+        lit = lit + transmission(objects, &shadow_ray_origin, &light_dir, light_distance);
+    }
+
+    lit * (1.0 / samples as f32)
+}
+
+// Light left after going `distance` from `origin` along `direction`. Opaque blocks stop it, glass
+// lets its share through at each surface, tinted by what it absorbs inside. Shadow rays go
+// straight through instead of bending, which keeps them cheap.
+fn transmission(objects: &Bvh, origin: &Vec3, direction: &Vec3, distance: f32) -> HdrColor {
+    let mut transmitted = HdrColor::new(1.0, 1.0, 1.0);
+    let mut origin = *origin;
+    let mut remaining = distance;
+
+    for _ in 0..MAX_SHADOW_CROSSINGS {
+        let hit = objects.ray_intersect(&origin, direction);
+        if !hit.is_intersecting || hit.distance >= remaining {
+            return transmitted;
+        }
 
-            // Modify shadow intensity based on the object's distance
-            shadow_intensity = 1.0 * attenuation; // Full shadow intensity when close, less when far
-            break;
+        // Both surfaces of a pane let through what light entering it would, an unbent ray
+        // leaving it could otherwise be reflected back inside
+        let leaving = direction.dot(&hit.normal) > 0.0;
+        let surface = Intersect { normal: if leaving { -hit.normal } else { hit.normal }, ..hit };
+        // Holes in blended texels let light through untouched, the covered part acts like the block
+        let (_, _, transparency) = surface_shares(direction, &surface);
+        let passing = (1.0 - hit.alpha) + hit.alpha * transparency;
+        if passing <= 0.0 {
+            return HdrColor::black();
         }
+        transmitted = transmitted * passing;
+        if leaving {
+            transmitted = transmitted * hit.material.transmittance(hit.distance);
+        }
+
+        origin = offset_origin(&hit, direction);
+        remaining -= hit.distance;
     }
 
-    shadow_intensity
+    HdrColor::black()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::internal::entitiy::cube::Cube;
+    use crate::internal::entitiy::grid::Grid;
+    use crate::internal::entitiy::material::Material;
+
+    const GLASS_INDEX: f32 = 1.5;
+
+    fn glass(transparency: f32, reflectivity: f32) -> Material {
+        Material { transparency, reflectivity, refractive_index: GLASS_INDEX, ..Material::black() }
+    }
 
-fn cast_day_shadow(intersect: &Intersect, light: &DayLight, objects: &[Box<dyn Object + Sync>]) -> f32 {
-    let light_dir = (light.get_position() - intersect.point).normalize();
-    let shadow_ray_origin = intersect.point + intersect.normal * 1e-4; // Avoid self-shadowing bias
-    let light_distance = (light.get_position() - shadow_ray_origin).magnitude(); // Distance from light to intersection
+    // Incoming direction at `angle` from the normal (0, 1, 0), arriving from above
+    fn incoming(angle: f32) -> Vec3 {
+        Vec3::new(angle.sin(), -angle.cos(), 0.0)
+    }
+
+    #[test]
+    fn fresnel_matches_known_values() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        // Head on, ((n - 1) / (n + 1))^2
+        assert!((fresnel(&incoming(0.0), &up, GLASS_INDEX) - 0.04).abs() < 1e-5);
+        // More and more is reflected towards grazing angles
+        let mut last = 0.0;
+        for step in 0..9 {
+            let reflected = fresnel(&incoming(step as f32 * 0.19), &up, GLASS_INDEX);
+            assert!(reflected >= last);
+            last = reflected;
+        }
+        assert!(fresnel(&incoming(1.5699), &up, GLASS_INDEX) > 0.99);
+        // Inside the glass past the critical angle everything is reflected
+        let critical = (1.0 / GLASS_INDEX).asin();
+        let leaving = -incoming(critical + 0.05);
+        assert_eq!(fresnel(&leaving, &up, GLASS_INDEX), 1.0);
+        assert!(fresnel(&-incoming(critical - 0.05), &up, GLASS_INDEX) < 1.0);
+    }
 
-    let mut shadow_intensity = 0.0; // Start with no shadow
+    #[test]
+    fn refraction_follows_snells_law() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        for angle in [0.1f32, 0.5, 1.0, 1.4] {
+            let refracted = refract(&incoming(angle), &up, GLASS_INDEX);
+            assert!(refracted.y < 0.0);
+            let sine = refracted.x / refracted.norm();
+            assert!((angle.sin() - GLASS_INDEX * sine).abs() < 1e-5);
+            // Out through the bottom of a slab, parallel to the way it came in
+            let out = refract(&refracted.normalize(), &-up, GLASS_INDEX);
+            assert!((out.normalize() - incoming(angle)).norm() < 1e-4);
+        }
+    }
 
-    for object in objects {
-        let shadow_intersect = object.ray_intersect(&shadow_ray_origin, &light_dir);
-        if shadow_intersect.is_intersecting {
-            // Compute the distance from the intersection to the shadow-casting object
-            let occlusion_distance = shadow_intersect.distance;
+    #[test]
+    fn surface_shares_conserve_energy() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        for (transparency, reflectivity) in [(0.0, 0.0), (0.0, 0.2), (0.5, 0.0), (0.9, 0.1), (1.0, 0.0), (2.0, 0.0)] {
+            let material = glass(transparency, reflectivity);
+            for angle in [0.0f32, 0.7, 1.5] {
+                for direction in [incoming(angle), -incoming(angle)] {
+                    let intersect = Intersect::new(Vec3::zeros(), up, 1.0, &material, HdrColor::black());
+                    let (surface, reflected, transmitted) = surface_shares(&direction, &intersect);
+                    assert!(surface >= 0.0 && reflected >= 0.0 && transmitted >= 0.0);
+                    assert!((surface + reflected + transmitted - 1.0).abs() < 1e-5);
+                    if transparency == 0.0 {
+                        assert_eq!((reflected, transmitted), (reflectivity, 0.0));
+                    }
+                }
+            }
+        }
+        // Transparency over 1 is clear glass, nothing is left for the surface color
+        let clear = glass(2.0, 0.0);
+        let intersect = Intersect::new(Vec3::zeros(), up, 1.0, &clear, HdrColor::black());
+        let (surface, reflected, _) = surface_shares(&incoming(0.0), &intersect);
+        assert_eq!(surface, 0.0);
+        assert!((reflected - 0.04).abs() < 1e-5);
+    }
 
-            // Calculate shadow attenuation based on the distance between the object and the light source
-            let attenuation = (occlusion_distance / light_distance).clamp(0.0, 1.0); // The farther the object, the smaller the shadow
+    #[test]
+    fn stained_glass_tints_by_thickness() {
+        let material = Material { absorption: HdrColor::new(0.1, 0.5, 1.0), ..glass(1.0, 0.0) };
+        let thin = material.transmittance(1.0);
+        let thick = material.transmittance(2.0);
+        assert!((thin.r - (-0.1f32).exp()).abs() < 1e-6);
+        assert!((thick.b - thin.b * thin.b).abs() < 1e-6);
+        assert!(thin.r > thin.g && thin.g > thin.b);
+        let clear = glass(1.0, 0.0).transmittance(5.0);
+        assert_eq!((clear.r, clear.g, clear.b), (1.0, 1.0, 1.0));
+    }
 
-            // Modify shadow intensity based on the object's distance
-            shadow_intensity = 1.0 * attenuation; // Full shadow intensity when close, less when far
-            break;
+    // A row of four blocks along x, from -2 to 2, with blocks in the cells of `placed` in that order
+    fn row(placed: &[usize]) -> (Bvh, Vec<Cube>) {
+        let mut grid = Grid::new(1.0, 1, 4, 1);
+        let mut cubes = Vec::new();
+        for &x in placed {
+            let material = Arc::new(Material::black());
+            grid.add_cube(x, 0, 0, Arc::clone(&material));
+            let min = Vec3::new(x as f32 - 2.0, -0.5, -0.5);
+            cubes.push(Cube::new(min, min + Vec3::new(1.0, 1.0, 1.0), material));
         }
+        (Bvh::new(vec![Box::new(grid)]), cubes)
     }
 
-    shadow_intensity
+    // Shadow strength when the first cube in placement order the ray touches decides it, wherever
+    // that cube is along the ray, scaled by its distance over the light's
+    fn first_cube_shadow(cubes: &[Cube], origin: &Vec3, direction: &Vec3, light_distance: f32) -> f32 {
+        cubes
+            .iter()
+            .map(|cube| cube.ray_intersect(origin, direction))
+            .find(|hit| hit.is_intersecting)
+            .map_or(0.0, |hit| (hit.distance / light_distance).clamp(0.0, 1.0))
+    }
+
+    #[test]
+    fn only_blocks_between_the_surface_and_the_light_cast_shadows() {
+        // From x = -3 towards a light at x = 0.5, in the empty third cell
+        let origin = Vec3::new(-3.0, 0.0, 0.0);
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+        let light_distance = 3.5;
+
+        // The first cube rule shades fully from a block past the light, and how much a block in
+        // front of it shades depends on which of the two was placed first
+        let (objects, cubes) = row(&[3]);
+        assert_eq!(first_cube_shadow(&cubes, &origin, &direction, light_distance), 1.0);
+        assert_eq!(transmission(&objects, &origin, &direction, light_distance), HdrColor::new(1.0, 1.0, 1.0));
+
+        for placed in [[3, 0], [0, 3]] {
+            let (objects, cubes) = row(&placed);
+            let expected = if placed[0] == 3 { 1.0 } else { 1.0 / light_distance };
+            assert_eq!(first_cube_shadow(&cubes, &origin, &direction, light_distance), expected);
+            assert_eq!(transmission(&objects, &origin, &direction, light_distance), HdrColor::black());
+        }
+    }
+
+    // A column of three cells from y = -1.5 to 1.5 with `material` in the middle one
+    fn pane(material: Material) -> Bvh {
+        let mut grid = Grid::new(1.0, 3, 1, 1);
+        grid.add_cube(0, 1, 0, Arc::new(material));
+        Bvh::new(vec![Box::new(grid)])
+    }
+
+    #[test]
+    fn tinted_panes_pass_what_both_surfaces_and_the_glass_let_through() {
+        // From the floor straight up to a light above the pane
+        let floor = Vec3::new(0.0, -1.4, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let absorption = HdrColor::new(0.1, 0.5, 2.0);
+
+        let objects = pane(Material { absorption, ..glass(1.0, 0.0) });
+        let lit = transmission(&objects, &floor, &up, 2.8);
+        let surface = 1.0 - fresnel(&up, &-up, GLASS_INDEX);
+        for (channel, absorbed) in [(lit.r, absorption.r), (lit.g, absorption.g), (lit.b, absorption.b)] {
+            let expected = surface * surface * (-absorbed).exp();
+            assert!((channel - expected).abs() < 1e-4, "{} against {}", channel, expected);
+        }
+
+        // Nothing gets through an opaque block, and a light below the pane isn't shaded by it
+        assert_eq!(transmission(&pane(Material::black()), &floor, &up, 2.8), HdrColor::black());
+        assert_eq!(transmission(&objects, &floor, &up, 0.5), HdrColor::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn glass_hides_less_ambient_light_than_stone() {
+        // A point under a ceiling one block up that reaches 2.5 blocks to every side
+        let ceiling = |material: Material| {
+            let material = Arc::new(material);
+            let mut grid = Grid::new(1.0, 3, 5, 5);
+            let blocks: Vec<_> = (0..25).map(|index| (index % 5, index / 5, Arc::clone(&material))).collect();
+            grid.add_layer(2, &blocks);
+            Bvh::new(vec![Box::new(grid)])
+        };
+        let floor = Material::black();
+        let intersect = Intersect::new(Vec3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), 1.0, &floor, HdrColor::black());
+        let settings = RenderSettings { ao_samples: 512, ao_distance: 4.0, ..RenderSettings::default() };
+        let occlusion = |objects: &Bvh| ambient_occlusion(&intersect, objects, &settings, &mut Rng::new(5, 0));
+
+        let stone = occlusion(&ceiling(Material::black()));
+        let glass = occlusion(&ceiling(glass(2.0, 0.0)));
+        assert!(stone > 0.75, "{}", stone);
+        assert!(glass > 0.0 && glass < 0.25, "{}", glass);
+        // Too far away to count
+        let near = RenderSettings { ao_distance: 0.9, ..settings };
+        assert_eq!(ambient_occlusion(&intersect, &ceiling(Material::black()), &near, &mut Rng::new(5, 0)), 0.0);
+    }
+
+    // A glowing block the size of a grid block, centered on `center`
+    fn glowing_block(center: Vec3) -> AreaLight {
+        let half = Vec3::new(0.5, 0.5, 0.5);
+        AreaLight::new(center - half, center + half, HdrColor::new(4.0, 4.0, 4.0))
+    }
+
+    // Light from `lights` on the west face of the last cell of `row`, looking at it from the west
+    fn lit_face(lights: &[AreaLight], objects: &Bvh, samples: u32) -> HdrColor {
+        let surface = Material { albedo: [1.0, 0.0], ..Material::black() };
+        let intersect = Intersect::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), 1.0, &surface, HdrColor::new(1.0, 1.0, 1.0));
+        area_light_contribution(&intersect, &Vec3::new(-1.0, 0.0, 0.0), lights, objects, samples, &mut Rng::new(9, 0))
+    }
+
+    #[test]
+    fn glowing_blocks_light_their_neighbours_unless_something_is_in_between() {
+        // Glowing in the first cell of the row, the second cell empty or holding stone
+        let light = [glowing_block(Vec3::new(-1.5, 0.0, 0.0))];
+        let (open, _) = row(&[3]);
+        let (blocked, _) = row(&[1, 3]);
+
+        let lit = lit_face(&light, &open, 64);
+        assert!(lit.r > 0.1 && lit.r == lit.g && lit.g == lit.b, "{:?}", lit);
+        assert_eq!(lit_face(&light, &blocked, 64), HdrColor::black());
+    }
+
+    #[test]
+    fn picking_one_block_per_sample_adds_up_to_every_block() {
+        let (objects, _) = row(&[3]);
+        let near = || glowing_block(Vec3::new(-1.5, 0.0, 0.0));
+        let far = || glowing_block(Vec3::new(-3.5, 1.5, 1.0));
+        let behind = || glowing_block(Vec3::new(2.5, 0.0, 0.0));
+
+        let samples = 20000;
+        let each = lit_face(&[near()], &objects, samples).r + lit_face(&[far()], &objects, samples).r;
+        let together = lit_face(&[near(), far(), behind()], &objects, samples).r;
+        assert!((together - each).abs() < 0.03 * each, "{} against {}", together, each);
+        assert_eq!(behind().importance(&Vec3::new(1.0, 0.0, 0.0), &Vec3::new(-1.0, 0.0, 0.0)), 0.0);
+    }
 }
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+// An unpacked Java Edition resource pack. Block textures are found by name under
+// `assets/minecraft/textures/block/`, so switching packs changes the look of every diorama, and
+// block models and blockstates under `models/` and `blockstates/` next to them.
+#[derive(Debug, Clone)]
+pub struct ResourcePack {
+    root: PathBuf,
+}
+
+impl ResourcePack {
+    pub fn open(dir: &str) -> Result<ResourcePack, String> {
+        let root = PathBuf::from(dir);
+        if !root.join("assets").join("minecraft").join("textures").is_dir() {
+            return Err(format!("'{}' is not a resource pack, it has no assets/minecraft/textures", dir));
+        }
+        Ok(ResourcePack { root })
+    }
+
+    // The image for a texture name like Minecraft writes them: `stone` and `block/stone` are block
+    // textures, `minecraft:block/stone` names the namespace too. None if the pack doesn't have it.
+    pub fn texture_path(&self, name: &str) -> Option<PathBuf> {
+        if name.starts_with('/') || name.ends_with(".png") {
+            return None;
+        }
+        let path = self.resolve("textures", name, Some("block"), "png");
+        path.is_file().then_some(path)
+    }
+
+    // A model like `oak_stairs`, `block/oak_stairs` or `minecraft:block/oak_stairs`
+    pub fn model_path(&self, name: &str) -> PathBuf {
+        self.resolve("models", name, Some("block"), "json")
+    }
+
+    // The blockstate of a block like `oak_stairs` or `minecraft:oak_stairs`
+    pub fn blockstate_path(&self, name: &str) -> PathBuf {
+        self.resolve("blockstates", name, None, "json")
+    }
+
+    // Where a resource location points, names without a folder are taken to be in `folder`
+    fn resolve(&self, kind: &str, name: &str, folder: Option<&str>, extension: &str) -> PathBuf {
+        let (namespace, name) = name.split_once(':').unwrap_or(("minecraft", name));
+        let name = match folder {
+            Some(folder) if !name.contains('/') => format!("{}/{}", folder, name),
+            _ => name.to_string(),
+        };
+        self.root.join("assets").join(namespace).join(kind).join(format!("{}.{}", name, extension))
+    }
+}
@@ -0,0 +1,312 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+// Small deterministic random number generator (PCG32), so renders with the same seed
+// give the same image no matter how rayon schedules the rows.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    // Independent generator for one pixel of a render
+    pub fn for_pixel(seed: u64, x: usize, y: usize) -> Rng {
+        Rng::new(mix(seed ^ mix(((y as u64) << 32) | x as u64)), y as u64)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+}
+
+// SplitMix64 finalizer, spreads nearby pixel coordinates over the whole seed space
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// Direction around `normal` with a density proportional to the cosine to it
+pub fn cosine_hemisphere(normal: &Vec3, rng: &mut Rng) -> Vec3 {
+    let helper = if normal.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+
+    let radius = rng.next_f32().sqrt();
+    let angle = 2.0 * PI * rng.next_f32();
+    let height = (1.0 - radius * radius).max(0.0).sqrt();
+    (tangent * (radius * angle.cos()) + bitangent * (radius * angle.sin()) + normal * height).normalize()
+}
+
+// Where the samples of a pixel are placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplePattern {
+    Grid,       // centers of a regular grid of cells
+    Stratified, // one random point inside each grid cell
+    Halton,     // Halton sequence in bases 2 and 3, randomly shifted per pixel
+    Sobol,      // 2D Sobol sequence, randomly scrambled per pixel
+}
+
+impl SamplePattern {
+    pub const ALL: [SamplePattern; 4] = [SamplePattern::Grid, SamplePattern::Stratified, SamplePattern::Halton, SamplePattern::Sobol];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplePattern::Grid => "grid",
+            SamplePattern::Stratified => "stratified",
+            SamplePattern::Halton => "halton",
+            SamplePattern::Sobol => "sobol",
+        }
+    }
+
+    // `count` points in the unit square
+    pub fn points(self, count: u32, rng: &mut Rng) -> Vec<(f32, f32)> {
+        match self {
+            SamplePattern::Grid | SamplePattern::Stratified => {
+                (0..count)
+                    .map(|i| {
+                        let (jitter_x, jitter_y) = if self == SamplePattern::Grid {
+                            (0.5, 0.5)
+                        } else {
+                            (rng.next_f32(), rng.next_f32())
+                        };
+                        let (x, y, width, height) = grid_cell(i, count);
+                        (x + jitter_x * width, y + jitter_y * height)
+                    })
+                    .collect()
+            }
+            SamplePattern::Halton => {
+                // Cranley-Patterson rotation keeps neighbouring pixels from sharing the same points
+                let (shift_x, shift_y) = (rng.next_f32(), rng.next_f32());
+                (0..count)
+                    .map(|i| ((radical_inverse(i + 1, 2) + shift_x).fract(), (radical_inverse(i + 1, 3) + shift_y).fract()))
+                    .collect()
+            }
+            SamplePattern::Sobol => {
+                // Random digit scrambling, XOR keeps the stratification of the sequence
+                let (scramble_x, scramble_y) = (rng.next_u32(), rng.next_u32());
+                (0..count)
+                    .map(|i| (to_unit(i.reverse_bits() ^ scramble_x), to_unit(sobol_second_dimension(i) ^ scramble_y)))
+                    .collect()
+            }
+        }
+    }
+}
+
+// Rows and columns of a near-square grid of `count` cells, the last row is short when `count`
+// isn't a product of the two, like 5 (3 and then 2)
+fn grid_shape(count: u32) -> (u32, u32) {
+    let columns = (1..=count).find(|columns| columns * columns >= count).unwrap_or(1);
+    (count.div_ceil(columns), columns)
+}
+
+// Corner and size of cell `index` of the grid for `count` samples. A short last row spreads its
+// cells across the whole pixel and is less tall, so every cell covers the same area.
+fn grid_cell(index: u32, count: u32) -> (f32, f32, f32, f32) {
+    let (_, columns) = grid_shape(count);
+    let first = index / columns * columns;
+    let in_row = columns.min(count - first);
+    let width = 1.0 / in_row as f32;
+    ((index - first) as f32 * width, first as f32 / count as f32, width, in_row as f32 / count as f32)
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inverse_base = 1.0 / base as f32;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f32 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+
+// Second dimension of the Sobol sequence, its generator matrix is Pascal's triangle mod 2
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+// Reconstruction filter, weighs each sample by its distance to the pixel center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFilter {
+    Box,      // every sample inside the pixel counts the same
+    Tent,     // linear falloff, reaches one pixel into the neighbours
+    Gaussian, // smooth falloff, sigma of half a pixel
+}
+
+const GAUSSIAN_SIGMA: f32 = 0.5;
+
+impl PixelFilter {
+    pub const ALL: [PixelFilter; 3] = [PixelFilter::Box, PixelFilter::Tent, PixelFilter::Gaussian];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFilter::Box => "box",
+            PixelFilter::Tent => "tent",
+            PixelFilter::Gaussian => "gaussian",
+        }
+    }
+
+    // Half width of the filter in pixels, samples are spread over this area
+    pub fn radius(self) -> f32 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.0,
+            PixelFilter::Gaussian => 1.5,
+        }
+    }
+
+    // Weight of a sample `(dx, dy)` pixels away from the pixel center
+    pub fn weight(self, dx: f32, dy: f32) -> f32 {
+        match self {
+            PixelFilter::Box => 1.0,
+            PixelFilter::Tent => (1.0 - dx.abs()).max(0.0) * (1.0 - dy.abs()).max(0.0),
+            PixelFilter::Gaussian => {
+                // Shifted down so the weight reaches zero at the radius instead of jumping
+                let gaussian = |d: f32| (-d * d / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+                let edge = gaussian(self.radius());
+                (gaussian(dx) - edge).max(0.0) * (gaussian(dy) - edge).max(0.0)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SamplePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for PixelFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for SamplePattern {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<SamplePattern, String> {
+        SamplePattern::ALL
+            .iter()
+            .find(|pattern| pattern.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("unknown sample pattern `{}`, expected grid, stratified, halton or sobol", name))
+    }
+}
+
+impl FromStr for PixelFilter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<PixelFilter, String> {
+        PixelFilter::ALL
+            .iter()
+            .find(|filter| filter.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("unknown filter `{}`, expected box, tent or gaussian", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_shapes_use_every_cell() {
+        assert_eq!(grid_shape(1), (1, 1));
+        assert_eq!(grid_shape(2), (1, 2));
+        assert_eq!(grid_shape(5), (2, 3));
+        assert_eq!(grid_shape(8), (3, 3));
+        assert_eq!(grid_shape(9), (3, 3));
+        assert_eq!(grid_shape(12), (3, 4));
+        for count in 1..=64 {
+            let (rows, columns) = grid_shape(count);
+            assert!((rows - 1) * columns < count && count <= rows * columns, "{} samples", count);
+            // Samples spread over both axes, so horizontal edges get smoothed as well as vertical ones
+            if count >= 4 {
+                assert!(rows > 1 && columns > 1, "{} samples in {} by {}", count, rows, columns);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_patterns_fill_every_cell_once() {
+        let mut rng = Rng::new(0, 0);
+        for pattern in [SamplePattern::Grid, SamplePattern::Stratified] {
+            for count in 1..=32 {
+                let points = pattern.points(count, &mut rng);
+                let mut cells = vec![0; count as usize];
+                for (x, y) in &points {
+                    assert!((0.0..1.0).contains(x) && (0.0..1.0).contains(y));
+                    for (index, samples) in cells.iter_mut().enumerate() {
+                        let (left, top, width, height) = grid_cell(index as u32, count);
+                        if (left..left + width).contains(x) && (top..top + height).contains(y) {
+                            *samples += 1;
+                        }
+                    }
+                }
+                assert!(cells.iter().all(|&samples| samples == 1), "{} samples of {}", count, pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_pattern_is_centered() {
+        let mut rng = Rng::new(0, 0);
+        for count in 1..=32 {
+            let points = SamplePattern::Grid.points(count, &mut rng);
+            let (sum_x, sum_y) = points.iter().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+            assert!((sum_x / count as f32 - 0.5).abs() < 1e-5 && (sum_y / count as f32 - 0.5).abs() < 1e-5, "{} samples", count);
+        }
+    }
+
+    #[test]
+    fn patterns_repeat_for_the_same_seed() {
+        for pattern in SamplePattern::ALL {
+            let first = pattern.points(16, &mut Rng::for_pixel(42, 3, 7));
+            let again = pattern.points(16, &mut Rng::for_pixel(42, 3, 7));
+            let other = pattern.points(16, &mut Rng::for_pixel(43, 3, 7));
+            assert_eq!(first, again);
+            if pattern != SamplePattern::Grid {
+                assert_ne!(first, other);
+            }
+        }
+    }
+}
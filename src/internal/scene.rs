@@ -0,0 +1,1089 @@
+use nalgebra_glm::Vec3;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::block_model::{self, Placement};
+use super::camera::Camera;
+use super::entitiy::color::{Color, HdrColor};
+use super::entitiy::grid::Grid;
+use super::entitiy::light::{AmbientLight, Attenuation, DayLight, DirectionalLight, Falloff, Light, PointLight, SpotLight};
+use super::entitiy::material::{AlphaMode, Diffuse, Material};
+use super::entitiy::texture::{self, FaceTextures, Texture, TextureRect, FACE_NAMES};
+use super::environment::Environment;
+use super::resource_pack::ResourcePack;
+use super::sky::SunSettings;
+
+const DEFAULT_TIME: f32 = 12.0; // noon, when a scene doesn't say
+const DEFAULT_MOON_PHASE: f32 = 0.5; // full moon
+
+// Everything needed to render a diorama, as described by a scene file.
+pub struct Scene {
+    pub name: String,
+    pub grid: Grid,
+    pub camera: Camera,
+    pub lights: Vec<Box<dyn Light + Sync>>,
+    pub ambient_light: AmbientLight,
+    pub day_light: DayLight,
+    pub environment: Option<Environment>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse { line: usize, column: usize, message: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "could not read scene file: {}", e),
+            SceneError::Parse { line, column, message } => {
+                write!(f, "line {}, column {}: {}", line, column, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+// A bare name like `house` refers to `scenes/house.scene`, anything else is used as a path.
+pub fn scene_path(scene: &str) -> String {
+    if scene.contains('/') || scene.contains('\\') || scene.ends_with(".scene") {
+        scene.to_string()
+    } else {
+        format!("scenes/{}.scene", scene)
+    }
+}
+
+// `resource_pack` given here takes the place of any `resource_pack` the scene names.
+pub fn load_scene(path: &str, resource_pack: Option<ResourcePack>) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path).map_err(SceneError::Io)?;
+    let name = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string());
+    parse_scene(&name, &source, resource_pack)
+}
+
+/*
+ Scene files are line based. Blank lines and comments are ignored, a comment starts with a `#`
+ at the beginning of a word (`color=#ff8800` is a color) and runs to the end of the line,
+ every other line is a directive followed by its arguments:
+
+   resource_pack path=packs/faithful
+   grid width=9 height=11 depth=10 cube_width=1.0
+   material grass texture=grass specular=80 albedo=0.6,0.3
+   material glowstone texture=glowstone emission=255,255,255 emission_strength=4
+   material red_glass texture=glass transparency=1 refractive_index=1.5 tint=230,60,60
+   material leaves texture=oak_leaves alpha=cutout alpha_cutoff=0.5
+   camera eye=0,0,20 center=0,0,0 up=0,1,0
+   point_light position=0,1,0 color=242,130,39 intensity=0.3 radius=0.1
+   spot_light position=0,3,2 direction=0,-1,0 inner_angle=0.3 outer_angle=0.5 attenuation=inverse_square
+   directional_light direction=-1,-2,-1 color=255,244,229 intensity=1
+   ambient_light color=230,164,50 intensity=0.5
+   day_light date=2024-06-21 time=15:00 latitude=45 turbidity=3 intensity=2 angular_diameter=0.05
+   layer 0
+   grass 0,0 1,0 2,0
+
+ Inside a layer, a line starting with a material name places that material at
+ each of the listed `x,z` cells of the current layer.
+
+ Point lights fade with `attenuation=inverse_square` or `attenuation=c,l,q`
+ (1 / (c + l·d + q·d²)), and give no light past an optional `range`.
+ Spot lights shine from `position` along `direction` at full strength up to
+ `inner_angle` from the axis, fading out at `outer_angle` (both radians, defaults
+ of 0.39 and 0.52); they take the same `radius`, `attenuation` and `range`.
+ Directional lights have only a `direction`: parallel rays from infinitely far away.
+ Point and spot lights with a `radius`, and day and directional lights with an `angular_diameter` (radians),
+ cast soft shadows, lights without a size cast hard ones.
+
+ The day light is the sun: its direction comes from the `date` (`YYYY-MM-DD` or `MM-DD`),
+ the local solar `time` (`HH:MM` or hours) and the `latitude` in degrees, and it is kept
+ `radius` away from `center`. Its color, its intensity (up to `intensity` with the sun
+ overhead) and the sky behind the scene follow the Preetham sky model, hazier with a
+ higher `turbidity`. Without a date and latitude the sun rises due east and passes overhead.
+ The moon rises opposite the sun and lights the night by its `moon_phase` (0 new, 0.5 full).
+
+ `environment file=studio.hdr rotation=1.57 intensity=1` loads an equirectangular Radiance
+ HDR map, turned by `rotation` radians around the vertical axis. It takes the place of the
+ sky, the sun and the moon: escaping rays see it, and it lights the scene from all around.
+
+ Materials with `transparency` are glass: up to that share (at most 1) of the light goes
+ through and is bent by the `refractive_index`, split with the reflection by the Fresnel
+ equations, so glass mirrors more at grazing angles. A `tint` color stains it, it is what
+ is left of white light after one unit of distance inside, thicker glass tints more.
+
+ Texture names are looked up in the `resource_pack` directory, if the scene or the command line
+ names one (`stone` is `assets/minecraft/textures/block/stone.png`, `minecraft:item/apple` works
+ too), then among the textures bundled in `assets/`, then as image files. A texture that isn't
+ found anywhere shows a magenta and black checkerboard. `resource_pack` must come before the
+ materials.
+
+ A `texture` six times wider than tall holds the six faces of a block side by side in the order
+ top, bottom, north (-z), south (+z), east (+x), west (-x); any other image goes on every face,
+ the first frame of it if it is an animation strip. `layout=strip` or `layout=single` says which
+ it is instead, and `top_rect=x,y,width,height` (likewise for each face, or `side_rect` for
+ the four sides) cuts each face out of it as an atlas. Without `texture`, `top=`, `bottom=` and
+ `side=` (or `north=` and the rest) name an image per face:
+
+   material log top=log_top.png bottom=log_top.png side=log_side.png
+   material crate texture=crate.png top_rect=0,0,32,32 bottom_rect=32,0,32,32 side_rect=0,32,32,32
+
+ With a resource pack, `model=oak_slab` shapes a block like the pack's block model, turned by
+ `x=` and `y=` (0, 90, 180 or 270 degrees) like a blockstate would, and
+ `blockstate=oak_stairs state=facing=east,half=top` picks the models the pack's blockstate
+ gives for those properties, every matching part of a multipart one like fences. Where it lists
+ several weighted models, each block takes one at random by its position, the same one every
+ render. The models bring their own textures, so `texture` and `color` don't go with them.
+ Faces the game tints by biome, like grass and leaves, are multiplied by a `block_tint` color,
+ which such models can't do without. Models a blockstate turns with `uvlock` keep their
+ textures lined up with the world:
+
+   material stairs blockstate=oak_stairs state=facing=east,half=bottom,shape=straight
+   material torch model=torch alpha=cutout emission=255,200,120 emission_strength=2
+   material leaves blockstate=oak_leaves alpha=cutout block_tint=119,171,47
+
+ The alpha channel of a texture is ignored unless the material says otherwise:
+ `alpha=cutout` turns texels below `alpha_cutoff` (0.5 by default) into holes rays and
+ light go through, `alpha=blend` mixes each texel with what is behind it by its alpha.
+
+ Materials with an `emission` color or `emission_strength` glow: the emission tints
+ the block's own color, and every such block lights its surroundings as an area light.
+*/
+pub fn parse_scene(name: &str, source: &str, resource_pack: Option<ResourcePack>) -> Result<Scene, SceneError> {
+    let mut parser = Parser {
+        grid: None,
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        pack_overridden: resource_pack.is_some(),
+        resource_pack,
+        layer: None,
+        camera: None,
+        lights: Vec::new(),
+        ambient_light: None,
+        day_light: None,
+        environment: None,
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let tokens = tokenize(line, line_number);
+        if tokens.is_empty() {
+            continue;
+        }
+        parser.parse_line(&tokens)?;
+    }
+
+    let grid = match parser.grid {
+        Some(grid) => grid,
+        None => return Err(error(1, 1, "scene has no `grid` directive")),
+    };
+
+    Ok(Scene {
+        name: name.to_string(),
+        grid,
+        camera: parser.camera.unwrap_or_else(|| Camera::new(
+            Vec3::new(0.0, 0.0, 20.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        )),
+        lights: parser.lights,
+        ambient_light: parser.ambient_light.unwrap_or_else(|| AmbientLight::new(HdrColor::new(1.0, 1.0, 1.0), 0.5)),
+        day_light: parser.day_light.unwrap_or_else(|| DayLight::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            10.0,
+            day_angle(DEFAULT_TIME),
+            SunSettings::default(),
+            DEFAULT_MOON_PHASE,
+            2.0,
+            0.0,
+        )),
+        environment: parser.environment,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+// Splits a line into words, a word starting with `#` starts a comment
+fn tokenize(content: &str, line: usize) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None; // (byte offset, column)
+
+    for (column, (offset, c)) in content.char_indices().enumerate() {
+        if c == '#' && start.is_none() {
+            return tokens;
+        }
+        if c.is_whitespace() {
+            if let Some((begin, begin_column)) = start.take() {
+                tokens.push(Token { text: &content[begin..offset], line, column: begin_column });
+            }
+        } else if start.is_none() {
+            start = Some((offset, column + 1));
+        }
+    }
+    if let Some((begin, begin_column)) = start {
+        tokens.push(Token { text: &content[begin..], line, column: begin_column });
+    }
+
+    tokens
+}
+
+fn error(line: usize, column: usize, message: impl Into<String>) -> SceneError {
+    SceneError::Parse { line, column, message: message.into() }
+}
+
+struct Parser {
+    grid: Option<Grid>,
+    materials: HashMap<String, Vec<(u64, Arc<Material>)>>, // a blockstate with weighted models has a block for each pick, with its odds
+    textures: HashMap<String, Arc<Texture>>,
+    resource_pack: Option<ResourcePack>,
+    pack_overridden: bool, // a pack was picked on the command line, the scene's own is ignored
+    layer: Option<usize>,
+    camera: Option<Camera>,
+    lights: Vec<Box<dyn Light + Sync>>,
+    ambient_light: Option<AmbientLight>,
+    day_light: Option<DayLight>,
+    environment: Option<Environment>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, tokens: &[Token]) -> Result<(), SceneError> {
+        let directive = tokens[0];
+        let arguments = &tokens[1..];
+
+        match directive.text {
+            "grid" => self.parse_grid(directive, arguments),
+            "material" => self.parse_material(directive, arguments),
+            "resource_pack" => self.parse_resource_pack(directive, arguments),
+            "layer" => self.parse_layer(directive, arguments),
+            "camera" => self.parse_camera(directive, arguments),
+            "point_light" => self.parse_point_light(directive, arguments),
+            "spot_light" => self.parse_spot_light(directive, arguments),
+            "directional_light" => self.parse_directional_light(directive, arguments),
+            "ambient_light" => self.parse_ambient_light(directive, arguments),
+            "day_light" => self.parse_day_light(directive, arguments),
+            "environment" => self.parse_environment(directive, arguments),
+            name => match self.materials.get(name) {
+                Some(choices) => {
+                    let choices = choices.clone();
+                    self.parse_blocks(directive, arguments, &choices)
+                }
+                None => Err(error(directive.line, directive.column, format!("unknown directive or material `{}`", name))),
+            },
+        }
+    }
+
+    fn parse_grid(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        if self.grid.is_some() {
+            return Err(error(directive.line, directive.column, "`grid` may only be declared once"));
+        }
+        let mut properties = Properties::parse(directive, arguments, &["width", "height", "depth", "cube_width"])?;
+        let width = properties.required("width", parse_grid_size)?;
+        let height = properties.required("height", parse_grid_size)?;
+        let depth = properties.required("depth", parse_grid_size)?;
+        let cube_width = properties.optional("cube_width", parse_cube_width)?.unwrap_or(1.0);
+
+        self.grid = Some(Grid::new(cube_width, height, width, depth));
+        Ok(())
+    }
+
+    fn parse_material(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let name = match arguments.first() {
+            Some(name) if !name.text.contains('=') => *name,
+            _ => return Err(error(directive.line, directive.column, "`material` needs a name")),
+        };
+        if self.materials.contains_key(name.text) {
+            return Err(error(name.line, name.column, format!("material `{}` is already defined", name.text)));
+        }
+
+        let mut properties = Properties::parse(directive, &arguments[1..], &[
+            "texture", "layout", "color", "specular", "albedo", "reflectivity", "transparency", "refractive_index",
+            "tint", "emission", "emission_strength", "alpha", "alpha_cutoff",
+            "top", "bottom", "side", "north", "south", "east", "west",
+            "top_rect", "bottom_rect", "side_rect", "north_rect", "south_rect", "east_rect", "west_rect",
+            "model", "blockstate", "state", "x", "y", "block_tint",
+        ])?;
+
+        let placements = self.placements(directive, &mut properties)?;
+        let block_tint = match properties.take("block_tint") {
+            Some(tint) if placements.is_none() => {
+                return Err(error(tint.line, tint.column, "`block_tint` colors the tinted faces of a block model, it needs a `model` or `blockstate`"));
+            }
+            Some(tint) => Some(parse_color(tint)?),
+            None => None,
+        };
+        let diffuse = match self.face_textures(directive, &mut properties)? {
+            Some(_) if placements.is_some() => {
+                return Err(error(directive.line, directive.column, "a block model brings its own textures, drop the texture properties"));
+            }
+            Some(faces) => match properties.take("color") {
+                Some(color) => return Err(error(color.line, color.column, "a material takes either a texture or a `color`, not both")),
+                None => Diffuse::Texture(Box::new(faces)),
+            },
+            None => match properties.take("color") {
+                Some(color) if placements.is_some() => {
+                    return Err(error(color.line, color.column, "a block model brings its own textures, `color` doesn't apply"));
+                }
+                Some(color) => Diffuse::Color(parse_color(color)?),
+                None if placements.is_some() => Diffuse::Color(Color::new(0, 0, 0)), // the particle texture, once the model is loaded
+                None => return Err(error(directive.line, directive.column, "material needs a `texture` or a `color`")),
+            },
+        };
+        let albedo = properties.optional("albedo", parse_pair)?.unwrap_or((1.0, 0.0));
+        let emission = properties.optional("emission", parse_color)?;
+        let emission_strength = properties.optional("emission_strength", parse_f32)?;
+        let tint = properties.optional("tint", parse_color)?;
+        let mut alpha_mode = properties.optional("alpha", parse_alpha_mode)?.unwrap_or(AlphaMode::Opaque);
+        if let Some(cutoff) = properties.take("alpha_cutoff") {
+            match &mut alpha_mode {
+                AlphaMode::Cutout { threshold } => *threshold = parse_f32(cutoff)?,
+                _ => return Err(error(cutoff.line, cutoff.column, "`alpha_cutoff` only applies to `alpha=cutout`")),
+            }
+        }
+
+        let material = Material {
+            diffuse,
+            specular: properties.optional("specular", parse_f32)?.unwrap_or(10.0),
+            albedo: [albedo.0, albedo.1],
+            reflectivity: properties.optional("reflectivity", parse_f32)?.unwrap_or(0.0),
+            transparency: properties.optional("transparency", parse_f32)?.unwrap_or(0.0),
+            refractive_index: properties.optional("refractive_index", parse_f32)?.unwrap_or(1.0),
+            absorption: tint.map_or_else(HdrColor::black, absorption_for_tint),
+            // Giving only one of the two makes the block glow, white and at strength 1 by default
+            emission: match (emission, emission_strength) {
+                (None, None) => HdrColor::black(),
+                (Some(color), _) => HdrColor::from(color),
+                (None, Some(_)) => HdrColor::new(1.0, 1.0, 1.0),
+            },
+            emission_strength: emission_strength.unwrap_or(if emission.is_some() { 1.0 } else { 0.0 }),
+            alpha_mode,
+            shape: None,
+        };
+        let choices = match placements {
+            Some(parts) => {
+                let mut choices = Vec::new();
+                for (weight, placements) in block_model::combinations(&parts) {
+                    choices.push((weight, Arc::new(self.shaped(directive, material.clone(), block_tint, &placements)?)));
+                }
+                choices
+            }
+            None => vec![(1, Arc::new(material))],
+        };
+
+        self.materials.insert(name.text.to_string(), choices);
+        Ok(())
+    }
+
+    // How the faces of a block map onto its texture images: a `texture` laid out as a strip of six
+    // faces, the same image on every face with `layout=single`, rectangles of it with `*_rect`,
+    // or an image of its own for each face. `None` when the material names no texture at all.
+    fn face_textures(&mut self, directive: Token, properties: &mut Properties) -> Result<Option<FaceTextures>, SceneError> {
+        let texture = properties.take("texture");
+        let layout = properties.take("layout");
+        let images = face_properties(properties, "");
+        let rects = face_properties(properties, "_rect");
+
+        let Some(texture) = texture else {
+            if let Some(layout) = layout {
+                return Err(error(layout.line, layout.column, "`layout` needs a `texture`"));
+            }
+            if let Some(rect) = rects.iter().flatten().next() {
+                return Err(error(rect.line, rect.column, "face rectangles need a `texture` to cut them from"));
+            }
+            if images.iter().all(Option::is_none) {
+                return Ok(None);
+            }
+            let mut textures = Vec::with_capacity(6);
+            for (face, image) in images.into_iter().enumerate() {
+                match image {
+                    Some(image) => textures.push(self.texture(image)),
+                    None => return Err(error(directive.line, directive.column, format!("no texture for the {} face", FACE_NAMES[face]))),
+                }
+            }
+            return Ok(Some(FaceTextures::separate(textures.try_into().unwrap())));
+        };
+
+        if let Some(image) = images.iter().flatten().next() {
+            return Err(error(image.line, image.column, "a material takes either `texture` or an image per face, not both"));
+        }
+        let image = self.texture(texture);
+        if rects.iter().all(Option::is_none) {
+            return match layout {
+                None => Ok(Some(FaceTextures::fit(image))),
+                Some(layout) => match layout.text {
+                    "strip" => Ok(Some(FaceTextures::strip(image))),
+                    "single" => Ok(Some(FaceTextures::single(image))),
+                    _ => Err(error(layout.line, layout.column, format!("expected `strip` or `single`, found `{}`", layout.text))),
+                },
+            };
+        }
+        if let Some(layout) = layout {
+            return Err(error(layout.line, layout.column, "`layout` can't be combined with face rectangles"));
+        }
+        let mut face_rects = Vec::with_capacity(6);
+        for (face, rect) in rects.into_iter().enumerate() {
+            match rect {
+                Some(rect) => face_rects.push(parse_rect(rect, &image)?),
+                None => return Err(error(directive.line, directive.column, format!("no rectangle for the {} face", FACE_NAMES[face]))),
+            }
+        }
+        Ok(Some(FaceTextures::atlas(image, face_rects.try_into().unwrap())))
+    }
+
+    fn texture(&mut self, token: Token) -> Arc<Texture> {
+        find_texture(&mut self.textures, self.resource_pack.as_ref(), token.text, token.line)
+    }
+
+    // The models a material is drawn with: those a `blockstate` picks for the `state`, each part
+    // a list of weighted models to pick from, or one `model` turned by `x` and `y`. None for plain cubes.
+    fn placements(&self, directive: Token, properties: &mut Properties) -> Result<Option<Vec<Vec<Placement>>>, SceneError> {
+        let model = properties.take("model");
+        let blockstate = properties.take("blockstate");
+        let state = properties.take("state");
+        let x = properties.take("x");
+        let y = properties.take("y");
+        let source = match (model, blockstate) {
+            (Some(_), Some(blockstate)) => return Err(error(blockstate.line, blockstate.column, "a material takes either `model` or `blockstate`, not both")),
+            (Some(model), None) | (None, Some(model)) => model,
+            (None, None) => {
+                if let Some(stray) = state.or(x).or(y) {
+                    return Err(error(stray.line, stray.column, "`state`, `x` and `y` need a `model` or `blockstate`"));
+                }
+                return Ok(None);
+            }
+        };
+        let Some(pack) = &self.resource_pack else {
+            return Err(error(source.line, source.column, "block models come from a `resource_pack`, the scene has none"));
+        };
+
+        if blockstate.is_none() {
+            if let Some(state) = state {
+                return Err(error(state.line, state.column, "`state` only applies to a `blockstate`"));
+            }
+            let angle = |token: Option<Token>| match token {
+                Some(token) => match parse_usize(token)? {
+                    angle @ (0 | 90 | 180 | 270) => Ok(angle as i32),
+                    _ => Err(error(token.line, token.column, "model rotations are 0, 90, 180 or 270 degrees")),
+                },
+                None => Ok(0),
+            };
+            return Ok(Some(vec![vec![Placement { model: source.text.to_string(), x: angle(x)?, y: angle(y)?, weight: 1, uvlock: false }]]));
+        }
+
+        if let Some(stray) = x.or(y) {
+            return Err(error(stray.line, stray.column, "the blockstate turns its models itself, `x` and `y` only apply to a `model`"));
+        }
+        let mut pairs = Vec::new();
+        if let Some(state) = state {
+            for pair in state.text.split(',') {
+                match pair.split_once('=') {
+                    Some((key, value)) => pairs.push((key.to_string(), value.to_string())),
+                    None => return Err(error(state.line, state.column, format!("expected `property=value` pairs, found `{}`", pair))),
+                }
+            }
+        }
+        let placements = block_model::blockstate_models(pack, source.text, &pairs).map_err(|e| error(directive.line, directive.column, e))?;
+        if placements.is_empty() {
+            return Err(error(directive.line, directive.column, format!("blockstate `{}` shows nothing for that state", source.text)));
+        }
+        Ok(Some(placements))
+    }
+
+    // Loads the models into the material's shape, the particle texture colors the block as a whole
+    fn shaped(&mut self, directive: Token, material: Material, tint: Option<Color>, placements: &[Placement]) -> Result<Material, SceneError> {
+        let Parser { textures, resource_pack, .. } = self;
+        let pack = resource_pack.as_ref().unwrap();
+        let mut texture = |name: &str| find_texture(textures, Some(pack), name, directive.line);
+        let (shape, particle) = block_model::build_shape(pack, placements, &material, tint, &mut texture)
+            .map_err(|e| error(directive.line, directive.column, e))?;
+        let particle = particle.unwrap_or_else(|| Arc::clone(&texture::MISSING));
+        Ok(Material {
+            diffuse: Diffuse::Texture(Box::new(FaceTextures::single(particle))),
+            shape: Some(Arc::new(shape)),
+            ..material
+        })
+    }
+
+    fn parse_resource_pack(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        if !self.materials.is_empty() {
+            return Err(error(directive.line, directive.column, "`resource_pack` must come before any `material`"));
+        }
+        let mut properties = Properties::parse(directive, arguments, &["path"])?;
+        let path = properties.required("path", |token| Ok(token.text.to_string()))?;
+        if !self.pack_overridden {
+            let pack = ResourcePack::open(&path).map_err(|e| error(directive.line, directive.column, e))?;
+            self.resource_pack = Some(pack);
+        }
+        Ok(())
+    }
+
+    fn parse_layer(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let height = match &self.grid {
+            Some(grid) => grid.height,
+            None => return Err(error(directive.line, directive.column, "`grid` must be declared before any `layer`")),
+        };
+        let layer = match arguments {
+            [layer] => *layer,
+            _ => return Err(error(directive.line, directive.column, "`layer` takes exactly one layer index")),
+        };
+        let index = parse_usize(layer)?;
+        if index >= height {
+            return Err(error(layer.line, layer.column, format!("layer {} is outside the grid (height {})", index, height)));
+        }
+
+        self.layer = Some(index);
+        Ok(())
+    }
+
+    fn parse_blocks(&mut self, directive: Token, arguments: &[Token], choices: &[(u64, Arc<Material>)]) -> Result<(), SceneError> {
+        let layer = match self.layer {
+            Some(layer) => layer,
+            None => return Err(error(directive.line, directive.column, "blocks must be placed inside a `layer`")),
+        };
+        // `parse_layer` only sets a layer once the grid exists
+        let grid = self.grid.as_mut().unwrap();
+
+        let mut list = Vec::with_capacity(arguments.len());
+        for position in arguments {
+            let (x, z) = match position.text.split_once(',') {
+                Some((x, z)) => (
+                    x.parse::<usize>().map_err(|_| error(position.line, position.column, format!("invalid x coordinate `{}`", x)))?,
+                    z.parse::<usize>().map_err(|_| error(position.line, position.column, format!("invalid z coordinate `{}`", z)))?,
+                ),
+                None => return Err(error(position.line, position.column, format!("expected a block position `x,z`, found `{}`", position.text))),
+            };
+            if x >= grid.width || z >= grid.depth {
+                return Err(error(position.line, position.column, format!(
+                    "block {},{} is outside the grid ({}x{})", x, z, grid.width, grid.depth
+                )));
+            }
+            let material = match choices {
+                [(_, material)] => material,
+                _ => {
+                    let weights: Vec<u64> = choices.iter().map(|(weight, _)| *weight).collect();
+                    &choices[block_model::pick(&weights, x, layer, z)].1
+                }
+            };
+            list.push((x, z, Arc::clone(material)));
+        }
+
+        grid.add_layer(layer, &list);
+        Ok(())
+    }
+
+    fn parse_camera(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &["eye", "center", "up"])?;
+        let eye = properties.required("eye", parse_vec3)?;
+        let center = properties.optional("center", parse_vec3)?.unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+        let up = properties.optional("up", parse_vec3)?.unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+
+        self.camera = Some(Camera::new(eye, center, up));
+        Ok(())
+    }
+
+    fn parse_point_light(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &[
+            "position", "color", "intensity", "radius", "attenuation", "range",
+        ])?;
+        let position = properties.required("position", parse_vec3)?;
+        let color = properties.optional("color", parse_color)?.map_or(HdrColor::new(1.0, 1.0, 1.0), HdrColor::from);
+        let intensity = properties.optional("intensity", parse_f32)?.unwrap_or(1.0);
+        let radius = properties.optional("radius", parse_f32)?.unwrap_or(0.0);
+        let attenuation = properties.attenuation()?;
+
+        self.lights.push(Box::new(PointLight::new(position, color, intensity, radius, attenuation)));
+        Ok(())
+    }
+
+    fn parse_spot_light(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &[
+            "position", "direction", "color", "intensity", "inner_angle", "outer_angle", "radius", "attenuation", "range",
+        ])?;
+        let position = properties.required("position", parse_vec3)?;
+        let direction = properties.required("direction", parse_direction)?;
+        let color = properties.optional("color", parse_color)?.map_or(HdrColor::new(1.0, 1.0, 1.0), HdrColor::from);
+        let intensity = properties.optional("intensity", parse_f32)?.unwrap_or(1.0);
+        let outer_angle = properties.optional("outer_angle", parse_f32)?.unwrap_or(PI / 6.0);
+        let inner_angle = properties.optional("inner_angle", parse_f32)?.unwrap_or(outer_angle * 0.75);
+        if inner_angle > outer_angle {
+            return Err(error(directive.line, directive.column, format!(
+                "inner_angle {} is wider than outer_angle {}", inner_angle, outer_angle
+            )));
+        }
+        let radius = properties.optional("radius", parse_f32)?.unwrap_or(0.0);
+        let attenuation = properties.attenuation()?;
+
+        self.lights.push(Box::new(SpotLight {
+            position,
+            direction,
+            color,
+            intensity,
+            inner_angle,
+            outer_angle,
+            radius,
+            attenuation,
+        }));
+        Ok(())
+    }
+
+    fn parse_directional_light(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &["direction", "color", "intensity", "angular_diameter"])?;
+        let direction = properties.required("direction", parse_direction)?;
+        let color = properties.optional("color", parse_color)?.map_or(HdrColor::new(1.0, 1.0, 1.0), HdrColor::from);
+        let intensity = properties.optional("intensity", parse_f32)?.unwrap_or(1.0);
+        let angular_diameter = properties.optional("angular_diameter", parse_f32)?.unwrap_or(0.0);
+
+        self.lights.push(Box::new(DirectionalLight::new(direction, color, intensity, angular_diameter)));
+        Ok(())
+    }
+
+    fn parse_ambient_light(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &["color", "intensity"])?;
+        let color = properties.optional("color", parse_color)?.map_or(HdrColor::new(1.0, 1.0, 1.0), HdrColor::from);
+        let intensity = properties.optional("intensity", parse_f32)?.unwrap_or(0.5);
+
+        self.ambient_light = Some(AmbientLight::new(color, intensity));
+        Ok(())
+    }
+
+    fn parse_day_light(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &[
+            "center", "radius", "date", "time", "latitude", "turbidity", "moon_phase", "intensity", "angular_diameter",
+        ])?;
+        let center = properties.optional("center", parse_vec3)?.unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+        let radius = properties.optional("radius", parse_f32)?.unwrap_or(10.0);
+        let defaults = SunSettings::default();
+        let sun = SunSettings {
+            day_of_year: properties.optional("date", parse_date)?.unwrap_or(defaults.day_of_year),
+            latitude: properties.optional("latitude", parse_f32)?.map_or(defaults.latitude, f32::to_radians),
+            turbidity: properties.optional("turbidity", parse_f32)?.unwrap_or(defaults.turbidity),
+        };
+        let time = properties.optional("time", parse_time)?.unwrap_or(DEFAULT_TIME);
+        let moon_phase = properties.optional("moon_phase", parse_f32)?.unwrap_or(DEFAULT_MOON_PHASE);
+        let intensity = properties.optional("intensity", parse_f32)?.unwrap_or(2.0);
+        let angular_diameter = properties.optional("angular_diameter", parse_f32)?.unwrap_or(0.0);
+
+        self.day_light = Some(DayLight::new(center, radius, day_angle(time), sun, moon_phase, intensity, angular_diameter));
+        Ok(())
+    }
+
+    fn parse_environment(&mut self, directive: Token, arguments: &[Token]) -> Result<(), SceneError> {
+        let mut properties = Properties::parse(directive, arguments, &["file", "rotation", "intensity"])?;
+        let file = properties.required("file", |token| Ok(token.text.to_string()))?;
+        let rotation = properties.optional("rotation", parse_f32)?.unwrap_or(0.0);
+        let intensity = properties.optional("intensity", parse_f32)?.unwrap_or(1.0);
+
+        let environment = Environment::load(&file, rotation, intensity).map_err(|e| error(directive.line, directive.column, format!(
+            "could not load environment map '{}': {}", file, e
+        )))?;
+        self.environment = Some(environment);
+        Ok(())
+    }
+}
+
+// The `key=value` arguments of a single directive.
+struct Properties<'a> {
+    directive: Token<'a>,
+    values: Vec<(&'a str, Token<'a>)>,
+}
+
+impl<'a> Properties<'a> {
+    fn parse(directive: Token<'a>, arguments: &[Token<'a>], allowed: &[&str]) -> Result<Self, SceneError> {
+        let mut values: Vec<(&str, Token)> = Vec::new();
+
+        for argument in arguments {
+            let (key, value) = match argument.text.split_once('=') {
+                Some((key, value)) if !key.is_empty() && !value.is_empty() => (key, value),
+                _ => return Err(error(argument.line, argument.column, format!("expected `key=value`, found `{}`", argument.text))),
+            };
+            if !allowed.contains(&key) {
+                return Err(error(argument.line, argument.column, format!(
+                    "unknown property `{}` for `{}` (expected one of: {})", key, directive.text, allowed.join(", ")
+                )));
+            }
+            if values.iter().any(|(existing, _)| *existing == key) {
+                return Err(error(argument.line, argument.column, format!("property `{}` is given twice", key)));
+            }
+            let value_token = Token { text: value, line: argument.line, column: argument.column + key.chars().count() + 1 };
+            values.push((key, value_token));
+        }
+
+        Ok(Properties { directive, values })
+    }
+
+    fn take(&mut self, key: &str) -> Option<Token<'a>> {
+        let index = self.values.iter().position(|(existing, _)| *existing == key)?;
+        Some(self.values.remove(index).1)
+    }
+
+    fn optional<T>(&mut self, key: &str, parse: fn(Token) -> Result<T, SceneError>) -> Result<Option<T>, SceneError> {
+        self.take(key).map(parse).transpose()
+    }
+
+    fn required<T>(&mut self, key: &str, parse: fn(Token) -> Result<T, SceneError>) -> Result<T, SceneError> {
+        match self.take(key) {
+            Some(value) => parse(value),
+            None => Err(error(self.directive.line, self.directive.column, format!(
+                "`{}` is missing the `{}` property", self.directive.text, key
+            ))),
+        }
+    }
+
+    // The `attenuation` and `range` properties shared by lights with a position
+    fn attenuation(&mut self) -> Result<Attenuation, SceneError> {
+        Ok(Attenuation {
+            falloff: self.optional("attenuation", parse_falloff)?.unwrap_or(Falloff::None),
+            range: self.optional("range", parse_f32)?,
+        })
+    }
+}
+
+fn parse_usize(token: Token) -> Result<usize, SceneError> {
+    token.text.parse().map_err(|_| error(token.line, token.column, format!("expected a whole number, found `{}`", token.text)))
+}
+
+// A side of the grid, a grid without blocks along one side has no room for any
+fn parse_grid_size(token: Token) -> Result<usize, SceneError> {
+    match parse_usize(token)? {
+        0 => Err(error(token.line, token.column, "the grid needs room for at least one block on every side")),
+        size => Ok(size),
+    }
+}
+
+fn parse_cube_width(token: Token) -> Result<f32, SceneError> {
+    let width = parse_f32(token)?;
+    if width > 0.0 && width.is_finite() {
+        Ok(width)
+    } else {
+        Err(error(token.line, token.column, format!("`cube_width` must be above 0, found `{}`", token.text)))
+    }
+}
+
+fn parse_f32(token: Token) -> Result<f32, SceneError> {
+    token.text.parse().map_err(|_| error(token.line, token.column, format!("expected a number, found `{}`", token.text)))
+}
+
+fn parse_components(token: Token, count: usize) -> Result<Vec<f32>, SceneError> {
+    let components: Vec<&str> = token.text.split(',').collect();
+    if components.len() != count {
+        return Err(error(token.line, token.column, format!(
+            "expected {} comma separated numbers, found `{}`", count, token.text
+        )));
+    }
+
+    let mut values = Vec::with_capacity(count);
+    let mut column = token.column;
+    for component in components {
+        let value = component.parse::<f32>()
+            .map_err(|_| error(token.line, column, format!("expected a number, found `{}`", component)))?;
+        values.push(value);
+        column += component.chars().count() + 1;
+    }
+    Ok(values)
+}
+
+fn parse_pair(token: Token) -> Result<(f32, f32), SceneError> {
+    let values = parse_components(token, 2)?;
+    Ok((values[0], values[1]))
+}
+
+fn parse_vec3(token: Token) -> Result<Vec3, SceneError> {
+    let values = parse_components(token, 3)?;
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+// A direction vector, normalized
+fn parse_direction(token: Token) -> Result<Vec3, SceneError> {
+    let direction = parse_vec3(token)?;
+    if direction.magnitude() <= f32::EPSILON {
+        return Err(error(token.line, token.column, "direction can't be zero"));
+    }
+    Ok(direction.normalize())
+}
+
+// `YYYY-MM-DD` or `MM-DD` to the day of the year, the year only matters for February 29th
+fn parse_date(token: Token) -> Result<u32, SceneError> {
+    const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    const DAYS_IN_MONTH: [u32; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let invalid = || error(token.line, token.column, format!("expected a date `YYYY-MM-DD` or `MM-DD`, found `{}`", token.text));
+
+    let parts: Vec<&str> = token.text.split('-').collect();
+    let (year, month, day) = match parts.as_slice() {
+        [year, month, day] => (Some(year.parse::<i32>().map_err(|_| invalid())?), month, day),
+        [month, day] => (None, month, day),
+        _ => return Err(invalid()),
+    };
+    let month = month.parse::<u32>().map_err(|_| invalid())?;
+    let day = day.parse::<u32>().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || day == 0 || day > DAYS_IN_MONTH[month as usize - 1] {
+        return Err(invalid());
+    }
+
+    // Without a year February 29th is taken as March 1st
+    let leap_year = year.is_some_and(|year| year % 4 == 0 && (year % 100 != 0 || year % 400 == 0));
+    if year.is_some() && !leap_year && month == 2 && day == 29 {
+        return Err(invalid());
+    }
+    let leap_day = if leap_year && month > 2 { 1 } else { 0 };
+    Ok(DAYS_BEFORE_MONTH[month as usize - 1] + day + leap_day)
+}
+
+// `HH:MM` or decimal hours, local solar time
+fn parse_time(token: Token) -> Result<f32, SceneError> {
+    let invalid = || error(token.line, token.column, format!("expected a time `HH:MM` or hours, found `{}`", token.text));
+    let hours = match token.text.split_once(':') {
+        Some((hours, minutes)) => {
+            let minutes = minutes.parse::<f32>().map_err(|_| invalid())?;
+            if !(0.0..60.0).contains(&minutes) {
+                return Err(invalid());
+            }
+            hours.parse::<f32>().map_err(|_| invalid())? + minutes / 60.0
+        }
+        None => token.text.parse::<f32>().map_err(|_| invalid())?,
+    };
+    if !(0.0..=24.0).contains(&hours) {
+        return Err(invalid());
+    }
+    Ok(hours)
+}
+
+// The day light's angle for a time of day, sunrise at 6:00 is 0
+fn day_angle(hours: f32) -> f32 {
+    (hours - 6.0) * PI / 12.0
+}
+
+// `none`, `inverse_square`, or `constant,linear,quadratic` coefficients
+fn parse_falloff(token: Token) -> Result<Falloff, SceneError> {
+    match token.text {
+        "none" => Ok(Falloff::None),
+        "inverse_square" => Ok(Falloff::InverseSquare),
+        _ if token.text.contains(',') => {
+            let values = parse_components(token, 3)?;
+            Ok(Falloff::Polynomial { constant: values[0], linear: values[1], quadratic: values[2] })
+        }
+        _ => Err(error(token.line, token.column, format!(
+            "expected `none`, `inverse_square` or `constant,linear,quadratic`, found `{}`", token.text
+        ))),
+    }
+}
+
+fn parse_alpha_mode(token: Token) -> Result<AlphaMode, SceneError> {
+    match token.text {
+        "opaque" => Ok(AlphaMode::Opaque),
+        "cutout" => Ok(AlphaMode::Cutout { threshold: 0.5 }),
+        "blend" => Ok(AlphaMode::Blend),
+        _ => Err(error(token.line, token.column, format!("expected `opaque`, `cutout` or `blend`, found `{}`", token.text))),
+    }
+}
+
+// Textures are shared between materials that name the same one, so each file is only decoded once.
+// A name is looked up in the resource pack first, then among the bundled textures, then as a
+// file. One that isn't anywhere shows the missing texture, and is reported the first time.
+fn find_texture(textures: &mut HashMap<String, Arc<Texture>>, resource_pack: Option<&ResourcePack>, name: &str, line: usize) -> Arc<Texture> {
+    Arc::clone(textures.entry(name.to_string()).or_insert_with(|| {
+        if let Some(path) = resource_pack.and_then(|pack| pack.texture_path(name)) {
+            return Arc::new(Texture::new(&path.to_string_lossy()));
+        }
+        if let Some(texture) = texture::builtin(name) {
+            return texture;
+        }
+        if Path::new(name).is_file() {
+            return Arc::new(Texture::new(name));
+        }
+        eprintln!("line {}: texture `{}` not found, using the missing texture", line, name);
+        Arc::clone(&texture::MISSING)
+    }))
+}
+
+// The value for each face under `<face><suffix>`, the four sides falling back to `side<suffix>`
+fn face_properties<'a>(properties: &mut Properties<'a>, suffix: &str) -> [Option<Token<'a>>; 6] {
+    let side = properties.take(&format!("side{}", suffix));
+    FACE_NAMES.map(|face| {
+        let own = properties.take(&format!("{}{}", face, suffix));
+        match face {
+            "top" | "bottom" => own,
+            _ => own.or(side),
+        }
+    })
+}
+
+// `x,y,width,height` in pixels, inside `texture`
+fn parse_rect(token: Token, texture: &Texture) -> Result<TextureRect, SceneError> {
+    let values = parse_components(token, 4)?;
+    if values.iter().any(|value| *value < 0.0 || value.fract() != 0.0) {
+        return Err(error(token.line, token.column, format!("expected whole pixel counts, found `{}`", token.text)));
+    }
+    let [x, y, width, height] = [values[0], values[1], values[2], values[3]].map(|value| value as usize);
+    if width == 0 || height == 0 || x + width > texture.width || y + height > texture.height {
+        return Err(error(token.line, token.column, format!(
+            "rectangle `{}` is empty or outside the {}x{} texture", token.text, texture.width, texture.height
+        )));
+    }
+    Ok(TextureRect { x, y, width, height })
+}
+
+// Beer-Lambert coefficients that leave `tint` of the light after one unit of distance
+fn absorption_for_tint(tint: Color) -> HdrColor {
+    let tint = HdrColor::from(tint);
+    let coefficient = |channel: f32| -channel.max(1e-4).ln();
+    HdrColor::new(coefficient(tint.r), coefficient(tint.g), coefficient(tint.b))
+}
+
+// Colors are either `r,g,b` with channels in 0-255 or a `#rrggbb` hex code.
+fn parse_color(token: Token) -> Result<Color, SceneError> {
+    if let Some(hex) = token.text.strip_prefix('#') {
+        return match u32::from_str_radix(hex, 16) {
+            Ok(value) if hex.len() == 6 => Ok(Color::from_hex(value)),
+            _ => Err(error(token.line, token.column, format!("invalid hex color `{}`", token.text))),
+        };
+    }
+
+    let values = parse_components(token, 3)?;
+    if values.iter().any(|channel| !(0.0..=255.0).contains(channel)) {
+        return Err(error(token.line, token.column, format!("color channels must be between 0 and 255, found `{}`", token.text)));
+    }
+    Ok(Color::new(values[0] as u8, values[1] as u8, values[2] as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::temp_dir::TempDir;
+
+    fn block_color(scene: &Scene, x: usize, y: usize, z: usize) -> Color {
+        match &scene.grid.block(x, y, z).expect("block placed").diffuse {
+            Diffuse::Color(color) => *color,
+            Diffuse::Texture(_) => panic!("expected a plain color"),
+        }
+    }
+
+    #[test]
+    fn hex_colors_are_not_comments() {
+        let source = "
+            # a red block and a green one
+            grid width=2 height=1 depth=1 # one row
+            material red color=#ff0000 # trailing comment
+            material green color=0,255,0 #00ff00
+            layer 0
+            red 0,0 # the first block
+            green 1,0
+        ";
+        let scene = parse_scene("test", source, None).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(block_color(&scene, 0, 0, 0), Color::from_hex(0xff0000));
+        assert_eq!(block_color(&scene, 1, 0, 0), Color::new(0, 255, 0));
+    }
+
+    #[test]
+    fn comments_end_the_line() {
+        let tokens = tokenize("material red color=#ff0000 #color=0,0,0", 1);
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(texts, ["material", "red", "color=#ff0000"]);
+        assert!(tokenize("# grid width=1", 1).is_empty());
+        assert!(tokenize("   #grid", 1).is_empty());
+    }
+
+    #[test]
+    fn errors_point_at_the_bad_token() {
+        let source = "grid width=1 height=1 depth=1\nmaterial red color=#ff00zz";
+        match parse_scene("test", source, None) {
+            Err(SceneError::Parse { line, column, .. }) => assert_eq!((line, column), (2, 20)),
+            other => panic!("expected a parse error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn grids_need_room_for_blocks() {
+        let position = |source: &str| match parse_scene("test", source, None) {
+            Err(SceneError::Parse { line, column, .. }) => (line, column),
+            other => panic!("expected a parse error for `{}`, got {:?}", source, other.err()),
+        };
+        assert_eq!(position("grid width=0 height=1 depth=1"), (1, 12));
+        assert_eq!(position("grid width=1 height=0 depth=1"), (1, 21));
+        assert_eq!(position("grid width=1 height=1 depth=0"), (1, 29));
+        assert_eq!(position("grid width=1 height=1 depth=1 cube_width=0"), (1, 42));
+        assert_eq!(position("grid width=1 height=1 depth=1 cube_width=-0.5"), (1, 42));
+        assert_eq!(position("grid width=1 height=1 depth=1 cube_width=inf"), (1, 42));
+        assert!(parse_scene("test", "grid width=1 height=1 depth=1 cube_width=0.5", None).is_ok());
+    }
+
+    #[test]
+    fn weighted_blockstates_vary_from_block_to_block() {
+        let model = |top| format!(r#"{{ "elements": [{{ "from": [0, 0, 0], "to": [16, {}, 16], "faces": {{ "up": {{ "texture": "stone" }} }} }}] }}"#, top);
+        let dir = TempDir::new("scene_weights");
+        let files = [
+            ("blockstates/rock.json", String::from(r#"{ "variants": { "": [{ "model": "low" }, { "model": "high", "weight": 3 }] } }"#)),
+            ("models/block/low.json", model(4)),
+            ("models/block/high.json", model(12)),
+        ];
+        let files: Vec<(&str, &str)> = files.iter().map(|(path, json)| (*path, json.as_str())).collect();
+        let pack = dir.resource_pack(&files);
+
+        let positions: Vec<String> = (0..8).flat_map(|x| (0..8).map(move |z| format!("{},{}", x, z))).collect();
+        let source = format!("grid width=8 height=1 depth=8\nmaterial rock blockstate=rock\nlayer 0\nrock {}", positions.join(" "));
+        let scene = parse_scene("test", &source, Some(pack)).unwrap_or_else(|e| panic!("{}", e));
+        let high = (0..64)
+            .filter(|index| {
+                let shape = scene.grid.block(index / 8, 0, index % 8).unwrap().shape.as_ref().unwrap();
+                shape.elements[0].to.y == 12.0
+            })
+            .count();
+        assert!((36..=60).contains(&high), "{} of 64 blocks took the model weighted 3 to 1", high);
+
+        let again = parse_scene("test", &source, Some(dir.resource_pack(&files))).unwrap();
+        for index in 0..64 {
+            let (x, z) = (index / 8, index % 8);
+            let top = |scene: &Scene| scene.grid.block(x, 0, z).unwrap().shape.as_ref().unwrap().elements[0].to.y;
+            assert_eq!(top(&scene), top(&again));
+        }
+    }
+
+    fn token(text: &str) -> Token<'_> {
+        Token { text, line: 1, column: 1 }
+    }
+
+    #[test]
+    fn dates_count_days_from_new_year() {
+        let day = |text| parse_date(token(text)).ok();
+        assert_eq!(day("2024-01-01"), Some(1));
+        assert_eq!(day("2023-06-21"), Some(172));
+        assert_eq!(day("2024-06-21"), Some(173)); // after a leap day
+        assert_eq!(day("06-21"), Some(172));
+        assert_eq!(day("2023-12-31"), Some(365));
+        assert_eq!(day("2024-12-31"), Some(366));
+        assert_eq!(day("2024-02-29"), Some(60));
+        assert_eq!(day("2000-02-29"), Some(60));
+        for invalid in ["2023-02-29", "1900-02-29", "2024-13-01", "2024-04-31", "2024-00-10", "06", "June 21", "2024-06-21-1"] {
+            assert_eq!(day(invalid), None, "{} was taken as a date", invalid);
+        }
+    }
+
+    #[test]
+    fn times_are_hours_of_the_day() {
+        let hours = |text| parse_time(token(text)).ok();
+        assert_eq!(hours("15:30"), Some(15.5));
+        assert_eq!(hours("06:00"), Some(6.0));
+        assert_eq!(hours("9.25"), Some(9.25));
+        assert_eq!(hours("24:00"), Some(24.0));
+        for invalid in ["12:60", "25", "-1", "noon", "12:xx"] {
+            assert_eq!(hours(invalid), None, "{} was taken as a time", invalid);
+        }
+        assert_eq!(day_angle(6.0), 0.0);
+        assert!((day_angle(18.0) - PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn blocks_outside_the_grid_are_rejected() {
+        let source = "grid width=1 height=1 depth=1\nmaterial red color=#ff0000\nlayer 0\nred 1,0";
+        assert!(parse_scene("test", source, None).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+
+use super::entitiy::color::HdrColor;
+
+// Daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight" (1999).
+// Directions use x pointing east, y up and z pointing south.
+
+const SKY_SCALE: f32 = 0.04; // zenith luminance in kcd/m² to the renderer's radiance
+const NIGHT_SKY: HdrColor = HdrColor { r: 0.0, g: 0.0, b: 0.03 };
+const MIN_SUN_ELEVATION: f32 = 0.01; // the model breaks down once the sun sets
+
+// Wavelengths standing in for the red, green and blue channels, in micrometers
+const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+// Where the sun is and how hazy the air is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunSettings {
+    pub day_of_year: u32, // 1 for January 1st
+    pub latitude: f32,    // radians, north is positive
+    pub turbidity: f32,   // 2 for a clear sky up to about 10 for haze
+}
+
+impl Default for SunSettings {
+    // Equinox at the equator: the sun rises due east, passes overhead and sets due west
+    fn default() -> Self {
+        SunSettings { day_of_year: 80, latitude: 0.0, turbidity: 3.0 }
+    }
+}
+
+// Unit vector towards the sun at `hours` of local solar time
+pub fn sun_direction(settings: &SunSettings, hours: f32) -> Vec3 {
+    let declination = -23.44f32.to_radians() * (2.0 * PI / 365.0 * (settings.day_of_year as f32 + 10.0)).cos();
+    let hour_angle = (hours - 12.0) * PI / 12.0;
+    let latitude = settings.latitude;
+
+    let up = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let east = -declination.cos() * hour_angle.sin();
+    let north = latitude.cos() * declination.sin() - latitude.sin() * declination.cos() * hour_angle.cos();
+    Vec3::new(east, up, -north).normalize()
+}
+
+// Share of the sunlight that makes it through the atmosphere, relative to the sun straight overhead.
+// Rayleigh scattering by air and Mie scattering by haze take more blue out the longer the path is,
+// so the sun turns orange and dim towards the horizon.
+pub fn sun_transmittance(sun_direction: &Vec3, turbidity: f32) -> HdrColor {
+    let zenith_angle = sun_direction.y.clamp(0.0, 1.0).acos();
+    // Kasten and Young's air mass, stays finite at the horizon
+    let air_mass = 1.0 / (zenith_angle.cos() + 0.50572 * (96.07995 - zenith_angle.to_degrees()).powf(-1.6364));
+    let haze = 0.04608 * turbidity - 0.04586;
+
+    let channel = |wavelength: f32| {
+        let optical_depth = 0.008735 * wavelength.powf(-4.08) + haze * wavelength.powf(-1.3);
+        (-(air_mass - 1.0) * optical_depth).exp()
+    };
+    // Nothing once the sun has gone below the horizon
+    let visible = smoothstep(-0.01, 0.01, sun_direction.y);
+    HdrColor::new(channel(WAVELENGTHS[0]), channel(WAVELENGTHS[1]), channel(WAVELENGTHS[2])) * visible
+}
+
+// Sky radiance for every view direction, for one sun position
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    sun_direction: Vec3,      // kept above the horizon, where the model holds
+    zenith: [f32; 3],         // luminance Y and chromaticity x, y straight up
+    perez: [[f32; 5]; 3],     // distribution coefficients A to E for Y, x and y
+    normalization: [f32; 3],  // 1 / F(0, sun zenith angle), so the zenith gets its own value
+    twilight: f32,            // fades the sky to night after sunset
+}
+
+impl Sky {
+    pub fn new(sun_direction: &Vec3, turbidity: f32) -> Sky {
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        let elevation = sun_direction.y.clamp(-1.0, 1.0).asin();
+        let twilight = smoothstep(-0.12, 0.02, elevation);
+
+        // Below the horizon the sky is lit as if the sun had just set
+        let mut horizontal = Vec3::new(sun_direction.x, 0.0, sun_direction.z);
+        if horizontal.magnitude() < 1e-4 {
+            horizontal = Vec3::new(1.0, 0.0, 0.0);
+        }
+        let clamped_elevation = elevation.max(MIN_SUN_ELEVATION);
+        let sun = horizontal.normalize() * clamped_elevation.cos() + Vec3::new(0.0, clamped_elevation.sin(), 0.0);
+        let theta = PI / 2.0 - clamped_elevation;
+
+        let t = turbidity;
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (theta2, theta3) = (theta * theta, theta * theta * theta);
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let normalization = perez.map(|coefficients| 1.0 / perez_function(&coefficients, 0.0, theta));
+
+        Sky {
+            sun_direction: sun,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            normalization,
+            twilight,
+        }
+    }
+
+    // Linear RGB radiance seen looking along `direction`
+    pub fn radiance(&self, direction: &Vec3) -> HdrColor {
+        // Rays below the horizon see the horizon's color
+        let theta = direction.y.clamp(0.001, 1.0).acos();
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * perez_function(&self.perez[i], theta, gamma) * self.normalization[i]);
+        let sky = xyy_to_rgb(x, y, luminance.max(0.0) * SKY_SCALE);
+        sky * self.twilight + NIGHT_SKY
+    }
+
+    // 0 in daylight, 1 once the sun is far enough below the horizon for the stars to come out
+    pub fn darkness(&self) -> f32 {
+        1.0 - self.twilight
+    }
+}
+
+// Perez et al. luminance distribution for a direction `theta` from the zenith and `gamma` from the sun
+fn perez_function(coefficients: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / theta.cos().max(0.01)).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> HdrColor {
+    if y <= 0.0 {
+        return HdrColor::black();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    HdrColor::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+const STAR_CELLS: f32 = 180.0;   // grid cells along each face of the cube the stars are spread over
+const STAR_DENSITY: f32 = 0.05;  // share of the cells holding a star
+const STAR_RADIUS: f32 = 0.2;    // in cells
+
+// Stars fixed to the celestial sphere, turned around the pole by `day_angle` so they move
+// across the sky with the sun. `direction` looks out from the scene.
+pub fn star_field(direction: &Vec3, latitude: f32, day_angle: f32) -> HdrColor {
+    let pole = Vec3::new(0.0, latitude.sin(), -latitude.cos());
+    let star_direction = nalgebra_glm::rotate_vec3(direction, day_angle, &pole);
+
+    // Project onto the faces of a cube and look up the grid cell, each cell may hold one star
+    let abs = star_direction.abs();
+    let (face, u, v): (u32, f32, f32) = if abs.x >= abs.y && abs.x >= abs.z {
+        (if star_direction.x > 0.0 { 0 } else { 1 }, star_direction.y / abs.x, star_direction.z / abs.x)
+    } else if abs.y >= abs.z {
+        (if star_direction.y > 0.0 { 2 } else { 3 }, star_direction.x / abs.y, star_direction.z / abs.y)
+    } else {
+        (if star_direction.z > 0.0 { 4 } else { 5 }, star_direction.x / abs.z, star_direction.y / abs.z)
+    };
+    let cell_u = (u + 1.0) * 0.5 * STAR_CELLS;
+    let cell_v = (v + 1.0) * 0.5 * STAR_CELLS;
+    let (column, row) = (cell_u.floor() as u32, cell_v.floor() as u32);
+
+    let mut hash = Hash(face.wrapping_mul(0x9E37_79B9) ^ column.wrapping_mul(0x85EB_CA6B) ^ row.wrapping_mul(0xC2B2_AE35));
+    if hash.next() >= STAR_DENSITY {
+        return HdrColor::black();
+    }
+    let (center_u, center_v) = (column as f32 + 0.2 + 0.6 * hash.next(), row as f32 + 0.2 + 0.6 * hash.next());
+    let distance = ((cell_u - center_u).powi(2) + (cell_v - center_v).powi(2)).sqrt() / STAR_RADIUS;
+    if distance >= 1.0 {
+        return HdrColor::black();
+    }
+
+    // Mostly faint stars with a few bright ones, from orange to blue white
+    let brightness = 0.05 + 2.0 * hash.next().powi(6);
+    let temperature = hash.next();
+    let color = HdrColor::new(1.0 - 0.3 * temperature, 0.85 + 0.1 * temperature, 0.7 + 0.3 * temperature);
+    color * (brightness * (1.0 - distance * distance))
+}
+
+// Stateless hash for the star field, the same cell always gives the same star
+struct Hash(u32);
+
+impl Hash {
+    // Uniform in [0, 1)
+    fn next(&mut self) -> f32 {
+        let mut x = self.0.wrapping_add(0x9E37_79B9);
+        self.0 = x;
+        x = (x ^ (x >> 16)).wrapping_mul(0x21F0_AAAD);
+        x = (x ^ (x >> 15)).wrapping_mul(0x735A_2D97);
+        x ^= x >> 15;
+        (x >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elevation(direction: &Vec3) -> f32 {
+        direction.y.asin().to_degrees()
+    }
+
+    #[test]
+    fn noon_sun_stands_at_the_known_elevation() {
+        // 90° - latitude + declination, the declination is +23.44° at the June solstice and
+        // -23.44° at the December one
+        for (day_of_year, latitude, expected) in [(172, 45.0, 68.44), (355, 45.0, 21.56), (172, 0.0, 66.56), (172, -33.9, 32.66)] {
+            let settings = SunSettings { day_of_year, latitude: f32::to_radians(latitude), turbidity: 3.0 };
+            let noon = sun_direction(&settings, 12.0);
+            assert!((elevation(&noon) - expected).abs() < 0.3, "day {} at {}°: {}° instead of {}°", day_of_year, latitude, elevation(&noon), expected);
+        }
+    }
+
+    #[test]
+    fn sun_rises_east_and_sets_west() {
+        let equinox = SunSettings::default();
+        assert!(sun_direction(&equinox, 12.0).y > 0.999);
+        let sunrise = sun_direction(&equinox, 6.0);
+        assert!(sunrise.y.abs() < 0.02 && sunrise.x > 0.99, "{}", sunrise);
+        let sunset = sun_direction(&equinox, 18.0);
+        assert!(sunset.y.abs() < 0.02 && sunset.x < -0.99, "{}", sunset);
+        assert!(sun_direction(&equinox, 0.0).y < -0.999);
+
+        // North of the tropics the noon sun is in the south, +z
+        let north = SunSettings { day_of_year: 172, latitude: 52f32.to_radians(), turbidity: 3.0 };
+        assert!(sun_direction(&north, 12.0).z > 0.0);
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::resource_pack::ResourcePack;
+
+// A directory of the system's temp directory for one test, deleted with everything in it when
+// dropped, so a failing test leaves nothing behind either
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("cg_minecraft_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // `relative` inside the directory as a string, for the functions that take a path as `&str`
+    pub fn file(&self, relative: &str) -> String {
+        self.path.join(relative).to_str().unwrap().to_string()
+    }
+
+    // Makes the directory a resource pack holding `files`, their paths under `assets/minecraft`
+    pub fn resource_pack(&self, files: &[(&str, &str)]) -> ResourcePack {
+        let minecraft = self.path.join("assets").join("minecraft");
+        fs::create_dir_all(minecraft.join("textures")).unwrap();
+        for (path, contents) in files {
+            let path = minecraft.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        ResourcePack::open(self.path.to_str().unwrap()).unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::entitiy::color::{Color, HdrColor};
+
+// Operators that squeeze the unbounded radiance of a render into displayable [0, 1] values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    Clamp,    // clip every channel at 1
+    Reinhard, // L / (1 + L) on luminance, keeps hues until a channel would pass 1
+    Aces,     // Narkowicz's fit of the ACES filmic curve
+}
+
+impl ToneMapping {
+    pub const ALL: [ToneMapping; 3] = [ToneMapping::Clamp, ToneMapping::Reinhard, ToneMapping::Aces];
+
+    pub fn apply(self, color: HdrColor) -> HdrColor {
+        match self {
+            ToneMapping::Clamp => HdrColor::new(color.r.clamp(0.0, 1.0), color.g.clamp(0.0, 1.0), color.b.clamp(0.0, 1.0)),
+            ToneMapping::Reinhard => {
+                let luminance = color.luminance();
+                if luminance <= 0.0 {
+                    return HdrColor::black();
+                }
+                let mapped = luminance / (1.0 + luminance);
+                let scaled = color * (mapped / luminance);
+                // A saturated color can have a channel over 1 at a luminance under 1, it is blended
+                // towards white at the same luminance until that channel is 1 instead of clipped
+                let brightest = scaled.r.max(scaled.g).max(scaled.b);
+                if brightest <= 1.0 {
+                    return scaled;
+                }
+                let keep = (1.0 - mapped) / (brightest - mapped);
+                let desaturate = |channel: f32| mapped + (channel - mapped) * keep;
+                HdrColor::new(desaturate(scaled.r), desaturate(scaled.g), desaturate(scaled.b))
+            }
+            ToneMapping::Aces => {
+                let curve = |x: f32| {
+                    let x = x.max(0.0);
+                    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+                };
+                HdrColor::new(curve(color.r), curve(color.g), curve(color.b))
+            }
+        }
+    }
+
+    // The operator after this one, used to cycle through them in the viewer
+    pub fn next(self) -> ToneMapping {
+        let index = ToneMapping::ALL.iter().position(|&operator| operator == self).unwrap();
+        ToneMapping::ALL[(index + 1) % ToneMapping::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapping::Clamp => "clamp",
+            ToneMapping::Reinhard => "reinhard",
+            ToneMapping::Aces => "aces",
+        }
+    }
+}
+
+impl fmt::Display for ToneMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ToneMapping {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ToneMapping, String> {
+        ToneMapping::ALL
+            .iter()
+            .find(|operator| operator.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("unknown tone mapping `{}`, expected clamp, reinhard or aces", name))
+    }
+}
+
+// Turns a linear HDR color into the sRGB value shown on screen.
+// `exposure` is in stops, every +1 doubles the brightness before tone mapping.
+pub fn tone_map(color: HdrColor, tone_mapping: ToneMapping, exposure: f32) -> Color {
+    tone_mapping.apply(color * exposure.exp2()).to_srgb()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f32) -> HdrColor {
+        HdrColor::new(value, value, value)
+    }
+
+    #[test]
+    fn clamp_clips_each_channel() {
+        assert_eq!(ToneMapping::Clamp.apply(HdrColor::new(-1.0, 0.5, 7.0)), HdrColor::new(0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn reinhard_compresses_luminance_and_stays_in_range() {
+        for value in [0.0f32, 0.25, 1.0, 4.0, 100.0] {
+            let mapped = ToneMapping::Reinhard.apply(gray(value));
+            assert!((mapped.r - value / (1.0 + value)).abs() < 1e-6);
+            assert_eq!((mapped.r, mapped.r), (mapped.g, mapped.b));
+        }
+
+        // Bright saturated red keeps its mapped luminance and its hue, and isn't clipped
+        let red = HdrColor::new(10.0, 0.5, 0.2);
+        let luminance = red.luminance();
+        let mapped = ToneMapping::Reinhard.apply(red);
+        assert!((mapped.r - 1.0).abs() < 1e-6);
+        assert!(mapped.g <= 1.0 && mapped.b <= 1.0 && mapped.r > mapped.g && mapped.g > mapped.b);
+        assert!((mapped.luminance() - luminance / (1.0 + luminance)).abs() < 1e-5);
+
+        // Dim colors only get scaled
+        let dim = HdrColor::new(0.4, 0.2, 0.1);
+        let scale = 1.0 / (1.0 + dim.luminance());
+        let mapped = ToneMapping::Reinhard.apply(dim);
+        assert!((mapped.r - 0.4 * scale).abs() < 1e-6 && (mapped.b - 0.1 * scale).abs() < 1e-6);
+    }
+
+    #[test]
+    fn aces_follows_the_filmic_curve() {
+        let at = |value: f32| ToneMapping::Aces.apply(gray(value)).r;
+        assert_eq!(at(0.0), 0.0);
+        assert!((at(1.0) - 2.54 / 3.16).abs() < 1e-6);
+        assert_eq!(at(1000.0), 1.0);
+        // Rising all the way, the fit tops out a little over 1 which is clipped
+        let mut last = 0.0;
+        for step in 1..100 {
+            let mapped = at(step as f32 * 0.1);
+            assert!(mapped > last || mapped == 1.0);
+            last = mapped;
+        }
+    }
+
+    #[test]
+    fn srgb_round_trips_every_channel_value() {
+        for value in 0..=255u8 {
+            let color = Color::new(value, 255 - value, value / 2);
+            assert_eq!(tone_map(HdrColor::from(color), ToneMapping::Clamp, 0.0), color);
+        }
+        // Half the light is well over half the 8 bit range
+        assert_eq!(gray(0.5).to_srgb(), Color::new(188, 188, 188));
+        // One stop of exposure doubles the light before mapping
+        assert_eq!(tone_map(gray(0.25), ToneMapping::Clamp, 1.0), gray(0.5).to_srgb());
+    }
+}
@@ -0,0 +1,38 @@
+use super::bvh::Bvh;
+use super::environment::Environment;
+use super::entitiy::grid::{Grid, CHUNK_SIZE};
+use super::entitiy::light::{AmbientLight, AreaLight, DayLight, Light};
+use super::entitiy::object::Object;
+use super::lightmap::LightMap;
+
+// Everything rays can hit or be lit by, ready for rendering
+pub struct World {
+    pub objects: Bvh, // over the chunks of the grid, so rays skip the empty parts of the diorama
+    pub lights: Vec<Box<dyn Light + Sync>>,
+    pub area_lights: Vec<AreaLight>, // emissive blocks of the grid
+    pub ambient_light: AmbientLight,
+    pub day_light: DayLight,
+    pub environment: Option<Environment>, // replaces the sky, the sun and the moon when set
+    pub light_map: LightMap,              // Minecraft light levels of the grid, for the light level shading
+}
+
+impl World {
+    pub fn new(grid: Grid, lights: Vec<Box<dyn Light + Sync>>, ambient_light: AmbientLight, day_light: DayLight, environment: Option<Environment>) -> World {
+        let area_lights = grid.emissive_blocks();
+        let light_map = LightMap::new(&grid);
+        let chunks = grid
+            .into_chunks(CHUNK_SIZE)
+            .into_iter()
+            .map(|chunk| Box::new(chunk) as Box<dyn Object + Sync>)
+            .collect();
+        World {
+            objects: Bvh::new(chunks),
+            lights,
+            area_lights,
+            ambient_light,
+            day_light,
+            environment,
+            light_map,
+        }
+    }
+}
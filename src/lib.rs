@@ -1,23 +1,113 @@
+#![allow(non_snake_case)]
+
 mod internal;
 
-use internal::camera::Camera;
-use minifb::{Window, WindowOptions, Key};
-use nalgebra_glm::Vec3;
-use std::sync::Arc;
-use std::time::Duration;
+use minifb::{Window, WindowOptions, Key, KeyRepeat};
+use std::error::Error;
+use std::time::{Duration, Instant};
 use std::f32::consts::PI;
+use internal::camera::Camera;
+use internal::cli::{self, Command, RenderOptions, ViewOptions};
+use internal::export::{save_image, screenshot_path, ImageMetadata};
 use internal::framebuffer::Framebuffer;
-use internal::render::render;
+use internal::render::{render, render_pass, Integrator, RenderSettings};
+use internal::resource_pack::ResourcePack;
+use internal::sampling::{PixelFilter, SamplePattern};
 use internal::entitiy::color::Color;
-use internal::entitiy::material::{Material, Diffuse};
-use internal::entitiy::cube::Cube;
-use internal::entitiy::object::Object;
-use internal::entitiy::light::{AmbientLight, DayLight, Light, PointLight};
-use internal::entitiy::texture::{BOOK_SHELF, CHEST, COBBLESTONE, CRAFTING_TABLE, DARK_OAK_PLANKS, FURNACE, GLASS, GLOWSTONE, GRASS, JUKEBOX, OAK_LOG, OAK_PLANKS};
-use internal::entitiy::grid::{self, Grid};
+use internal::scene::{load_scene, scene_path, Scene};
+use internal::world::World;
+
+// Entry point for the binary, `args` excludes the program name.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match cli::parse_args(args)? {
+        Command::View(options) => start(&options),
+        Command::Render(options) => render_to_file(&options),
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+    }
+}
+
+// Renders a single frame without opening a window and saves it as an image.
+pub fn render_to_file(options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+    let path = scene_path(&options.scene);
+    let resource_pack = options.resource_pack.as_deref().map(ResourcePack::open).transpose()?;
+    let mut scene = load_scene(&path, resource_pack).map_err(|e| format!("{}: {}", path, e))?;
+
+    if let Some(eye) = options.eye {
+        scene.camera.eye = eye;
+    }
+    if let Some(center) = options.center {
+        scene.camera.center = center;
+    }
+    if let Some(day_angle) = options.day_angle {
+        scene.day_light.translate_day_light(day_angle - scene.day_light.day_angle);
+    }
+
+    let Scene { name, grid, camera, lights, ambient_light, day_light, environment } = scene;
+    let mut world = World::new(grid, lights, ambient_light, day_light, environment);
+    if options.stats {
+        world.objects.count_traversals();
+    }
+    let settings = RenderSettings {
+        samples: options.samples,
+        pattern: options.pattern,
+        filter: options.filter,
+        seed: options.seed,
+        shadow_samples: options.shadow_samples,
+        tone_mapping: options.tone_mapping,
+        exposure: options.exposure,
+        integrator: options.integrator,
+        ao_samples: options.ao_samples,
+        ao_distance: options.ao_distance,
+        shading: options.shading,
+        texture_filter: options.texture_filter,
+        bounces: options.bounces,
+    };
+    let mut framebuffer = Framebuffer::new(options.width, options.height, Color::new(0, 0, 0));
+
+    let render_start = Instant::now();
+    render(&mut framebuffer, &world, &camera, &settings);
+    let render_time = render_start.elapsed();
+
+    let out = options.out.clone().unwrap_or_else(|| format!("{}.png", name));
+    let metadata = ImageMetadata::new(&name, &camera, Some(render_time));
+    save_image(&framebuffer, &out, &metadata).map_err(|e| format!("could not write '{}': {}", out, e))?;
+
+    println!(
+        "Rendered {} ({}x{}, {} spp, {} pattern, {} filter, {}) in {:.2?} -> {}",
+        name, options.width, options.height, options.samples, options.pattern, options.filter, options.integrator, render_time, out
+    );
+    if options.stats {
+        println!("{}", world.objects.stats());
+    }
+    Ok(())
+}
+
+// Saves a frame under screenshots/ and returns the message to show in the window title
+fn save_screenshot(framebuffer: &Framebuffer, scene: &str, camera: &Camera, render_time: Option<Duration>) -> String {
+    let path = screenshot_path(scene);
+    let result = std::fs::create_dir_all("screenshots")
+        .map_err(|e| e.to_string())
+        .and_then(|_| save_image(framebuffer, &path, &ImageMetadata::new(scene, camera, render_time)).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => format!("saved {}", path),
+        Err(e) => {
+            eprintln!("could not write '{}': {}", path, e);
+            String::from("screenshot failed")
+        }
+    }
+}
+
+// Opens the viewer window, a scene or resource pack that can't be loaded is an error like for `render`
+pub fn start(options: &ViewOptions) -> Result<(), Box<dyn Error>> {
+
+    let scene_path = scene_path(&options.scene);
+    let resource_pack = options.resource_pack.as_deref().map(ResourcePack::open).transpose()?;
+    let scene = load_scene(&scene_path, resource_pack).map_err(|e| format!("{}: {}", scene_path, e))?;
+    let Scene { name, grid: diorama, mut camera, lights, ambient_light, day_light: sun, environment } = scene;
 
-pub fn start(){
-    
     // Window Size configuration
     let window_width = 800;
     let window_height = 600;
@@ -29,483 +119,43 @@ pub fn start(){
   
     // Window Objects initialization
     let mut framebuffer = Framebuffer::new(window_width, window_height, Color::new(0, 0, 0));
+    let mut settings = RenderSettings::default();
+    // Passes averaged into the frame on screen, the path tracer and the ambient occlusion view keep
+    // adding them while nothing moves
+    let mut pass: u32 = 0;
+    // Short confirmations, like a saved screenshot, are shown after the settings for a while
+    let title = |settings: &RenderSettings, pass: u32, status: Option<&str>| {
+        let integrator = match settings.integrator {
+            Integrator::Whitted => settings.integrator.to_string(),
+            Integrator::PathTracer | Integrator::AmbientOcclusion => format!("{}, {} spp", settings.integrator, pass * settings.samples),
+        };
+        let title = format!(
+            "Minecraft Diorama - {} [{}, {:+.1} EV, {}, {} shading, {} textures]",
+            name, settings.tone_mapping, settings.exposure, integrator, settings.shading, settings.texture_filter
+        );
+        match status {
+            Some(status) => format!("{} - {}", title, status),
+            None => title,
+        }
+    };
+    let mut status: Option<(String, Instant)> = None;
     let mut window = Window::new(
-      "Minecraft Diorama",
+      &title(&settings, pass, None),
       window_width,
       window_height,
       WindowOptions::default()
-    ).unwrap();
-    
-    // Create an array of Box<dyn Object>
-    let grass = Arc::new( Material {
-        diffuse: Diffuse::Texture(GRASS.clone()),
-        specular: 80.0,
-        albedo: [0.6, 0.3],
-        reflectivity: 0.0,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
+    )?;
     
-    let cobbleston = Arc::new (Material {
-        diffuse: Diffuse::Texture(COBBLESTONE.clone()),
-        specular: 50.0,
-        albedo: [1.0, 0.0],
-        reflectivity: 0.0,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let oak_log = Arc::new (Material {
-        diffuse: Diffuse::Texture(OAK_LOG.clone()),
-        specular: 0.5,
-        albedo: [0.9, 0.1],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let oak_planks = Arc::new (Material {
-        diffuse: Diffuse::Texture(OAK_PLANKS.clone()),
-        specular: 50.0,
-        albedo: [1.0, 0.0],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let dark_oak_planks = Arc::new (Material {
-        diffuse: Diffuse::Texture(DARK_OAK_PLANKS.clone()),
-        specular: 8.0,
-        albedo: [0.7, 0.2],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let furnace = Arc::new (Material {
-        diffuse: Diffuse::Texture(FURNACE.clone()),
-        specular: 10.0,
-        albedo: [0.9, 0.1],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let book_shelf = Arc::new (Material {
-        diffuse: Diffuse::Texture(BOOK_SHELF.clone()),
-        specular: 10.0,
-        albedo: [0.9, 0.1],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-    let jukebox = Arc::new (Material {
-        diffuse: Diffuse::Texture(JUKEBOX.clone()),
-        specular: 10.0,
-        albedo: [0.9, 0.1],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let chest = Arc::new (Material {
-        diffuse: Diffuse::Texture(CHEST.clone()),
-        specular: 10.0,
-        albedo: [0.9, 0.1],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let crafting_table = Arc::new (Material {
-        diffuse: Diffuse::Texture(CRAFTING_TABLE.clone()),
-        specular: 10.0,
-        albedo: [0.9, 0.1],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let glass = Arc::new (Material {
-        diffuse: Diffuse::Texture(GLASS.clone()),
-        specular: 8.0,
-        albedo: [0.2, 0.8],
-        reflectivity: 0.0,
-        transparency: 2.0,
-        refractive_index: 1.5,
-    });
-
-    let glowstone = Arc::new (Material {
-        diffuse: Diffuse::Texture(GLOWSTONE.clone()),
-        specular: 80.0,
-        albedo: [0.1, 0.9],
-        reflectivity: 0.2,
-        transparency: 0.0,
-        refractive_index: 1.0,
-    });
-
-    let mut diorama = Grid::new(1.0, 11, 9, 10);
-    diorama.add_layer(0, &vec![
-        (0, 0, Arc::clone(&grass)),
-        (1, 0, Arc::clone(&grass)),
-        (2, 0, Arc::clone(&grass)),
-        (3, 0, Arc::clone(&grass)),
-        (4, 0, Arc::clone(&grass)),
-        (5, 0, Arc::clone(&grass)),
-        (6, 0, Arc::clone(&grass)),
-        (7, 0, Arc::clone(&grass)),
-        (8, 0, Arc::clone(&grass)),
-
-        (0, 1, Arc::clone(&grass)),
-        (0, 2, Arc::clone(&grass)),
-        (0, 3, Arc::clone(&grass)),
-        (0, 4, Arc::clone(&grass)),
-        (0, 5, Arc::clone(&grass)),
-        (0, 6, Arc::clone(&grass)),
-        (0, 7, Arc::clone(&grass)),
-
-        (8, 1, Arc::clone(&grass)),
-        (8, 2, Arc::clone(&grass)),
-        (8, 3, Arc::clone(&grass)),
-        (8, 4, Arc::clone(&grass)),
-        (8, 5, Arc::clone(&grass)),
-        (8, 6, Arc::clone(&grass)),
-        (8, 7, Arc::clone(&grass)),
-        (8, 8, Arc::clone(&grass)),
-        (8, 9, Arc::clone(&grass)),
-
-        (2, 9, Arc::clone(&grass)),
-        (3, 9, Arc::clone(&grass)),
-        (4, 9, Arc::clone(&grass)),
-        (5, 9, Arc::clone(&grass)),
-        (6, 9, Arc::clone(&grass)),
-        (7, 9, Arc::clone(&grass)),
-       
-        (1, 8, Arc::clone(&grass)),
-        (1, 7, Arc::clone(&grass)),
-        (2, 8, Arc::clone(&grass)),
-    ]);
-
-    diorama.add_layer(1, &vec![
-        (1, 1, Arc::clone(&oak_log)),
-        (7, 1, Arc::clone(&oak_log)),
-        (7, 8, Arc::clone(&oak_log)),
-        
-        
-        (1, 2, Arc::clone(&cobbleston)),
-        (1, 3, Arc::clone(&cobbleston)),
-        (1, 4, Arc::clone(&cobbleston)),
-        (1, 5, Arc::clone(&cobbleston)),
-        (1, 6, Arc::clone(&cobbleston)),
-
-        (2, 1, Arc::clone(&cobbleston)),
-        (3, 1, Arc::clone(&cobbleston)),
-        (4, 1, Arc::clone(&cobbleston)),
-        (5, 1, Arc::clone(&cobbleston)),
-        (6, 1, Arc::clone(&cobbleston)),
-
-        (7, 2, Arc::clone(&cobbleston)),
-        (7, 3, Arc::clone(&cobbleston)),
-        (7, 4, Arc::clone(&cobbleston)),
-        (7, 5, Arc::clone(&cobbleston)),
-        (7, 6, Arc::clone(&cobbleston)),
-        (7, 7, Arc::clone(&cobbleston)),
-
-        (3, 8, Arc::clone(&cobbleston)),
-        (4, 8, Arc::clone(&cobbleston)),
-        (5, 8, Arc::clone(&cobbleston)),
-        (6, 8, Arc::clone(&cobbleston)),
-
-        (2, 7, Arc::clone(&cobbleston)),
-        (3, 7, Arc::clone(&cobbleston)),
-        (4, 7, Arc::clone(&cobbleston)),
-        (5, 7, Arc::clone(&cobbleston)),
-
-        (2, 6, Arc::clone(&cobbleston)),
-        (3, 6, Arc::clone(&cobbleston)),
-        (4, 6, Arc::clone(&cobbleston)),
-        (5, 6, Arc::clone(&cobbleston)),
-
-        (2, 5, Arc::clone(&cobbleston)),
-        (3, 5, Arc::clone(&cobbleston)),
-        (4, 5, Arc::clone(&cobbleston)),
-        (5, 5, Arc::clone(&cobbleston)),
-
-        (3, 4, Arc::clone(&cobbleston)),
-        (4, 4, Arc::clone(&cobbleston)),
-        (5, 4, Arc::clone(&cobbleston)),
-        (6, 4, Arc::clone(&cobbleston)),
-
-        (3, 3, Arc::clone(&cobbleston)),
-        (4, 3, Arc::clone(&cobbleston)),
-        (5, 3, Arc::clone(&cobbleston)),
-        (6, 3, Arc::clone(&cobbleston)),
-
-        (2, 2, Arc::clone(&cobbleston)),
-        (3, 2, Arc::clone(&cobbleston)),
-        (4, 2, Arc::clone(&cobbleston)),
-        (5, 2, Arc::clone(&cobbleston)),
-        (6, 2, Arc::clone(&cobbleston)),
-    ]);
-
-    diorama.add_layer(2, &vec![
-        (1, 1, Arc::clone(&oak_log)),
-        (7, 1, Arc::clone(&oak_log)),
-        (7, 8, Arc::clone(&oak_log)),
-
-        (1, 2, Arc::clone(&oak_planks)),
-        (1, 3, Arc::clone(&oak_planks)),
-        (1, 4, Arc::clone(&oak_planks)),
-        (1, 5, Arc::clone(&oak_planks)),
-
-        (2, 1, Arc::clone(&oak_planks)),
-        (3, 1, Arc::clone(&oak_planks)),
-        (5, 1, Arc::clone(&oak_planks)),
-        (6, 1, Arc::clone(&oak_planks)),
-
-        (7, 2, Arc::clone(&oak_planks)),
-        (7, 3, Arc::clone(&oak_planks)),
-        (7, 4, Arc::clone(&oak_planks)),
-        (7, 5, Arc::clone(&oak_planks)),
-        (7, 6, Arc::clone(&oak_planks)),
-        (7, 7, Arc::clone(&oak_planks)),
-        
-        (2, 3, Arc::clone(&crafting_table)),
-        (2, 4, Arc::clone(&chest)),
-
-        (6, 5, Arc::clone(&furnace)),
-        (6, 6, Arc::clone(&furnace)),
-
-        (6, 7, Arc::clone(&dark_oak_planks)),
-    ]);
-
-    diorama.add_layer(3, &vec![
-        (1, 1, Arc::clone(&oak_log)),
-        (7, 1, Arc::clone(&oak_log)),
-        (7, 8, Arc::clone(&oak_log)),
-
-        (1, 2, Arc::clone(&oak_planks)),
-        (1, 3, Arc::clone(&oak_planks)),
+    let mut world = World::new(diorama, lights, ambient_light, sun, environment);
 
-        (2, 1, Arc::clone(&glass)),
-        (3, 1, Arc::clone(&oak_planks)),
-        (5, 1, Arc::clone(&oak_planks)),
-        (6, 1, Arc::clone(&glass)),
-
-        (7, 2, Arc::clone(&oak_planks)),
-        (7, 3, Arc::clone(&oak_planks)),
-        (7, 4, Arc::clone(&oak_planks)),
-        (7, 5, Arc::clone(&oak_planks)),
-        (7, 6, Arc::clone(&oak_planks)),
-        (7, 7, Arc::clone(&oak_planks)),
-
-        (6, 5, Arc::clone(&furnace)),
-
-        (6, 6, Arc::clone(&dark_oak_planks)),
-    ]);
-    
-    diorama.add_layer(4, &vec![
-        (1, 1, Arc::clone(&oak_log)),
-        (7, 1, Arc::clone(&oak_log)),
-        (7, 8, Arc::clone(&oak_log)),
-
-        (1, 2, Arc::clone(&oak_planks)),
-
-        (2, 1, Arc::clone(&oak_planks)),
-        (3, 1, Arc::clone(&oak_planks)),
-        (4, 1, Arc::clone(&oak_planks)),
-        (5, 1, Arc::clone(&oak_planks)),
-        (6, 1, Arc::clone(&oak_planks)),
-
-        (7, 2, Arc::clone(&oak_planks)),
-        (7, 3, Arc::clone(&oak_planks)),
-        (7, 4, Arc::clone(&oak_planks)),
-        (7, 5, Arc::clone(&oak_planks)),
-        (7, 6, Arc::clone(&glass)),
-        (7, 7, Arc::clone(&oak_planks)),
-
-        (6, 5, Arc::clone(&dark_oak_planks)),
-    ]);
-
-    diorama.add_layer(5, &vec![
-        (1, 1, Arc::clone(&oak_log)),
-        (7, 1, Arc::clone(&oak_log)),
-        (7, 8, Arc::clone(&oak_log)),
-
-        (1, 2, Arc::clone(&oak_planks)),
-
-        (2, 1, Arc::clone(&oak_planks)),
-        (3, 1, Arc::clone(&oak_planks)),
-        (4, 1, Arc::clone(&oak_planks)),
-        (5, 1, Arc::clone(&oak_planks)),
-        (6, 1, Arc::clone(&oak_planks)),
-
-        (7, 2, Arc::clone(&oak_planks)),
-        (7, 3, Arc::clone(&oak_planks)),
-        (7, 4, Arc::clone(&oak_planks)),
-        (7, 5, Arc::clone(&oak_planks)),
-        (7, 6, Arc::clone(&oak_planks)),
-        (7, 7, Arc::clone(&oak_planks)),
-
-        (6, 4, Arc::clone(&dark_oak_planks)),
-        
-        (3, 5, Arc::clone(&dark_oak_planks)),
-        (4, 5, Arc::clone(&dark_oak_planks)),
-        (5, 5, Arc::clone(&dark_oak_planks)),
-
-        (2, 4, Arc::clone(&dark_oak_planks)),
-        (3, 4, Arc::clone(&dark_oak_planks)),
-        (4, 4, Arc::clone(&dark_oak_planks)),
-        (5, 4, Arc::clone(&dark_oak_planks)),
-
-        (2, 3, Arc::clone(&dark_oak_planks)),
-        (3, 3, Arc::clone(&dark_oak_planks)),
-        (4, 3, Arc::clone(&dark_oak_planks)),
-        (5, 3, Arc::clone(&dark_oak_planks)),
-
-        (2, 2, Arc::clone(&glowstone)),
-        (3, 2, Arc::clone(&dark_oak_planks)),
-        (4, 2, Arc::clone(&dark_oak_planks)),
-        (5, 2, Arc::clone(&dark_oak_planks)),
-        (6, 2, Arc::clone(&glowstone)),
-    ]);
-
-    diorama.add_layer(6, &vec![
-        (1, 1, Arc::clone(&oak_log)),
-        (7, 1, Arc::clone(&oak_log)),
-        (7, 8, Arc::clone(&oak_log)),
-
-        (1, 2, Arc::clone(&oak_planks)),
-
-        (2, 1, Arc::clone(&glass)),
-        (3, 1, Arc::clone(&glass)),
-        (4, 1, Arc::clone(&glass)),
-        (5, 1, Arc::clone(&glass)),
-        (6, 1, Arc::clone(&glass)),
-
-        (7, 2, Arc::clone(&oak_planks)),
-        (7, 3, Arc::clone(&oak_planks)),
-        (7, 4, Arc::clone(&oak_planks)),
-        (7, 5, Arc::clone(&oak_planks)),
-        (7, 6, Arc::clone(&oak_planks)),
-        (7, 7, Arc::clone(&oak_planks)),
-
-        (6, 3, Arc::clone(&jukebox)),
-
-        (2, 2, Arc::clone(&book_shelf)),
-        (2, 3, Arc::clone(&book_shelf)),
-    ]);
-    
-    diorama.add_layer(7, &vec![
-        (2, 1, Arc::clone(&oak_planks)),
-        (3, 1, Arc::clone(&glass)),
-        (4, 1, Arc::clone(&glass)),
-        (5, 1, Arc::clone(&glass)),
-        (6, 1, Arc::clone(&oak_planks)),
-
-        (1, 0, Arc::clone(&dark_oak_planks)),
-        (1, 1, Arc::clone(&dark_oak_planks)),
-        (1, 2, Arc::clone(&dark_oak_planks)),
-        (1, 3, Arc::clone(&dark_oak_planks)),
-        (1, 4, Arc::clone(&dark_oak_planks)),
-
-        (7, 0, Arc::clone(&dark_oak_planks)),
-        (7, 1, Arc::clone(&dark_oak_planks)),
-        (7, 2, Arc::clone(&dark_oak_planks)),
-        (7, 3, Arc::clone(&dark_oak_planks)),
-        (7, 4, Arc::clone(&dark_oak_planks)),
-        (7, 5, Arc::clone(&dark_oak_planks)),
-        (7, 6, Arc::clone(&dark_oak_planks)),
-        (7, 7, Arc::clone(&dark_oak_planks)),
-        (7, 8, Arc::clone(&dark_oak_planks)),
-    ]);
-
-    diorama.add_layer(8, &vec![
-        (3, 1, Arc::clone(&oak_planks)),
-        (4, 1, Arc::clone(&glass)),
-        (5, 1, Arc::clone(&oak_planks)),
-
-        (2, 0, Arc::clone(&dark_oak_planks)),
-        (2, 1, Arc::clone(&dark_oak_planks)),
-        (2, 2, Arc::clone(&dark_oak_planks)),
-        (2, 3, Arc::clone(&dark_oak_planks)),
-        (2, 4, Arc::clone(&dark_oak_planks)),
-        (2, 5, Arc::clone(&dark_oak_planks)),
-
-        (6, 0, Arc::clone(&dark_oak_planks)),
-        (6, 1, Arc::clone(&dark_oak_planks)),
-        (6, 2, Arc::clone(&dark_oak_planks)),
-        (6, 3, Arc::clone(&dark_oak_planks)),
-        (6, 4, Arc::clone(&dark_oak_planks)),
-        (6, 5, Arc::clone(&dark_oak_planks)),
-        (6, 6, Arc::clone(&dark_oak_planks)),
-        (6, 7, Arc::clone(&dark_oak_planks)),
-    ]);
-    diorama.add_layer(9, &vec![
-        (4, 0, Arc::clone(&dark_oak_planks)),
-
-        (3, 0, Arc::clone(&dark_oak_planks)),
-        (3, 1, Arc::clone(&dark_oak_planks)),
-        (3, 2, Arc::clone(&dark_oak_planks)),
-        (3, 3, Arc::clone(&dark_oak_planks)),
-        (3, 4, Arc::clone(&dark_oak_planks)),
-        (3, 5, Arc::clone(&dark_oak_planks)),
-
-        (5, 0, Arc::clone(&dark_oak_planks)),
-        (5, 1, Arc::clone(&dark_oak_planks)),
-        (5, 2, Arc::clone(&dark_oak_planks)),
-        (5, 3, Arc::clone(&dark_oak_planks)),
-        (5, 4, Arc::clone(&dark_oak_planks)),
-        (5, 5, Arc::clone(&dark_oak_planks)),
-        (5, 6, Arc::clone(&dark_oak_planks)),
-    ]);
-    diorama.add_layer(10, &vec![
-
-        (4, 0, Arc::clone(&dark_oak_planks)),
-        (4, 1, Arc::clone(&dark_oak_planks)),
-        (4, 2, Arc::clone(&dark_oak_planks)),
-        (4, 3, Arc::clone(&dark_oak_planks)),
-        (4, 4, Arc::clone(&dark_oak_planks)),
-        (4, 5, Arc::clone(&dark_oak_planks)),
-    ]);
-    
-    let mut camera = Camera::new(
-        Vec3::new(0.0, 0.0, 20.0),
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(0.0, 1.0, 0.0)
-    );
-    
-    let lights: [Box<dyn Light + Sync>; 2] = [
-        Box::new(
-            PointLight::new(
-            Vec3::new(-0.5, -2.0, 2.0),
-            Color::new(242, 130, 39),
-            0.3)
-        ),
-        Box::new(
-            PointLight::new(
-            Vec3::new(0.0, 1.0, 0.0),
-            Color::new(242, 130, 39),
-            0.3)
-        ),
-    ];
-
-    let ambient_light = AmbientLight::new(Color::new(230, 164, 50), 0.5);
-
-    let mut sun = DayLight::new(
-        Vec3::new(10.0, 0.0, 0.0), 
-        Vec3::new(0.0,0.0,0.0),
-        10.0, 0.0, 
-        Color::new(255, 255, 255), 
-        2.0
-    );
-    
-    
     let mut day_angle = PI / 3.0;
     
     const ROTATION_SPEED : f32 = PI / 10.0;
     const ZOOM_SPEED : f32 = 0.2;
     const DAY_SPEED : f32 = PI / 40.0;
+    const EXPOSURE_STEP : f32 = 0.5;
+    const STATUS_DURATION : Duration = Duration::from_secs(3);
+    const MAX_PASSES : u32 = 1024;
 
     // RENDER LOOP
     while window.is_open() {
@@ -536,19 +186,89 @@ pub fn start(){
         }
 
         // Day Change
+        let mut restart = false;
         if window.is_key_down(Key::M) {
-            sun.translate_day_light(DAY_SPEED);
+            world.day_light.translate_day_light(DAY_SPEED);
             day_angle = (day_angle + DAY_SPEED) % (2.0 * PI);
-            render(&mut framebuffer, &diorama.objects, &camera, &lights, &sun, &ambient_light);
+            restart = true;
         }
         if window.is_key_down(Key::N) {
-            sun.translate_day_light(-DAY_SPEED);
+            world.day_light.translate_day_light(-DAY_SPEED);
             day_angle = (day_angle - DAY_SPEED) % (2.0 * PI);
-            render(&mut framebuffer, &diorama.objects, &camera, &lights, &sun, &ambient_light);
+            restart = true;
+        }
+
+        // P cycles through the fast Whitted renderer, the path tracer and the ambient occlusion view
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            settings.integrator = settings.integrator.next();
+            restart = true;
+        }
+
+        // L cycles between ray traced lighting and Minecraft's flat and smooth light levels
+        if window.is_key_pressed(Key::L, KeyRepeat::No) {
+            settings.shading = settings.shading.next();
+            restart = true;
         }
 
-        if camera.check_if_changed() {
-            render(&mut framebuffer, &diorama.objects, &camera, &lights, &sun, &ambient_light);
+        // F cycles the texture filter, nearest for crisp pixels or the mipmapped ones against shimmering
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            settings.texture_filter = settings.texture_filter.next();
+            restart = true;
+        }
+
+        // Tone mapping only needs the last frame's radiance, not a new render
+        let mut tone_changed = false;
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            settings.tone_mapping = settings.tone_mapping.next();
+            tone_changed = true;
+        }
+        if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+            settings.exposure += EXPOSURE_STEP;
+            tone_changed = true;
+        }
+        if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+            settings.exposure -= EXPOSURE_STEP;
+            tone_changed = true;
+        }
+        if tone_changed {
+            framebuffer.tone_map(settings.tone_mapping, settings.exposure);
+            window.set_title(&title(&settings, pass, status.as_ref().map(|(message, _)| message.as_str())));
+        }
+
+        // F2 saves the frame on screen, F3 renders the same view again at screenshot quality
+        if window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            let message = save_screenshot(&framebuffer, &name, &camera, None);
+            window.set_title(&title(&settings, pass, Some(&message)));
+            status = Some((message, Instant::now()));
+        }
+        if window.is_key_pressed(Key::F3, KeyRepeat::No) {
+            window.set_title(&title(&settings, pass, Some("rendering screenshot...")));
+            let mut screenshot = Framebuffer::new(options.screenshot_width, options.screenshot_height, Color::new(0, 0, 0));
+            let screenshot_settings = RenderSettings {
+                samples: options.screenshot_samples,
+                pattern: SamplePattern::Stratified,
+                filter: PixelFilter::Tent,
+                ..settings
+            };
+            let render_start = Instant::now();
+            render(&mut screenshot, &world, &camera, &screenshot_settings);
+            let message = save_screenshot(&screenshot, &name, &camera, Some(render_start.elapsed()));
+            window.set_title(&title(&settings, pass, Some(&message)));
+            status = Some((message, Instant::now()));
+        }
+        if status.as_ref().is_some_and(|(_, shown)| shown.elapsed() > STATUS_DURATION) {
+            status = None;
+            window.set_title(&title(&settings, pass, None));
+        }
+
+        // Anything that changes the picture starts over, otherwise the noisy integrators refine the frame
+        if camera.check_if_changed() || restart {
+            pass = 0;
+        }
+        if pass == 0 || (settings.integrator != Integrator::Whitted && pass < MAX_PASSES) {
+            render_pass(&mut framebuffer, &world, &camera, &settings, pass);
+            pass += 1;
+            window.set_title(&title(&settings, pass, status.as_ref().map(|(message, _)| message.as_str())));
         }
 
         window
@@ -557,6 +277,7 @@ pub fn start(){
 
         std::thread::sleep(frame_delay)
     }
-  
+
+    Ok(())
 }
 
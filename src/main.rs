@@ -1,6 +1,13 @@
-use CG_Minecraft::start;
+#![allow(non_snake_case)]
+
+use CG_Minecraft::run;
+use std::env;
+use std::process;
 
 fn main() {
-    println!("Hello, world!");
-    start()
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }